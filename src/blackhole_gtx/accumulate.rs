@@ -0,0 +1,210 @@
+//! Progressive accumulation for `BlackholeGtx` stills.
+//!
+//! While the camera holds still, every frame traces one jittered ray per pixel into `sample` and
+//! `accumulate.frag` blends it into a running average kept in `Rgba32Float`, ping-ponging between
+//! two textures. The average is what ends up on screen.
+
+use bytemuck::{Pod, Zeroable};
+
+use crate::{
+    bind_group_info, include_glsl,
+    new_abstractions::{Buff, BuffInfo, Tex, TexInfo, TISampler, TITexture, ZSTValue, _2D},
+    render_pipeline_info,
+};
+
+use super::core::{Vertex, VERTEX_BUFF};
+
+const AVERAGE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba32Float;
+
+/// Uniform for `accumulate.frag`, padded to 16 bytes.
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+#[repr(C)]
+struct AccumulateData {
+    weight: f32,
+    _spacer: [f32; 3],
+}
+
+bind_group_info!(AccumulateGroup; wgpu::ShaderStages::FRAGMENT;
+    0 => (TexInfo::<_2D, TITexture>, wgpu::TextureSampleType::Float { filterable: false }),
+    1 => (TexInfo::<_2D, TITexture>, wgpu::TextureSampleType::Float { filterable: false }),
+    2 => (TexInfo::<_2D, TISampler>, wgpu::SamplerBindingType::NonFiltering),
+    3 => (BuffInfo::<AccumulateData>, wgpu::BufferBindingType::Uniform),
+);
+
+render_pipeline_info!(AccumulatePipeline;
+    0 => AccumulateGroupInfo<'device>,
+    ;
+    0 => (Vertex, wgpu::VertexStepMode::Vertex),
+);
+
+/// Radical inverse of `i` in `base`, for a Halton sequence.
+fn halton(mut i: u32, base: u32) -> f32 {
+    let mut result = 0.0;
+    let mut f = 1.0;
+    while i > 0 {
+        f /= base as f32;
+        result += f * (i % base) as f32;
+        i /= base;
+    }
+    result
+}
+
+pub struct Accumulator {
+    pipeline: AccumulatePipeline,
+    /// What the tracer renders the current sample into, in the view's format.
+    sample: Tex<_2D>,
+    /// Running averages, `bindings[i]` reads `averages[i]` and the pass writes the other one.
+    averages: [Tex<_2D>; 2],
+    bindings: [AccumulateGroup; 2],
+    data_buff: Buff<AccumulateData>,
+    sample_format: wgpu::TextureFormat,
+    /// Samples in the average so far.
+    count: u32,
+    /// Stop tracing after this many samples, 0 for never.
+    pub max_samples: u32,
+}
+
+impl Accumulator {
+    pub fn new(size: (u32, u32), view_format: wgpu::TextureFormat, device: &wgpu::Device) -> Self {
+        let group_info = AccumulateGroupInfo::new(device);
+        let pipeline = AccumulatePipeline::new(
+            device,
+            (
+                device.create_shader_module(include_glsl!(
+                    "shaders/draw.vert",
+                    naga::ShaderStage::Vertex
+                )),
+                "main",
+            ),
+            (
+                device.create_shader_module(include_glsl!(
+                    "shaders/accumulate.frag",
+                    naga::ShaderStage::Fragment
+                )),
+                "main",
+            ),
+            &[Some(AVERAGE_FORMAT.into()), Some(view_format.into())],
+            &VERTEX_BUFF,
+            &group_info,
+        );
+        let data = AccumulateData {
+            weight: 1.0,
+            _spacer: [0.0; 3],
+        };
+        let data_buff = Buff::new(device, &BuffInfo::<AccumulateData>::IT, &[data]);
+        let (sample, averages, bindings) = Self::create_targets(&data_buff, size, view_format, device);
+        Self {
+            pipeline,
+            sample,
+            averages,
+            bindings,
+            data_buff,
+            sample_format: view_format,
+            count: 0,
+            max_samples: 0,
+        }
+    }
+
+    fn create_targets(
+        data_buff: &Buff<AccumulateData>,
+        size: (u32, u32),
+        sample_format: wgpu::TextureFormat,
+        device: &wgpu::Device,
+    ) -> (Tex<_2D>, [Tex<_2D>; 2], [AccumulateGroup; 2]) {
+        let group_info = AccumulateGroupInfo::new(device);
+        let sample = Tex::<_2D>::create_uninit_with_format("accumulate-sample", size, sample_format, device);
+        let averages: [Tex<_2D>; 2] = std::array::from_fn(|i| {
+            Tex::<_2D>::create_uninit_with_format(&format!("accumulate-average-{}", i), size, AVERAGE_FORMAT, device)
+        });
+        let bindings = std::array::from_fn(|i| {
+            group_info.bind(
+                sample.binding_texture(),
+                averages[i].binding_texture(),
+                averages[i].binding_sampler(),
+                data_buff.slice(..),
+            )
+        });
+        (sample, averages, bindings)
+    }
+
+    pub fn resize(&mut self, size: (u32, u32), device: &wgpu::Device) {
+        (self.sample, self.averages, self.bindings) =
+            Self::create_targets(&self.data_buff, size, self.sample_format, device);
+        self.reset();
+    }
+
+    /// Start over, for when the picture changed.
+    pub fn reset(&mut self) {
+        self.count = 0;
+    }
+
+    /// Whether the average has all the samples it needs, tracing more would be wasted.
+    pub fn is_done(&self) -> bool {
+        self.max_samples != 0 && self.count >= self.max_samples
+    }
+
+    /// Offset of the next sample from the pixel centre, in pixels.
+    pub fn jitter(&self) -> [f32; 2] {
+        [halton(self.count + 1, 2) - 0.5, halton(self.count + 1, 3) - 0.5]
+    }
+
+    /// Where the next sample is traced into.
+    pub fn sample_view(&self) -> &wgpu::TextureView {
+        &self.sample.view
+    }
+
+    /// Blend the traced sample into the average, or just redraw the average once it is done, and
+    /// draw it into `view`.
+    pub fn resolve(
+        &mut self,
+        enc: &mut wgpu::CommandEncoder,
+        view: &wgpu::TextureView,
+        queue: &wgpu::Queue,
+        index_buff: &Buff<u16>,
+        vertex_buff: &Buff<Vertex>,
+    ) {
+        let weight = if self.is_done() { 0.0 } else { 1.0 / (self.count + 1) as f32 };
+        self.data_buff.write(0, &[AccumulateData { weight, _spacer: [0.0; 3] }], queue);
+
+        let read = (self.count % 2) as usize;
+        {
+            let mut pass = enc.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("accumulate"),
+                color_attachments: &[
+                    Some(wgpu::RenderPassColorAttachment {
+                        view: &self.averages[1 - read].view,
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                            store: true,
+                        },
+                    }),
+                    Some(wgpu::RenderPassColorAttachment {
+                        view,
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                            store: true,
+                        },
+                    }),
+                ],
+                depth_stencil_attachment: None,
+            });
+            self.pipeline.draw_indexed(
+                &mut pass,
+                0..6, 0..1,
+                index_buff.slice(..),
+                vertex_buff.slice(..),
+                &self.bindings[read],
+            );
+        }
+
+        // Once done the count stays, and the pass keeps copying the same average over.
+        if !self.is_done() {
+            self.count += 1;
+            if self.is_done() {
+                log::info!("Accumulated {} samples", self.count);
+            }
+        }
+    }
+}
@@ -0,0 +1,221 @@
+//! Free-flight camera for `BlackholeGtx`.
+//!
+//! Camera space is the one `init_raydir` builds rays in: x to the right of the screen, y down and
+//! z forward. The orientation rotates camera space into the (z-up) scene.
+
+use std::collections::HashSet;
+
+use winit::event::VirtualKeyCode;
+
+/// In `RS` per second.
+const MOVE_SPEED: f32 = 1.0;
+/// Speed multiplier while shift is held.
+const FAST_FACTOR: f32 = 5.0;
+/// In radians per second.
+const ROLL_SPEED: f32 = 1.0;
+/// In radians per pixel.
+const MOUSE_SENSITIVITY: f32 = 0.005;
+
+/// Unit quaternion `[x, y, z, w]`, the layout `draw.frag` expects for `rotation`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Quat(pub [f32; 4]);
+
+impl Quat {
+    /// Right handed rotation by `angle` radians around the unit vector `axis`.
+    pub fn from_axis_angle(axis: [f32; 3], angle: f32) -> Self {
+        let (s, c) = (angle / 2.0).sin_cos();
+        Self([axis[0] * s, axis[1] * s, axis[2] * s, c])
+    }
+
+    /// Look along `forward` (in the scene) with the scene's z axis up and no roll.
+    pub fn look_along(forward: [f32; 3]) -> Self {
+        let [x, y, z] = forward;
+        let len = (x * x + y * y + z * z).sqrt();
+        let yaw = Self::from_axis_angle([0.0, 0.0, 1.0], (-x).atan2(y));
+        let pitch = Self::from_axis_angle([1.0, 0.0, 0.0], (z / len).clamp(-1.0, 1.0).asin());
+        // Starting from looking along +y.
+        let base = Self::from_axis_angle([1.0, 0.0, 0.0], -std::f32::consts::FRAC_PI_2);
+        (yaw * pitch * base).normalize()
+    }
+
+    /// Spherical interpolation from `self` at `t = 0` to `other` at `t = 1`, the short way round.
+    pub fn slerp(self, other: Self, t: f32) -> Self {
+        let mut dot: f32 = self.0.iter().zip(other.0).map(|(a, b)| a * b).sum();
+        let other = if dot < 0.0 {
+            dot = -dot;
+            Self(other.0.map(|it| -it))
+        } else {
+            other
+        };
+        let (w0, w1) = if dot > 0.9995 {
+            (1.0 - t, t)
+        } else {
+            let angle = dot.acos();
+            let sin = angle.sin();
+            (((1.0 - t) * angle).sin() / sin, (t * angle).sin() / sin)
+        };
+        Self(std::array::from_fn(|i| w0 * self.0[i] + w1 * other.0[i])).normalize()
+    }
+
+    pub fn normalize(self) -> Self {
+        let len = self.0.iter().map(|it| it * it).sum::<f32>().sqrt();
+        Self(self.0.map(|it| it / len))
+    }
+
+    pub fn rotate(self, v: [f32; 3]) -> [f32; 3] {
+        // v + 2 w (q x v) + 2 q x (q x v)
+        let [x, y, z, w] = self.0;
+        let cross = |a: [f32; 3], b: [f32; 3]| {
+            [
+                a[1] * b[2] - a[2] * b[1],
+                a[2] * b[0] - a[0] * b[2],
+                a[0] * b[1] - a[1] * b[0],
+            ]
+        };
+        let t = cross([x, y, z], v).map(|it| 2.0 * it);
+        let u = cross([x, y, z], t);
+        std::array::from_fn(|i| v[i] + w * t[i] + u[i])
+    }
+}
+
+impl std::ops::Mul for Quat {
+    type Output = Self;
+    /// Rotate by `rhs` first, then by `self`.
+    fn mul(self, rhs: Self) -> Self {
+        let [x1, y1, z1, w1] = self.0;
+        let [x2, y2, z2, w2] = rhs.0;
+        Self([
+            w1 * x2 + x1 * w2 + y1 * z2 - z1 * y2,
+            w1 * y2 - x1 * z2 + y1 * w2 + z1 * x2,
+            w1 * z2 + x1 * y2 - y1 * x2 + z1 * w2,
+            w1 * w2 - x1 * x2 - y1 * y2 - z1 * z2,
+        ])
+    }
+}
+
+/// Flies around with WASD, R/F for up and down and Q/E to roll, all relative to where it faces.
+/// Moving the mouse turns it.
+pub struct Camera {
+    pub position: [f32; 3],
+    pub orientation: Quat,
+    held: HashSet<VirtualKeyCode>,
+}
+
+impl Camera {
+    pub fn new(position: [f32; 3], orientation: Quat) -> Self {
+        Self {
+            position,
+            orientation,
+            held: HashSet::new(),
+        }
+    }
+
+    /// Track a key press or release, returns whether the camera uses that key.
+    pub fn key(&mut self, keycode: VirtualKeyCode, pressed: bool) -> bool {
+        use VirtualKeyCode::*;
+        if !matches!(keycode, W | A | S | D | R | F | Q | E | LShift | RShift) {
+            return false;
+        }
+        if pressed {
+            self.held.insert(keycode);
+        } else {
+            self.held.remove(&keycode);
+        }
+        true
+    }
+
+    /// Forget the held keys, for when the window loses focus and won't see them released.
+    pub fn release_all(&mut self) {
+        self.held.clear();
+    }
+
+    /// Turn by a mouse movement of `delta` pixels.
+    pub fn look(&mut self, delta: [f64; 2]) {
+        let yaw = Quat::from_axis_angle([0.0, 1.0, 0.0], delta[0] as f32 * MOUSE_SENSITIVITY);
+        let pitch = Quat::from_axis_angle([1.0, 0.0, 0.0], -delta[1] as f32 * MOUSE_SENSITIVITY);
+        self.turn(yaw * pitch);
+    }
+
+    /// Apply a rotation given in camera space.
+    fn turn(&mut self, rotation: Quat) {
+        self.orientation = (self.orientation * rotation).normalize();
+    }
+
+    /// Move and roll according to the held keys over `dt` seconds, returns whether anything changed.
+    pub fn advance(&mut self, dt: f32) -> bool {
+        let axis = |pos: VirtualKeyCode, neg: VirtualKeyCode| {
+            self.held.contains(&pos) as i32 as f32 - self.held.contains(&neg) as i32 as f32
+        };
+        let direction = [
+            axis(VirtualKeyCode::D, VirtualKeyCode::A),
+            axis(VirtualKeyCode::F, VirtualKeyCode::R),
+            axis(VirtualKeyCode::W, VirtualKeyCode::S),
+        ];
+        let roll = axis(VirtualKeyCode::E, VirtualKeyCode::Q);
+        if direction == [0.0; 3] && roll == 0.0 {
+            return false;
+        }
+
+        let fast = self.held.contains(&VirtualKeyCode::LShift) || self.held.contains(&VirtualKeyCode::RShift);
+        let speed = MOVE_SPEED * if fast { FAST_FACTOR } else { 1.0 };
+        let step = self.orientation.rotate(direction);
+        for (p, s) in self.position.iter_mut().zip(step) {
+            *p += s * speed * dt;
+        }
+        self.turn(Quat::from_axis_angle([0.0, 0.0, 1.0], roll * ROLL_SPEED * dt));
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: [f32; 3], b: [f32; 3]) {
+        assert!(a.iter().zip(b).all(|(a, b)| (a - b).abs() < 1e-5), "{:?} != {:?}", a, b);
+    }
+
+    fn looking_along_y() -> Camera {
+        Camera::new([0.0, -3.0, 0.0], Quat::from_axis_angle([1.0, 0.0, 0.0], -std::f32::consts::FRAC_PI_2))
+    }
+
+    #[test]
+    fn controls_follow_the_view() {
+        let mut camera = looking_along_y();
+        assert_close(camera.orientation.rotate([0.0, 0.0, 1.0]), [0.0, 1.0, 0.0]);
+        assert_close(camera.orientation.rotate([0.0, -1.0, 0.0]), [0.0, 0.0, 1.0]);
+
+        // Turn right by 90 degrees, forward is now +x and right is -y.
+        camera.look([std::f64::consts::FRAC_PI_2 / MOUSE_SENSITIVITY as f64, 0.0]);
+        camera.key(VirtualKeyCode::W, true);
+        assert!(camera.advance(1.0));
+        assert_close(camera.position, [MOVE_SPEED, -3.0, 0.0]);
+
+        // Roll doesn't move the camera but turns up towards the right.
+        camera.key(VirtualKeyCode::W, false);
+        camera.key(VirtualKeyCode::E, true);
+        assert!(camera.advance(std::f32::consts::FRAC_PI_2 / ROLL_SPEED));
+        assert_close(camera.position, [MOVE_SPEED, -3.0, 0.0]);
+        assert_close(camera.orientation.rotate([0.0, -1.0, 0.0]), [0.0, -1.0, 0.0]);
+
+        camera.key(VirtualKeyCode::E, false);
+        assert!(!camera.advance(1.0));
+    }
+
+    #[test]
+    fn look_along_keeps_z_up() {
+        let q = Quat::look_along([0.0, 1.0, 0.0]);
+        for v in [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0]] {
+            assert_close(q.rotate(v), looking_along_y().orientation.rotate(v));
+        }
+        let forward = [-0.48, 0.6, 0.64];
+        let q = Quat::look_along(forward);
+        assert_close(q.rotate([0.0, 0.0, 1.0]), forward);
+        // Right stays horizontal.
+        assert!(q.rotate([1.0, 0.0, 0.0])[2].abs() < 1e-5);
+
+        let halfway = looking_along_y().orientation.slerp(Quat::look_along([1.0, 0.0, 0.0]), 0.5);
+        let diagonal = std::f32::consts::FRAC_1_SQRT_2;
+        assert_close(halfway.rotate([0.0, 0.0, 1.0]), [diagonal, diagonal, 0.0]);
+    }
+}
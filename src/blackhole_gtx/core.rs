@@ -1,24 +1,37 @@
+use anyhow::Context;
 use bytemuck::{Pod, Zeroable};
+use serde::Deserialize;
 use wgpu::vertex_attr_array;
 use winit::event::VirtualKeyCode;
 
 use crate::{
-    engine_base::EngineBase,
+    cli::SimArgs,
+    engine_base::{EngineBase, FrameClock, Tile},
     include_glsl,
-    new_abstractions::{Buff, BuffInfo, VertexLayoutInfo, ZSTValue},
+    load_img,
+    new_abstractions::{Buff, BuffInfo, Tex, TexInfo, TISampler, TITexture, VertexLayoutInfo, ZSTValue, _2D, _Cube},
     render_pipeline_info, bind_group_info,
+    snapshot::{Header, Snapshot},
+};
+
+use super::{
+    accumulate::Accumulator,
+    camera::{Camera, Quat},
+    geodesic,
+    observer::{Observer, ObserverMode, ObserverSettings},
+    path::CameraPath,
 };
 
 #[derive(Debug, Clone, Copy, Pod, Zeroable)]
 #[repr(C)]
-struct Vertex(f32, f32);
+pub struct Vertex(f32, f32);
 impl VertexLayoutInfo for Vertex {
     const ATTRIBUTES: &'static [wgpu::VertexAttribute] = &vertex_attr_array![
         0 => Float32x2,
     ];
 }
 
-const VERTEX_BUFF: BuffInfo<Vertex> = BuffInfo::IT;
+pub const VERTEX_BUFF: BuffInfo<Vertex> = BuffInfo::IT;
 const VERTEX_DATA: [Vertex; 4] = [
     Vertex(-1.0, -1.0),
     Vertex(-1.0, 1.0),
@@ -30,29 +43,231 @@ const INDECES_DATA: [u16; 6] = [0, 1, 2, 3, 2, 1];
 // const K: BuffInfo<>
 #[derive(Debug, Clone, Copy, Pod, Zeroable)]
 #[repr(C)]
-struct CameraData { // NOTE THAT ORDER MATTERS!!!
-    dims: [f32; 2],
-    rotation: [f32; 2], // TODO quaternion
-    position: [f32; 3],
-    fov_y: f32,
+pub struct CameraData { // NOTE THAT ORDER MATTERS!!!
+    pub dims: [f32; 2],
+    /// Offset of the rays from the pixel centres in pixels, x right and y down. Only set while
+    /// accumulating, see `accumulate::Accumulator`.
+    pub jitter: [f32; 2],
+
+    /// Quaternion from camera space to the scene, see `camera::Quat`.
+    pub rotation: [f32; 4],
+    pub position: [f32; 3],
+    pub fov_y: f32,
+
+    pub activated: u32,
+    pub a: f32,
+    pub metric: u32,
+    pub integrator: u32,
+
+    pub tolerance: f32,
+    pub disk: u32,
+    pub disk_inner: f32,
+    pub disk_outer: f32,
+
+    pub disk_temperature: f32,
+    pub skybox: u32,
+    /// Set while `draw.frag` renders the lookup table instead of the screen.
+    pub lut_pass: u32,
+    pub _spacer1: [u32; 1],
+
+    /// Observer's 4-velocity followed by the spatial legs along x, y and z, as columns in the
+    /// metric's coordinates. See `geodesic::Spacetime::tetrad`.
+    pub tetrad: [[f32; 4]; 4],
+
+    /// Part of the `dims` sized image the view shows, offset and size in pixels. The whole image
+    /// unless rendering in tiles, see `engine_base::Tile`.
+    pub tile: [f32; 4],
+}
+
+/// Spacetime the rays are traced through, values match the `METRIC_*` constants in `draw.frag`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+#[repr(u32)]
+pub enum Metric {
+    Schwarzschild = 0,
+    KerrBoyerLindquist = 1,
+    KerrSchild = 2,
+}
+impl Metric {
+    pub const ALL: [Metric; 3] = [Self::Schwarzschild, Self::KerrBoyerLindquist, Self::KerrSchild];
+    pub fn from_u32(v: u32) -> Self {
+        Self::ALL.into_iter().find(|it| *it as u32 == v).unwrap_or(Self::Schwarzschild)
+    }
+    fn next(self) -> Self {
+        Self::from_u32((self as u32 + 1) % Self::ALL.len() as u32)
+    }
+}
+impl std::str::FromStr for Metric {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "schwarzschild" => Ok(Self::Schwarzschild),
+            "kerr-bl" => Ok(Self::KerrBoyerLindquist),
+            "kerr-schild" | "kerr-ks" => Ok(Self::KerrSchild),
+            _ => Err("expected one of schwarzschild, kerr-bl, kerr-schild".to_string()),
+        }
+    }
+}
+impl TryFrom<String> for Metric {
+    type Error = String;
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// How geodesics are stepped, values match the `INTEGRATOR_*` constants in `draw.frag`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+#[repr(u32)]
+pub enum Integrator {
+    Leapfrog = 0,
+    RungeKutta4 = 1,
+    /// Adaptive Dormand-Prince 5(4), step size controlled by `CameraData::tolerance`.
+    DormandPrince = 2,
+}
+impl Integrator {
+    pub const ALL: [Integrator; 3] = [Self::Leapfrog, Self::RungeKutta4, Self::DormandPrince];
+    pub fn from_u32(v: u32) -> Self {
+        Self::ALL.into_iter().find(|it| *it as u32 == v).unwrap_or(Self::Leapfrog)
+    }
+    fn next(self) -> Self {
+        Self::from_u32((self as u32 + 1) % Self::ALL.len() as u32)
+    }
+}
+impl std::str::FromStr for Integrator {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "leapfrog" => Ok(Self::Leapfrog),
+            "rk4" => Ok(Self::RungeKutta4),
+            "dopri" | "rk45" => Ok(Self::DormandPrince),
+            _ => Err("expected one of leapfrog, rk4, dopri".to_string()),
+        }
+    }
+}
+impl TryFrom<String> for Integrator {
+    type Error = String;
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// What escaped rays see, values match the `SKYBOX_*` constants in `draw.frag`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum Skybox {
+    /// Procedural checker grid, the default.
+    Grid = 0,
+    Equirectangular = 1,
+    Cubemap = 2,
+}
 
-    activated: u32,
+impl Skybox {
+    pub const ALL: [Skybox; 3] = [Self::Grid, Self::Equirectangular, Self::Cubemap];
+    pub fn from_u32(v: u32) -> Self {
+        Self::ALL.into_iter().find(|it| *it as u32 == v).unwrap_or(Self::Grid)
+    }
+}
+
+/// File names (without extension) of the cube map faces in a `--skybox` directory. They follow the
+/// usual y-up convention, the shader turns `py` towards the scene's +z.
+const CUBEMAP_FACES: [&str; 6] = ["px", "nx", "py", "ny", "pz", "nz"];
+
+/// Rays of the lookup table, by angle from the direction towards the hole and by the angle around it.
+const LUT_SIZE: (u32, u32) = (1024, 256);
+const LUT_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+/// Everything the lookup table depends on, it's rebuilt when this changes by more than rounding.
+/// Schwarzschild is spherically symmetric, so only the distance matters there, Kerr also depends on
+/// the inclination. Only static observers use the table, a moving one sees a different sky every
+/// frame.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct LutKey {
+    radius: f32,
+    inclination: f32,
     a: f32,
-    _spacer0: u32,
-    _spacer1: u32,
+    metric: u32,
+}
+impl LutKey {
+    /// How far things may drift before the table is rebuilt, the radius relative to itself and the
+    /// inclination in radians. Far below what a texel resolves.
+    const RADIUS_TOLERANCE: f32 = 1e-3;
+    const INCLINATION_TOLERANCE: f32 = 1e-3;
+    const SPIN_TOLERANCE: f32 = 1e-4;
+
+    fn new(cameradata: &CameraData) -> Self {
+        let [x, y, z] = cameradata.position;
+        let radius = (x * x + y * y + z * z).sqrt();
+        let inclination = match Metric::from_u32(cameradata.metric) {
+            Metric::Schwarzschild => 0.0,
+            _ => (z / radius).acos(),
+        };
+        Self {
+            radius,
+            inclination,
+            a: cameradata.a,
+            metric: cameradata.metric,
+        }
+    }
+    fn covers(&self, other: &Self) -> bool {
+        self.metric == other.metric
+            && (self.radius - other.radius).abs() <= Self::RADIUS_TOLERANCE * self.radius
+            && (self.inclination - other.inclination).abs() <= Self::INCLINATION_TOLERANCE
+            && (self.a - other.a).abs() <= Self::SPIN_TOLERANCE
+    }
 }
+
+/// Largest spin the shader handles, `a = M` (with `RS = 1`) is an extremal black hole.
+const MAX_SPIN: f32 = 0.5;
+const SPIN_STEP: f32 = 0.05;
+/// Relative error per step allowed for `Integrator::DormandPrince`.
+const DEFAULT_TOLERANCE: f32 = 1e-4;
+/// f32 can't resolve much better than this.
+const MIN_TOLERANCE: f32 = 1e-6;
+const DEFAULT_DISK_OUTER: f32 = 12.0;
+/// Peak temperature of the disk in Kelvin.
+const DEFAULT_DISK_TEMPERATURE: f32 = 8000.0;
+const DEFAULT_FLYBY_SPEED: f64 = 0.6;
+/// Proper time of a moving observer per second, in `RS / c`.
+const DEFAULT_TIME_SCALE: f32 = 10.0;
+/// What snapshots of the camera are saved as, and where F5 and F9 go without `--load`.
+const SNAPSHOT_SIM: &str = "blackhole";
+const DEFAULT_SNAPSHOT: &str = "blackhole.snapshot";
 bind_group_info!(CameraDataGroup; wgpu::ShaderStages::FRAGMENT;
     0 => (BuffInfo::<CameraData>, wgpu::BufferBindingType::Uniform),
 );
 
+bind_group_info!(SkyboxGroup; wgpu::ShaderStages::FRAGMENT;
+    0 => (TexInfo::<_2D, TITexture>, wgpu::TextureSampleType::Float { filterable: true }),
+    1 => (TexInfo::<_Cube, TITexture>, wgpu::TextureSampleType::Float { filterable: true }),
+    2 => (TexInfo::<_2D, TISampler>, wgpu::SamplerBindingType::Filtering),
+);
+
+bind_group_info!(LutGroup; wgpu::ShaderStages::FRAGMENT;
+    0 => (TexInfo::<_2D, TITexture>, wgpu::TextureSampleType::Float { filterable: true }),
+    1 => (TexInfo::<_2D, TISampler>, wgpu::SamplerBindingType::Filtering),
+);
+
 render_pipeline_info!(GTXRenderPipeline;
     0 => CameraDataGroupInfo<'device>,
+    1 => SkyboxGroupInfo<'device>,
+    ;
+    0 => (Vertex, wgpu::VertexStepMode::Vertex),
+);
+
+render_pipeline_info!(PresolvedRenderPipeline;
+    0 => CameraDataGroupInfo<'device>,
+    1 => SkyboxGroupInfo<'device>,
+    2 => LutGroupInfo<'device>,
     ;
     0 => (Vertex, wgpu::VertexStepMode::Vertex),
 );
 
 pub struct BlackholeGtx {
     gtx_render_pipeline: GTXRenderPipeline,
+    /// `draw.frag` again, but rendering into the lookup table.
+    lut_render_pipeline: GTXRenderPipeline,
+    presolved_render_pipeline: PresolvedRenderPipeline,
 
     index_buff: Buff<u16>,
     vertex_buff: Buff<Vertex>,
@@ -62,81 +277,639 @@ pub struct BlackholeGtx {
     cameradata_buff: Buff<CameraData>,
     cameradata_modified: bool,
 
-    last_mouse_pos: [f64; 2],
+    /// Placeholders until a skybox is loaded, the shader only samples the one selected by
+    /// `CameraData::skybox`.
+    skybox_equirect: Tex<_2D>,
+    skybox_cube: Tex<_Cube>,
+    skybox_binding: SkyboxGroup,
+
+    /// Look rays up in a precomputed table rather than tracing every pixel, see `LutKey`.
+    use_lut: bool,
+    lut: Tex<_2D>,
+    lut_binding: LutGroup,
+    lut_cameradata_buff: Buff<CameraData>,
+    lut_cameradata_binding: CameraDataGroup,
+    /// What the table was last built for, `None` if it never was.
+    lut_key: Option<LutKey>,
+
+    camera: Camera,
+    /// Where the rays start from, the camera only turns it unless it's static.
+    observer: Observer,
+    observer_settings: ObserverSettings,
+    /// How fast a moving observer's proper time passes, see `DEFAULT_TIME_SCALE`.
+    time_scale: f32,
+    clock: FrameClock,
+    /// Scripted camera, and how far it has played.
+    path: Option<CameraPath>,
+    path_time: f32,
+    /// `None` until the cursor moves over the window.
+    last_mouse_pos: Option<[f64; 2]>,
     activated: bool,
+    /// Inner edge of the disk, the ISCO (which depends on the spin) if not set.
+    disk_inner: Option<f32>,
+
+    /// Average jittered frames while the camera holds still.
+    accumulate: bool,
+    /// Created the first time it's needed, `Rgba32Float` targets aren't everywhere.
+    accumulator: Option<Accumulator>,
+    /// See `Accumulator::max_samples`.
+    max_samples: u32,
+    view_format: wgpu::TextureFormat,
+    /// Size of the view, `cameradata.dims` is the whole image when rendering a tile.
+    view_size: (u32, u32),
+    tile: Option<Tile>,
+    /// Where F5 saves the camera and F9 restores it from, see `--load`.
+    snapshot_path: String,
 }
 
 impl BlackholeGtx {
     fn send_cameradata(&self, queue: &wgpu::Queue) {
         self.cameradata_buff.write(0, &[self.cameradata], &queue);
     }
+    fn sync_camera(&mut self) {
+        if self.observer.mode == ObserverMode::Static {
+            self.observer = Observer::fixed(self.spacetime(), self.camera.position.map(|it| it as f64));
+        }
+        self.cameradata.position = self.observer.position().map(|it| it as f32);
+        self.cameradata.rotation = self.view_rotation().0;
+        self.cameradata.tetrad = self.observer.tetrad().map(|col| col.map(|it| it as f32));
+        self.cameradata_modified = true;
+    }
+    fn spacetime(&self) -> geodesic::Spacetime {
+        geodesic::Spacetime::from_camera(&self.cameradata)
+    }
+    /// The camera's orientation, turned along with the observer.
+    fn view_rotation(&self) -> Quat {
+        let turn = Quat::from_axis_angle([0.0, 0.0, 1.0], self.observer.azimuth_change() as f32);
+        turn * self.camera.orientation
+    }
+    /// Start a new observer from wherever the camera is now.
+    fn set_observer(&mut self, mode: ObserverMode) -> anyhow::Result<()> {
+        self.camera.orientation = self.view_rotation();
+        self.camera.position = self.observer.position().map(|it| it as f32);
+        let forward = self.camera.orientation.rotate([0.0, 0.0, 1.0]).map(|it| it as f64);
+        let position = self.camera.position.map(|it| it as f64);
+        self.observer = Observer::new(mode, self.spacetime(), position, forward, &self.observer_settings)?;
+        self.sync_camera();
+        Ok(())
+    }
+    /// Restart the observer after the spacetime changed under it, its coordinates mean something
+    /// else now.
+    fn restart_observer(&mut self) {
+        if let Err(err) = self.set_observer(self.observer.mode) {
+            log::error!("{:#}, the camera is static now", err);
+            self.set_observer(ObserverMode::Static).expect("static observers always work");
+        }
+    }
+    fn log_observer(&self) {
+        let [v_r, v_th, v_ph] = self.observer.local_velocity();
+        log::info!(
+            "{:?} observer at r = {:.2}, moving at ({:.3}, {:.3}, {:.3}) c radially, polar and azimuthal",
+            self.observer.mode,
+            self.spacetime().r(self.spacetime().event(self.observer.position())),
+            v_r,
+            v_th,
+            v_ph
+        );
+    }
+    fn set_metric(&mut self, metric: Metric) {
+        if metric != Metric::Schwarzschild && self.cameradata.a == 0.0 {
+            log::info!("Metric {:?} with spin a = 0 is plain Schwarzschild", metric);
+        }
+        self.cameradata.metric = metric as u32;
+        self.cameradata_modified = true;
+        self.update_disk_inner();
+        self.restart_observer();
+    }
+    fn set_spin(&mut self, a: f32) {
+        self.cameradata.a = a.clamp(-MAX_SPIN, MAX_SPIN);
+        self.cameradata_modified = true;
+        self.update_disk_inner();
+        self.restart_observer();
+    }
+    fn update_disk_inner(&mut self) {
+        self.cameradata.disk_inner = self.disk_inner.unwrap_or_else(|| {
+            let a = match Metric::from_u32(self.cameradata.metric) {
+                Metric::Schwarzschild => 0.0,
+                _ => self.cameradata.a,
+            };
+            geodesic::isco(a as f64) as f32
+        });
+        self.cameradata_modified = true;
+    }
+    fn log_disk(&self) {
+        if self.cameradata.disk != 0 {
+            log::info!(
+                "Accretion disk from r = {:.2} to {:.2}, peak temperature {} K",
+                self.cameradata.disk_inner,
+                self.cameradata.disk_outer,
+                self.cameradata.disk_temperature
+            );
+        } else {
+            log::info!("Accretion disk off");
+        }
+    }
+    /// Trace the pixel under the cursor with the CPU reference tracer, to compare with the shader.
+    fn log_reference_trace(&self) {
+        let [x, y] = self.last_mouse_pos.unwrap_or_default();
+        let pixel = (x as u32, y as u32);
+        let uv = geodesic::pixel_uv(&self.cameradata, pixel);
+        let outcome = geodesic::trace_camera_ray(&self.cameradata, uv);
+        log::info!(
+            "Reference trace of pixel {:?}: {:?}, color {:?}",
+            pixel,
+            outcome,
+            geodesic::Tracer::from_camera(&self.cameradata).shade(outcome)
+        );
+    }
+    fn set_integrator(&mut self, integrator: Integrator) {
+        if self.cameradata.integrator != integrator as u32 {
+            self.lut_key = None;
+        }
+        self.cameradata.integrator = integrator as u32;
+        self.cameradata_modified = true;
+    }
+    fn set_tolerance(&mut self, tolerance: f32) {
+        let tolerance = tolerance.clamp(MIN_TOLERANCE, 1.0);
+        if self.cameradata.tolerance != tolerance {
+            self.lut_key = None;
+        }
+        self.cameradata.tolerance = tolerance;
+        self.cameradata_modified = true;
+    }
+    fn log_integrator(&self) {
+        log::info!(
+            "Integrator {:?}, tolerance {:.0e}",
+            Integrator::from_u32(self.cameradata.integrator),
+            self.cameradata.tolerance
+        );
+    }
+    /// Load the skybox from an equirectangular image, or from a directory with the six cube map
+    /// faces named as in [`CUBEMAP_FACES`].
+    fn load_skybox(&mut self, path: &str, device: &wgpu::Device, queue: &wgpu::Queue) -> anyhow::Result<()> {
+        let path_buf = std::path::Path::new(path);
+        if path_buf.is_dir() {
+            let entries = std::fs::read_dir(path_buf)?.collect::<Result<Vec<_>, _>>()?;
+            let mut faces = Vec::with_capacity(6);
+            for face in CUBEMAP_FACES {
+                let entry = entries
+                    .iter()
+                    .find(|it| it.path().file_stem().is_some_and(|stem| stem == face))
+                    .with_context(|| format!("cube map face `{}` not found in {}", face, path))?;
+                faces.push(image::open(entry.path()).with_context(|| format!("failed to load {:?}", entry.path()))?);
+            }
+            let faces = faces.try_into().expect("six faces");
+            self.skybox_cube = Tex::<_Cube>::create(path, faces, device, queue)?;
+            self.cameradata.skybox = Skybox::Cubemap as u32;
+        } else {
+            let img = load_img!(file path).with_context(|| format!("failed to load {}", path))?;
+            self.skybox_equirect = Tex::<_2D>::create(img, device, queue);
+            self.cameradata.skybox = Skybox::Equirectangular as u32;
+        }
+        self.skybox_binding = SkyboxGroupInfo::new(device).bind(
+            self.skybox_equirect.binding_texture(),
+            self.skybox_cube.binding_texture(),
+            self.skybox_equirect.binding_sampler(),
+        );
+        self.cameradata_modified = true;
+        log::info!("Loaded {:?} skybox from {}", Skybox::from_u32(self.cameradata.skybox), path);
+        Ok(())
+    }
+    fn log_lut(&self) {
+        if self.use_lut {
+            log::info!("Drawing from the geodesic lookup table");
+        } else {
+            log::info!("Tracing every pixel");
+        }
+        self.warn_lut();
+    }
+    /// Say what the lookup table leaves out, on stderr since it changes the picture.
+    fn warn_lut(&self) {
+        if !self.use_lut {
+            return;
+        }
+        if self.cameradata.disk != 0 {
+            log::warn!("The accretion disk is not drawn from the geodesic lookup table");
+        }
+        if self.observer.mode != ObserverMode::Static {
+            log::warn!("The geodesic lookup table only works for a static observer, tracing every pixel instead");
+        }
+    }
+    /// Whether this frame is drawn from the lookup table.
+    fn lut_active(&self) -> bool {
+        self.use_lut && self.activated && self.observer.mode == ObserverMode::Static
+    }
+    /// Trace the lookup table for the current position and spacetime.
+    ///
+    /// A fragment pass of `draw.frag` rather than a compute pass, the black hole only asks for
+    /// WebGL2 level devices, which have no compute shaders, and this way the table comes out of
+    /// the very same tracer as the screen.
+    fn build_lut(&mut self, enc: &mut wgpu::CommandEncoder, queue: &wgpu::Queue) {
+        let cameradata = CameraData {
+            dims: [LUT_SIZE.0 as f32, LUT_SIZE.1 as f32],
+            jitter: [0.0; 2],
+            tile: [0.0, 0.0, LUT_SIZE.0 as f32, LUT_SIZE.1 as f32],
+            activated: 1,
+            disk: 0,
+            lut_pass: 1,
+            ..self.cameradata
+        };
+        self.lut_cameradata_buff.write(0, &[cameradata], queue);
+
+        {
+            let mut pass = enc.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("geodesic-lut"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &self.lut.view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: None,
+            });
+            self.lut_render_pipeline.draw_indexed(
+                &mut pass,
+                0..6, 0..1,
+                self.index_buff.slice(..),
+                self.vertex_buff.slice(..),
+                &self.lut_cameradata_binding,
+                &self.skybox_binding,
+            );
+        }
+
+        let key = LutKey::new(&self.cameradata);
+        log::info!("Rebuilding the geodesic lookup table for r = {:.2}, a = {:.2}", key.radius, key.a);
+        self.lut_key = Some(key);
+    }
+    /// Apply the camera path at `path_time`.
+    fn follow_path(&mut self) {
+        let Some(sample) = self.path.as_ref().map(|it| it.sample(self.path_time, self.camera.position)) else {
+            return;
+        };
+        // Only through the setters when something changes, they restart the observer.
+        if let Some(metric) = sample.metric.filter(|it| *it as u32 != self.cameradata.metric) {
+            self.set_metric(metric);
+        }
+        if let Some(a) = sample.a.filter(|it| *it != self.cameradata.a) {
+            self.set_spin(a);
+        }
+        if let Some(integrator) = sample.integrator {
+            self.set_integrator(integrator);
+        }
+        if let Some(tolerance) = sample.tolerance {
+            self.set_tolerance(tolerance);
+        }
+        if let Some(disk) = sample.disk {
+            self.cameradata.disk = disk as u32;
+        }
+        if let Some(inner) = sample.disk_inner {
+            self.disk_inner = Some(inner);
+            self.update_disk_inner();
+        }
+        if let Some(outer) = sample.disk_outer {
+            self.cameradata.disk_outer = outer;
+        }
+        if let Some(temperature) = sample.disk_temperature {
+            self.cameradata.disk_temperature = temperature;
+        }
+        if let Some(fov_y) = sample.fov_y {
+            self.cameradata.fov_y = fov_y;
+        }
+        // A moving observer goes its own way, the path only turns it.
+        if let Some(position) = sample.position {
+            self.camera.position = position;
+        }
+        if let Some(rotation) = sample.rotation {
+            self.camera.orientation = rotation;
+        }
+        self.sync_camera();
+    }
+    /// Point the frustum at the current tile, or the whole view.
+    fn update_tile(&mut self) {
+        let (width, height) = self.view_size;
+        let (image, offset) = match self.tile {
+            Some(tile) => (tile.image, tile.offset),
+            None => ((width, height), (0, 0)),
+        };
+        self.cameradata.dims = [image.0 as f32, image.1 as f32];
+        self.cameradata.tile = [offset.0 as f32, offset.1 as f32, width as f32, height as f32];
+        self.cameradata_modified = true;
+    }
+    fn log_accumulate(&self) {
+        if self.accumulate {
+            log::info!("Accumulating samples while the camera holds still");
+        } else {
+            log::info!("Tracing one ray per pixel");
+        }
+    }
+    /// Save the camera, the spacetime and the disk to `path`.
+    fn save_snapshot(&self, path: &str) -> anyhow::Result<()> {
+        let [width, height] = self.cameradata.dims;
+        let mut header = Header::new(SNAPSHOT_SIM, (width as u32, height as u32));
+        header.time = self.path_time;
+        Snapshot::new(header, &[self.cameradata]).save(path)?;
+        log::info!("Saved the camera to {}", path);
+        Ok(())
+    }
+    fn load_snapshot(&mut self, path: &str) -> anyhow::Result<()> {
+        let snapshot = Snapshot::load(path)?;
+        snapshot.expect_sim(SNAPSHOT_SIM)?;
+        let [saved] = snapshot.payload::<CameraData>()?[..] else {
+            anyhow::bail!("{} should hold exactly one camera", path);
+        };
+        self.restore(saved);
+        self.path_time = snapshot.header.time;
+        log::info!("Loaded the camera from {}", path);
+        self.log_metric();
+        self.log_observer();
+        Ok(())
+    }
+    /// Take the camera, the spacetime and the disk from `saved`. The view, the skybox and the kind
+    /// of observer stay as they are, the observer starts over from the saved position.
+    fn restore(&mut self, saved: CameraData) {
+        self.cameradata = CameraData {
+            dims: self.cameradata.dims,
+            jitter: self.cameradata.jitter,
+            skybox: self.cameradata.skybox,
+            lut_pass: 0,
+            tile: self.cameradata.tile,
+            a: saved.a.clamp(-MAX_SPIN, MAX_SPIN),
+            metric: Metric::from_u32(saved.metric) as u32,
+            integrator: Integrator::from_u32(saved.integrator) as u32,
+            tolerance: saved.tolerance.clamp(MIN_TOLERANCE, 1.0),
+            ..saved
+        };
+        self.activated = saved.activated != 0;
+        // Only an inner edge set by hand sticks, the ISCO follows the spin.
+        self.disk_inner = None;
+        self.update_disk_inner();
+        if self.cameradata.disk_inner != saved.disk_inner {
+            self.disk_inner = Some(saved.disk_inner);
+            self.update_disk_inner();
+        }
+        self.camera.position = saved.position;
+        self.camera.orientation = Quat(saved.rotation);
+        let mode = self.observer.mode;
+        self.observer = Observer::fixed(self.spacetime(), saved.position.map(|it| it as f64));
+        if let Err(err) = self.set_observer(mode) {
+            log::error!("{:#}, the camera is static now", err);
+            self.set_observer(ObserverMode::Static).expect("static observers always work");
+        }
+    }
+    fn log_metric(&self) {
+        log::info!(
+            "Metric {:?}, spin a = {:.2}",
+            Metric::from_u32(self.cameradata.metric),
+            self.cameradata.a
+        );
+    }
 }
 
 impl EngineBase for BlackholeGtx {
     fn title() -> &'static str {
         "Black hole geodesic tracer."
     }
+    fn flags() -> &'static [&'static str] {
+        &["lut", "accumulate", "disk", "trace"]
+    }
+    fn usage() -> &'static str {
+        "\
+options:
+  --load <snapshot>      start from a saved camera, also where F5 and F9 go
+  --spin <a>             spin of the hole, within [-0.5, 0.5]
+  --metric <name>        schwarzschild, kerr-bl or kerr-schild
+  --integrator <name>    leapfrog, rk4 or dopri
+  --tolerance <t>        step error tolerance of dopri, within [1e-6, 1]
+  --trace                trace geodesics from the start
+  --disk                 draw the accretion disk
+  --disk-inner <r>       inner radius of the disk, the ISCO by default
+  --disk-outer <r>       outer radius of the disk
+  --disk-temperature <K> peak temperature of the disk
+  --skybox <path>        an equirectangular image, or a directory of six cube map faces
+  --observer <mode>      static, orbit, infall or flyby
+  --orbit-radius <r>     radius of the orbit, the starting distance by default
+  --flyby-speed <v>      starting speed of a flyby, as a fraction of c
+  --time-scale <t>       proper time of a moving observer per second, in RS / c
+  --path <file>          follow a keyframed camera path
+  --lut                  draw from a geodesic lookup table, static observers only
+  --accumulate           average the frames while the camera holds still
+  --samples <n>          stop accumulating after n samples, implies --accumulate
+
+keys:
+  W/A/S/D/R/F            move, faster with Shift
+  Q/E                    roll
+  mouse                  look around
+  Space                  toggle tracing geodesics
+  M                      cycle the metrics
+  Z/X                    lower and raise the spin
+  I                      cycle the integrators
+  [/]                    loosen and tighten the tolerance
+  K                      toggle the disk
+  L                      toggle the lookup table
+  J                      toggle accumulating
+  O                      cycle the observers
+  P                      trace the pixel under the cursor on the CPU
+  F5/F9                  save and load the camera
+"
+    }
     fn resize(
         &mut self,
         config: &wgpu::SurfaceConfiguration,
-        _device: &wgpu::Device,
+        device: &wgpu::Device,
         queue: &wgpu::Queue,
     ) {
-        self.cameradata.dims = [config.width as f32, config.height as f32];
+        self.view_size = (config.width, config.height);
+        self.update_tile();
         self.send_cameradata(&queue);
+        if let Some(accumulator) = &mut self.accumulator {
+            accumulator.resize((config.width, config.height), device);
+        }
+    }
+    fn configure(
+        &mut self,
+        args: &SimArgs,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> anyhow::Result<()> {
+        // Before everything else, so the options can change what the snapshot has.
+        if let Some(path) = args.get::<String>("load")? {
+            self.load_snapshot(&path)?;
+            self.snapshot_path = path;
+        }
+        if let Some(a) = args.get::<f32>("spin")? {
+            if a.abs() > MAX_SPIN {
+                anyhow::bail!("--spin must be within [-{0}, {0}]", MAX_SPIN);
+            }
+            self.set_spin(a);
+        }
+        if let Some(metric) = args.get::<Metric>("metric")? {
+            self.set_metric(metric);
+        }
+        if let Some(integrator) = args.get::<Integrator>("integrator")? {
+            self.set_integrator(integrator);
+        }
+        if let Some(tolerance) = args.get::<f32>("tolerance")? {
+            if !(MIN_TOLERANCE..=1.0).contains(&tolerance) {
+                anyhow::bail!("--tolerance must be within [{}, 1]", MIN_TOLERANCE);
+            }
+            self.set_tolerance(tolerance);
+        }
+        if let Some(inner) = args.get::<f32>("disk-inner")? {
+            self.disk_inner = Some(inner);
+            self.update_disk_inner();
+        }
+        if let Some(outer) = args.get::<f32>("disk-outer")? {
+            self.cameradata.disk_outer = outer;
+        }
+        if let Some(temperature) = args.get::<f32>("disk-temperature")? {
+            self.cameradata.disk_temperature = temperature;
+        }
+        if self.cameradata.disk_inner >= self.cameradata.disk_outer {
+            anyhow::bail!(
+                "the disk's inner radius ({}) must be below its outer radius ({})",
+                self.cameradata.disk_inner,
+                self.cameradata.disk_outer
+            );
+        }
+        if let Some(path) = args.get::<String>("skybox")? {
+            self.load_skybox(&path, device, queue)?;
+        }
+        if let Some(radius) = args.get::<f64>("orbit-radius")? {
+            self.observer_settings.orbit_radius = Some(radius);
+        }
+        if let Some(speed) = args.get::<f64>("flyby-speed")? {
+            self.observer_settings.flyby_speed = speed;
+        }
+        if let Some(time_scale) = args.get::<f32>("time-scale")? {
+            self.time_scale = time_scale;
+        }
+        if let Some(path) = args.get::<String>("path")? {
+            let camera_path = CameraPath::load(&path)?;
+            log::info!("Following the camera path in {}, {:.1} s long", path, camera_path.duration());
+            self.path = Some(camera_path);
+            self.follow_path();
+        }
+        if let Some(mode) = args.get::<ObserverMode>("observer")? {
+            self.set_observer(mode)?;
+            self.log_observer();
+        }
+        if args.flag("lut")? {
+            self.use_lut = true;
+        }
+        if args.flag("accumulate")? {
+            self.accumulate = true;
+        }
+        if let Some(samples) = args.get::<u32>("samples")? {
+            self.accumulate = true;
+            self.max_samples = samples;
+        }
+        if args.flag("disk")? {
+            self.cameradata.disk = 1;
+            self.cameradata_modified = true;
+        }
+        if args.flag("trace")? {
+            self.activated = true;
+            self.cameradata.activated = 1;
+            self.cameradata_modified = true;
+        }
+        self.warn_lut();
+        Ok(())
     }
     fn update(&mut self, event: winit::event::WindowEvent) {
         // unused
         match event {
-            #[allow(deprecated)]
-            winit::event::WindowEvent::CursorMoved { position, modifiers, .. } => {
-                let delta_pos = [
-                    position.x - self.last_mouse_pos[0],
-                    position.y - self.last_mouse_pos[1],
-                ];
-                if modifiers.shift() {
-                    self.cameradata.rotation[0] += (delta_pos[0] * 0.01) as f32; // yaw
-                    self.cameradata.rotation[1] += (delta_pos[1] * 0.01) as f32; // pitch
-                    self.cameradata.rotation[1] = self.cameradata.rotation[1].clamp(0.0, std::f32::consts::PI);
-                    if delta_pos.iter().all(|v| *v != 0.0) {
-                        self.cameradata_modified = true;
+            winit::event::WindowEvent::CursorMoved { position, .. } => {
+                if let Some(last) = self.last_mouse_pos {
+                    let delta_pos = [position.x - last[0], position.y - last[1]];
+                    if delta_pos != [0.0, 0.0] {
+                        self.camera.look(delta_pos);
+                        self.sync_camera();
                     }
                 }
-                self.last_mouse_pos = [position.x, position.y];
+                self.last_mouse_pos = Some([position.x, position.y]);
+            }
+            winit::event::WindowEvent::CursorLeft { .. } => {
+                // Don't turn by the jump to wherever the cursor comes back in.
+                self.last_mouse_pos = None;
+            }
+            winit::event::WindowEvent::Focused(false) => {
+                self.camera.release_all();
             }
             winit::event::WindowEvent::KeyboardInput { input, .. } => {
                 let keycode = input.virtual_keycode;
                 if let Some(keycode) = keycode {
-                    if let winit::event::ElementState::Pressed = input.state {
+                    let pressed = input.state == winit::event::ElementState::Pressed;
+                    if self.camera.key(keycode, pressed) {
+                        // Moves in `render`, as long as the key is held.
+                    } else if pressed {
                         match keycode {
-                            VirtualKeyCode::W => {
-                                self.cameradata.position[1] += 0.1;
+                            VirtualKeyCode::Space => {
+                                self.activated = !self.activated;
+                                self.cameradata.activated = if self.activated { 1 } else { 0 };
                                 self.cameradata_modified = true;
                             }
-                            VirtualKeyCode::S => {
-                                self.cameradata.position[1] -= 0.1;
-                                self.cameradata_modified = true;
+                            VirtualKeyCode::M => {
+                                self.set_metric(Metric::from_u32(self.cameradata.metric).next());
+                                self.log_metric();
                             }
-                            VirtualKeyCode::D => {
-                                self.cameradata.position[0] += 0.1;
-                                self.cameradata_modified = true;
+                            VirtualKeyCode::Z => {
+                                self.set_spin(self.cameradata.a - SPIN_STEP);
+                                self.log_metric();
                             }
-                            VirtualKeyCode::A => {
-                                self.cameradata.position[0] -= 0.1;
-                                self.cameradata_modified = true;
+                            VirtualKeyCode::X => {
+                                self.set_spin(self.cameradata.a + SPIN_STEP);
+                                self.log_metric();
                             }
-                            VirtualKeyCode::Q => {
-                                self.cameradata.position[2] += 0.1;
-                                self.cameradata_modified = true;
+                            VirtualKeyCode::I => {
+                                self.set_integrator(Integrator::from_u32(self.cameradata.integrator).next());
+                                self.log_integrator();
+                            }
+                            VirtualKeyCode::LBracket => {
+                                self.set_tolerance(self.cameradata.tolerance * 10.0);
+                                self.log_integrator();
+                            }
+                            VirtualKeyCode::RBracket => {
+                                self.set_tolerance(self.cameradata.tolerance / 10.0);
+                                self.log_integrator();
                             }
-                            VirtualKeyCode::E => {
-                                self.cameradata.position[2] -= 0.1;
+                            VirtualKeyCode::K => {
+                                self.cameradata.disk = 1 - self.cameradata.disk;
                                 self.cameradata_modified = true;
+                                self.log_disk();
+                                self.warn_lut();
                             }
-                            VirtualKeyCode::Space => {
-                                self.activated = !self.activated;
-                                self.cameradata.activated = if self.activated { 1 } else { 0 };
+                            VirtualKeyCode::P => {
+                                self.log_reference_trace();
+                            }
+                            VirtualKeyCode::L => {
+                                self.use_lut = !self.use_lut;
+                                self.log_lut();
+                            }
+                            VirtualKeyCode::J => {
+                                self.accumulate = !self.accumulate;
                                 self.cameradata_modified = true;
+                                self.log_accumulate();
+                            }
+                            VirtualKeyCode::O => {
+                                if let Err(err) = self.set_observer(self.observer.mode.next()) {
+                                    log::error!("{:#}", err);
+                                    self.set_observer(ObserverMode::Static).expect("static observers always work");
+                                }
+                                self.log_observer();
+                                self.warn_lut();
+                            }
+                            VirtualKeyCode::F5 => {
+                                if let Err(err) = self.save_snapshot(&self.snapshot_path) {
+                                    log::error!("{:#}", err);
+                                }
+                            }
+                            VirtualKeyCode::F9 => {
+                                let path = self.snapshot_path.clone();
+                                if let Err(err) = self.load_snapshot(&path) {
+                                    log::error!("{:#}", err);
+                                }
                             }
                             
                             _ => {}
@@ -158,21 +931,46 @@ impl EngineBase for BlackholeGtx {
         let vertex_buff = Buff::new(&device, &VERTEX_BUFF, &VERTEX_DATA);
         let index_buff = Buff::new(&device, &BuffInfo::IT, &INDECES_DATA);
 
+        // Looking along +y, with the scene's z up.
+        let camera = Camera::new(
+            [0.0, -3.0, 0.0],
+            Quat::from_axis_angle([1.0, 0.0, 0.0], -std::f32::consts::FRAC_PI_2),
+        );
         let cameradata = CameraData{
             dims: [config.width as f32, config.height as f32],
-            position: [0.0,-3.0,0.0],
-            rotation: [0.0,3.1415/2.0],
+            jitter: [0.0; 2],
+            position: camera.position,
+            rotation: camera.orientation.0,
             fov_y: std::f32::consts::FRAC_PI_2, // 45deg
             activated: 0,
             a: 0.0,
-            _spacer0:0,
-            _spacer1:0,
+            metric: Metric::Schwarzschild as u32,
+            integrator: Integrator::Leapfrog as u32,
+            tolerance: DEFAULT_TOLERANCE,
+            disk: 0,
+            disk_inner: geodesic::isco(0.0) as f32,
+            disk_outer: DEFAULT_DISK_OUTER,
+            disk_temperature: DEFAULT_DISK_TEMPERATURE,
+            skybox: Skybox::Grid as u32,
+            lut_pass: 0,
+            _spacer1: [0; 1],
+            tetrad: [[0.0; 4]; 4],
+            tile: [0.0, 0.0, config.width as f32, config.height as f32],
         };
         dbg!((cameradata, std::mem::size_of::<CameraData>()));
         let cameradata_buff = Buff::new(&device, &BuffInfo::<CameraData>::IT, &[cameradata]);
         let cameradata_info = CameraDataGroupInfo::new(&device);
         let cameradata_binding = cameradata_info.bind(cameradata_buff.slice(..));
 
+        let skybox_equirect = Tex::<_2D>::create_uninit("skybox-equirect", (1, 1), device);
+        let skybox_cube = Tex::<_Cube>::create_uninit("skybox-cube", 1, device);
+        let skybox_info = SkyboxGroupInfo::new(device);
+        let skybox_binding = skybox_info.bind(
+            skybox_equirect.binding_texture(),
+            skybox_cube.binding_texture(),
+            skybox_equirect.binding_sampler(),
+        );
+
         let gtx_render_pipeline = GTXRenderPipeline::new(
             &device,
             (
@@ -192,19 +990,122 @@ impl EngineBase for BlackholeGtx {
             &[Some(config.view_formats[0].into())],
             &VERTEX_BUFF,
             &cameradata_info,
+            &skybox_info,
         );
 
-        Self {
+        let mut lut = Tex::<_2D>::create_uninit_with_format("geodesic-lut", LUT_SIZE, LUT_FORMAT, device);
+        // The angle around the direction towards the hole runs along v and wraps, without repeating
+        // there's a seam where it goes from 2 pi back to 0.
+        lut.sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("geodesic-lut"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::Repeat,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        let lut_info = LutGroupInfo::new(device);
+        let lut_binding = lut_info.bind(lut.binding_texture(), lut.binding_sampler());
+        let lut_cameradata_buff = Buff::new(device, &BuffInfo::<CameraData>::IT, &[cameradata]);
+        let lut_cameradata_binding = cameradata_info.bind(lut_cameradata_buff.slice(..));
+
+        let lut_render_pipeline = GTXRenderPipeline::new(
+            device,
+            (
+                device.create_shader_module(include_glsl!(
+                    "shaders/draw.vert",
+                    naga::ShaderStage::Vertex
+                )),
+                "main",
+            ),
+            (
+                device.create_shader_module(include_glsl!(
+                    "shaders/draw.frag",
+                    naga::ShaderStage::Fragment
+                )),
+                "main",
+            ),
+            &[Some(LUT_FORMAT.into())],
+            &VERTEX_BUFF,
+            &cameradata_info,
+            &skybox_info,
+        );
+        let presolved_render_pipeline = PresolvedRenderPipeline::new(
+            device,
+            (
+                device.create_shader_module(include_glsl!(
+                    "shaders/draw.vert",
+                    naga::ShaderStage::Vertex
+                )),
+                "main",
+            ),
+            (
+                device.create_shader_module(include_glsl!(
+                    "shaders/draw-presolved-geodesics.frag",
+                    naga::ShaderStage::Fragment
+                )),
+                "main",
+            ),
+            &[Some(config.view_formats[0].into())],
+            &VERTEX_BUFF,
+            &cameradata_info,
+            &skybox_info,
+            &lut_info,
+        );
+
+        let mut this = Self {
             gtx_render_pipeline,
+            lut_render_pipeline,
+            presolved_render_pipeline,
             vertex_buff,
             index_buff,
             cameradata_binding,
             cameradata_buff,
             cameradata,
             cameradata_modified: false,
-            last_mouse_pos: [0.0, 0.0],
+            skybox_equirect,
+            skybox_cube,
+            skybox_binding,
+            use_lut: false,
+            lut,
+            lut_binding,
+            lut_cameradata_buff,
+            lut_cameradata_binding,
+            lut_key: None,
+            observer: Observer::fixed(
+                geodesic::Spacetime::from_camera(&cameradata),
+                camera.position.map(|it| it as f64),
+            ),
+            observer_settings: ObserverSettings {
+                orbit_radius: None,
+                flyby_speed: DEFAULT_FLYBY_SPEED,
+            },
+            time_scale: DEFAULT_TIME_SCALE,
+            camera,
+            clock: FrameClock::new(),
+            path: None,
+            path_time: 0.0,
+            last_mouse_pos: None,
             activated: false,
-        }
+            disk_inner: None,
+            accumulate: false,
+            accumulator: None,
+            max_samples: 0,
+            view_format: config.view_formats[0],
+            view_size: (config.width, config.height),
+            tile: None,
+            snapshot_path: DEFAULT_SNAPSHOT.to_string(),
+        };
+        this.sync_camera();
+        this
+    }
+    fn set_fixed_timestep(&mut self, dt: f32) {
+        self.clock.set_fixed(dt);
+    }
+    fn set_tile(&mut self, tile: Tile) -> bool {
+        self.tile = Some(tile);
+        self.update_tile();
+        true
     }
     fn render(
         &mut self,
@@ -216,21 +1117,51 @@ impl EngineBase for BlackholeGtx {
         let mut enc =
             device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
 
-        // {
-        //     self.cameradata.a = std::time::SystemTime::now().duration_since(std::time::SystemTime::UNIX_EPOCH).unwrap().as_secs_f64().sin() as f32;
-        //     self.cameradata_modified = true;
-        // }
+        let dt = self.clock.tick();
+        if self.path.is_some() {
+            self.path_time += dt;
+            self.follow_path();
+        }
+        let moving = self.observer.mode != ObserverMode::Static;
+        if moving {
+            self.observer.advance((dt * self.time_scale) as f64);
+        }
+        if self.camera.advance(dt) || moving {
+            self.sync_camera();
+        }
 
-        if self.cameradata_modified {
+        if self.accumulate && self.accumulator.is_none() {
+            self.accumulator = Some(Accumulator::new(self.view_size, self.view_format, device));
+        }
+        let accumulator = if self.accumulate { self.accumulator.as_mut() } else { None };
+        let jitter = match accumulator {
+            Some(accumulator) => {
+                // Everything but the jitter starts over.
+                if self.cameradata_modified {
+                    accumulator.reset();
+                }
+                accumulator.max_samples = self.max_samples;
+                accumulator.jitter()
+            }
+            None => [0.0; 2],
+        };
+        if self.cameradata_modified || jitter != self.cameradata.jitter {
+            self.cameradata.jitter = jitter;
             self.cameradata_modified = false;
             self.send_cameradata(queue);
         }
+        let use_lut = self.lut_active();
+        if use_lut && !self.lut_key.is_some_and(|it| it.covers(&LutKey::new(&self.cameradata))) {
+            self.build_lut(&mut enc, queue);
+        }
 
-        {
+        let accumulator = if self.accumulate { self.accumulator.as_ref() } else { None };
+        let target = accumulator.map_or(view, |it| it.sample_view());
+        if !accumulator.is_some_and(|it| it.is_done()) {
             let mut pass = enc.begin_render_pass(&wgpu::RenderPassDescriptor {
                 label: None,
                 color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &view,
+                    view: target,
                     resolve_target: None,
                     ops: wgpu::Operations {
                         load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
@@ -239,13 +1170,29 @@ impl EngineBase for BlackholeGtx {
                 })],
                 depth_stencil_attachment: None,
             });
-            self.gtx_render_pipeline.draw_indexed(
-                &mut pass,
-                0..6, 0..1,
-                self.index_buff.slice(..),
-                self.vertex_buff.slice(..),
-                &self.cameradata_binding,
-            );
+            if use_lut {
+                self.presolved_render_pipeline.draw_indexed(
+                    &mut pass,
+                    0..6, 0..1,
+                    self.index_buff.slice(..),
+                    self.vertex_buff.slice(..),
+                    &self.cameradata_binding,
+                    &self.skybox_binding,
+                    &self.lut_binding,
+                );
+            } else {
+                self.gtx_render_pipeline.draw_indexed(
+                    &mut pass,
+                    0..6, 0..1,
+                    self.index_buff.slice(..),
+                    self.vertex_buff.slice(..),
+                    &self.cameradata_binding,
+                    &self.skybox_binding,
+                );
+            }
+        }
+        if let Some(accumulator) = self.accumulator.as_mut().filter(|_| self.accumulate) {
+            accumulator.resolve(&mut enc, view, queue, &self.index_buff, &self.vertex_buff);
         }
 
         queue.submit(Some(enc.finish().into()));
@@ -0,0 +1,1074 @@
+//! CPU reference for the geodesic tracer in `shaders/draw.frag`, in f64.
+//!
+//! Everything here mirrors the GLSL function of the same name, including the step sizes and
+//! termination conditions, so single pixels can be checked against the shader and the physics can
+//! be tested without a GPU. Matrices are stored like GLSL `mat4`s, `m[column][row]`.
+
+use super::core::{CameraData, Integrator, Metric};
+
+pub type Vec3 = [f64; 3];
+pub type Vec4 = [f64; 4];
+pub type Mat4 = [[f64; 4]; 4];
+
+/// Matches `RS` in the shader, all lengths are in units of the Schwarzschild radius.
+pub const RS: f64 = 1.0;
+const MAX_STEPS: usize = 1000;
+const ESCAPE_RADIUS: f64 = 25.0 * RS;
+
+const ZERO: Mat4 = [[0.0; 4]; 4];
+
+const DOPRI_MIN_STEP: f64 = 1e-4;
+/// Dormand-Prince 5(4) tableau: stage coefficients, 5th order weights, and the difference between
+/// the 5th and the embedded 4th order weights.
+const DOPRI_A: [[f64; 6]; 6] = [
+    [0.0; 6],
+    [1.0 / 5.0, 0.0, 0.0, 0.0, 0.0, 0.0],
+    [3.0 / 40.0, 9.0 / 40.0, 0.0, 0.0, 0.0, 0.0],
+    [44.0 / 45.0, -56.0 / 15.0, 32.0 / 9.0, 0.0, 0.0, 0.0],
+    [19372.0 / 6561.0, -25360.0 / 2187.0, 64448.0 / 6561.0, -212.0 / 729.0, 0.0, 0.0],
+    [9017.0 / 3168.0, -355.0 / 33.0, 46732.0 / 5247.0, 49.0 / 176.0, -5103.0 / 18656.0, 0.0],
+];
+const DOPRI_B: [f64; 6] = [35.0 / 384.0, 0.0, 500.0 / 1113.0, 125.0 / 192.0, -2187.0 / 6784.0, 11.0 / 84.0];
+const DOPRI_E: [f64; 7] = [
+    71.0 / 57600.0,
+    0.0,
+    -71.0 / 16695.0,
+    71.0 / 1920.0,
+    -17253.0 / 339200.0,
+    22.0 / 525.0,
+    -1.0 / 40.0,
+];
+
+/// How a traced ray ended.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RayOutcome {
+    /// The ray left towards the background, from the given (rectilinear) direction.
+    Escaped(Vec3),
+    /// The ray fell into the horizon.
+    Captured,
+    /// The ray came from the accretion disk, at radius `r`. `redshift` is the observed over the
+    /// emitted frequency.
+    Disk { r: f64, redshift: f64 },
+}
+
+/// Thin accretion disk in the equatorial plane, see `camera.disk`.
+#[derive(Debug, Clone, Copy)]
+pub struct Disk {
+    pub inner: f64,
+    pub outer: f64,
+    /// Peak temperature in Kelvin.
+    pub temperature: f64,
+}
+
+fn sq(x: f64) -> f64 {
+    x * x
+}
+fn dot3(a: Vec3, b: Vec3) -> f64 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+fn dot4(a: Vec4, b: Vec4) -> f64 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2] + a[3] * b[3]
+}
+fn normalize3(v: Vec3) -> Vec3 {
+    let len = dot3(v, v).sqrt();
+    [v[0] / len, v[1] / len, v[2] / len]
+}
+fn add(a: Vec4, b: Vec4) -> Vec4 {
+    std::array::from_fn(|i| a[i] + b[i])
+}
+fn scale(v: Vec4, s: f64) -> Vec4 {
+    v.map(|it| it * s)
+}
+/// `s * sum(w * v)`
+fn combine(s: f64, terms: &[(f64, Vec4)]) -> Vec4 {
+    std::array::from_fn(|i| s * terms.iter().map(|(w, v)| w * v[i]).sum::<f64>())
+}
+fn mat_vec(m: &Mat4, v: Vec4) -> Vec4 {
+    std::array::from_fn(|row| (0..4).map(|col| m[col][row] * v[col]).sum())
+}
+fn outer(a: Vec4, b: Vec4) -> Mat4 {
+    std::array::from_fn(|col| std::array::from_fn(|row| a[col] * b[row]))
+}
+fn mat_add(a: &Mat4, b: &Mat4, scale_b: f64) -> Mat4 {
+    std::array::from_fn(|col| std::array::from_fn(|row| a[col][row] + scale_b * b[col][row]))
+}
+fn diag(d: Vec4) -> Mat4 {
+    std::array::from_fn(|col| std::array::from_fn(|row| if col == row { d[col] } else { 0.0 }))
+}
+
+const G_MINKOWSKY: Mat4 = [
+    [-1.0, 0.0, 0.0, 0.0],
+    [0.0, 1.0, 0.0, 0.0],
+    [0.0, 0.0, 1.0, 0.0],
+    [0.0, 0.0, 0.0, 1.0],
+];
+
+///////// ----- COORDINATE UTILITIES ----- /////////
+
+pub fn spherical_to_rectilinear([r, th, ph]: Vec3) -> Vec3 {
+    [r * th.sin() * ph.cos(), r * th.sin() * ph.sin(), r * th.cos()]
+}
+pub fn rectilinear_to_spherical(v: Vec3) -> Vec3 {
+    let r = dot3(v, v).sqrt();
+    if r == 0.0 {
+        return [0.0; 3];
+    }
+    let vn = normalize3(v);
+    if vn[0] == 0.0 && vn[1] == 0.0 {
+        [r, vn[2].acos(), 0.0]
+    } else {
+        [r, vn[2].acos(), vn[1].atan2(vn[0])]
+    }
+}
+/// Mirrors `tangent_spherical_to_rectilinear_mat`, the negated tangent map, so `v` maps to the
+/// direction the ray comes from.
+pub fn tangent_spherical_to_rectilinear([r, th, ph]: Vec3, v: Vec3) -> Vec3 {
+    let e_r = spherical_to_rectilinear([1.0, th, ph]);
+    let e_th = spherical_to_rectilinear([1.0, th + std::f64::consts::FRAC_PI_2, ph]);
+    let e_ph = [-ph.sin(), ph.cos(), 0.0];
+    let (s_r, s_th, s_ph) = (v[0], v[1] * r, v[2] * r * th.sin());
+    std::array::from_fn(|i| -(s_r * e_r[i] + s_th * e_th[i] + s_ph * e_ph[i]))
+}
+
+///////// ----- CAMERA ----- /////////
+
+/// `uv` for the center of pixel `(x, y)` (origin top left), as the vertex shader hands it over,
+/// moved by `camera.jitter`.
+pub fn pixel_uv(camera: &CameraData, (x, y): (u32, u32)) -> [f64; 2] {
+    let [width, height] = camera.dims.map(|it| it as f64);
+    let [jitter_x, jitter_y] = camera.jitter.map(|it| it as f64);
+    [
+        (x as f64 + 0.5 + jitter_x) / width * 2.0 - 1.0,
+        1.0 - (y as f64 + 0.5 + jitter_y) / height * 2.0,
+    ]
+}
+
+/// Mirrors `init_raydir`.
+pub fn init_raydir(camera: &CameraData, uv: [f64; 2]) -> Vec3 {
+    let [width, height] = camera.dims.map(|it| it as f64);
+    let p = normalize3([
+        uv[0] * width / height,
+        -uv[1],
+        1.0 / (camera.fov_y as f64 / 2.0).tan(),
+    ]);
+    // quat_rotate
+    let [x, y, z, w] = camera.rotation.map(|it| it as f64);
+    let cross = |a: Vec3, b: Vec3| {
+        [a[1] * b[2] - a[2] * b[1], a[2] * b[0] - a[0] * b[2], a[0] * b[1] - a[1] * b[0]]
+    };
+    let t = cross([x, y, z], p).map(|it| 2.0 * it);
+    let u = cross([x, y, z], t);
+    std::array::from_fn(|i| p[i] + w * t[i] + u[i])
+}
+
+/// Tangent of the photon arriving from direction `dir` at the observer whose tetrad this is, as
+/// `main` sets up `x1`.
+pub fn ray_tangent(tetrad: &Mat4, dir: Vec3) -> Vec4 {
+    mat_vec(tetrad, [1.0, -dir[0], -dir[1], -dir[2]])
+}
+
+/// Mirrors `background`, so traced pixels can be compared with the shader's output.
+pub fn background(dir: Vec3) -> [f64; 4] {
+    let d = normalize3(dir);
+    let m = |v: f64| v.rem_euclid(0.1) > 0.05;
+    let grid = if (m(d[0]) == m(d[1])) == m(d[2]) { 1.0 } else { 0.0 };
+    let channel = |v: f64| v.max(0.0) * (grid * 0.4 + 0.6) - grid * 0.1 * v;
+    [channel(d[0]), channel(d[1]), channel(d[2]), 1.0]
+}
+
+///////// ----- GR STUFF ----- /////////
+
+/// A metric together with the black hole's spin, see `camera.metric` / `camera.a`.
+#[derive(Debug, Clone, Copy)]
+pub struct Spacetime {
+    pub metric: Metric,
+    pub a: f64,
+}
+
+impl Spacetime {
+    pub fn from_camera(camera: &CameraData) -> Self {
+        Self {
+            metric: Metric::from_u32(camera.metric),
+            a: camera.a as f64,
+        }
+    }
+
+    /// Whether coordinates are `(t, r, th, ph)` rather than Cartesian `(t, x, y, z)`.
+    pub fn is_spherical(&self) -> bool {
+        self.metric != Metric::KerrSchild
+    }
+
+    /// Radial coordinate of the event `x`.
+    pub fn r(&self, x: Vec4) -> f64 {
+        if self.is_spherical() {
+            x[1]
+        } else {
+            self.r_kerr_ks([x[1], x[2], x[3]])
+        }
+    }
+
+    pub fn r_horizon(&self) -> f64 {
+        match self.metric {
+            Metric::Schwarzschild => RS,
+            _ => (RS + (sq(RS) - 4.0 * sq(self.a)).max(0.0).sqrt()) / 2.0,
+        }
+    }
+
+    pub fn g(&self, x: Vec4) -> Mat4 {
+        match self.metric {
+            Metric::Schwarzschild => diag(g_schwarzschild(x)),
+            Metric::KerrBoyerLindquist => self.g_kerr_bl(x),
+            Metric::KerrSchild => self.g_kerr_ks(x),
+        }
+    }
+
+    pub fn g_inv(&self, x: Vec4) -> Mat4 {
+        match self.metric {
+            Metric::Schwarzschild => diag(g_schwarzschild(x).map(|it| 1.0 / it)),
+            Metric::KerrBoyerLindquist => g_inv_kerr_bl(&self.g_kerr_bl(x)),
+            Metric::KerrSchild => self.g_inv_kerr_ks(x),
+        }
+    }
+
+    pub fn dgdx(&self, x: Vec4) -> [Mat4; 4] {
+        match self.metric {
+            Metric::Schwarzschild => dgdx_schwarzschild(x).map(diag),
+            Metric::KerrBoyerLindquist => self.dgdx_kerr_bl(x),
+            Metric::KerrSchild => self.dgdx_kerr_ks(x),
+        }
+    }
+
+    /// `cs[l][i][j]` is the Christoffel symbol with upper index `l`.
+    pub fn christoffel_symbols(&self, x: Vec4) -> [Mat4; 4] {
+        christoffel_symbols(&self.g_inv(x), &self.dgdx(x))
+    }
+
+    /// Rescale `x1` to unit spatial length and solve for its time component so it is a
+    /// future-directed null vector. Left alone inside the ergosphere, like the shader.
+    pub fn normalize_null_geodesic(&self, x0: Vec4, x1: &mut Vec4) {
+        let len = dot3([x1[1], x1[2], x1[3]], [x1[1], x1[2], x1[3]]).sqrt();
+        for v in x1.iter_mut() {
+            *v /= len;
+        }
+        let g = self.g(x0);
+        let a = g[0][0];
+        if a >= 0.0 {
+            return;
+        }
+        let spatial = [0.0, x1[1], x1[2], x1[3]];
+        let b = 2.0 * dot4(g[0], spatial);
+        let c = dot4(mat_vec(&g, spatial), spatial);
+        x1[0] = (-b - (b * b - 4.0 * a * c).max(0.0).sqrt()) / (2.0 * a);
+    }
+
+    /// Mirrors `acceleration`, the second derivative of the geodesic.
+    pub fn acceleration(&self, x0: Vec4, x1: Vec4) -> Vec4 {
+        geodesic_acceleration(&self.christoffel_symbols(x0), x1)
+    }
+
+    /// Mirrors `flat_norm`: Euclidean length of the displacement `v` at `x0`.
+    pub fn flat_norm(&self, x0: Vec4, v: Vec4) -> f64 {
+        if self.is_spherical() {
+            let (r, sin_th) = (x0[1], x0[2].sin());
+            (sq(v[1]) + sq(r * v[2]) + sq(r * sin_th * v[3])).sqrt()
+        } else {
+            dot3([v[1], v[2], v[3]], [v[1], v[2], v[3]]).sqrt()
+        }
+    }
+
+    pub fn step_leapfrog(&self, dt: f64, x0: &mut Vec4, x1: &mut Vec4) {
+        *x0 = add(*x0, scale(*x1, dt * 0.5));
+        *x1 = add(*x1, scale(self.acceleration(*x0, *x1), dt));
+        *x0 = add(*x0, scale(*x1, dt * 0.5));
+    }
+
+    pub fn step_rk4(&self, dt: f64, x0: &mut Vec4, x1: &mut Vec4) {
+        let k1x = *x1;
+        let k1v = self.acceleration(*x0, k1x);
+        let k2x = add(*x1, scale(k1v, 0.5 * dt));
+        let k2v = self.acceleration(add(*x0, scale(k1x, 0.5 * dt)), k2x);
+        let k3x = add(*x1, scale(k2v, 0.5 * dt));
+        let k3v = self.acceleration(add(*x0, scale(k2x, 0.5 * dt)), k3x);
+        let k4x = add(*x1, scale(k3v, dt));
+        let k4v = self.acceleration(add(*x0, scale(k3x, dt)), k4x);
+        *x0 = add(*x0, combine(dt / 6.0, &[(1.0, k1x), (2.0, k2x), (2.0, k3x), (1.0, k4x)]));
+        *x1 = add(*x1, combine(dt / 6.0, &[(1.0, k1v), (2.0, k2v), (2.0, k3v), (1.0, k4v)]));
+    }
+
+    /// One Dormand-Prince 5(4) step, returns the next state and the relative error of the step.
+    pub fn step_dopri(&self, dt: f64, x0: Vec4, x1: Vec4) -> (Vec4, Vec4, f64) {
+        let mut kx = [[0.0; 4]; 7];
+        let mut kv = [[0.0; 4]; 7];
+        for stage in 0..6 {
+            let weights = |k: &[Vec4; 7]| {
+                combine(dt, &std::array::from_fn::<_, 6, _>(|i| (DOPRI_A[stage][i], k[i])))
+            };
+            kx[stage] = add(x1, weights(&kv));
+            kv[stage] = self.acceleration(add(x0, weights(&kx)), kx[stage]);
+        }
+        let solution = |k: &[Vec4; 7]| combine(dt, &std::array::from_fn::<_, 6, _>(|i| (DOPRI_B[i], k[i])));
+        let x0_next = add(x0, solution(&kx));
+        let x1_next = add(x1, solution(&kv));
+        kx[6] = x1_next;
+        kv[6] = self.acceleration(x0_next, x1_next);
+        let err = |k: &[Vec4; 7]| combine(dt, &std::array::from_fn::<_, 7, _>(|i| (DOPRI_E[i], k[i])));
+        let err = (self.flat_norm(x0, err(&kx)) / self.r(x0))
+            .max(self.flat_norm(x0, err(&kv)) / self.flat_norm(x0, x1));
+        (x0_next, x1_next, err)
+    }
+
+    fn r_stop(&self) -> f64 {
+        match self.metric {
+            Metric::Schwarzschild => RS * 1.03,
+            Metric::KerrBoyerLindquist => self.r_horizon() + 0.02 * RS,
+            Metric::KerrSchild => self.r_horizon(),
+        }
+    }
+
+    /// Mirrors `equator_side`, only the sign is meaningful.
+    fn equator_side(&self, x0: Vec4) -> f64 {
+        if self.is_spherical() {
+            x0[2].cos()
+        } else {
+            x0[3]
+        }
+    }
+
+    /// Killing vector of rotations around the spin axis at `x`, `d/dph` in spherical coordinates.
+    pub fn d_ph(&self, x: Vec4) -> Vec4 {
+        if self.is_spherical() {
+            [0.0, 0.0, 0.0, 1.0]
+        } else {
+            [0.0, -x[2], x[1], 0.0]
+        }
+    }
+
+    /// Mirrors `disk_redshift`: observed over emitted frequency of a photon with tangent `x1`
+    /// leaving the disk at `x0`. `x1_camera` is the tangent the ray started out with at
+    /// `x0_camera`, built from the observer's tetrad. 0 where there is no circular orbit.
+    pub fn disk_redshift(&self, x0_camera: Vec4, x1_camera: Vec4, x0: Vec4, x1: Vec4) -> f64 {
+        let r = self.r(x0);
+        let a = if self.metric == Metric::Schwarzschild { 0.0 } else { self.a };
+        let m = RS / 2.0;
+        let omega = m.sqrt() / (r.powf(1.5) + a * m.sqrt());
+        let d_ph = self.d_ph(x0);
+
+        let g = self.g(x0);
+        let u = add([1.0, 0.0, 0.0, 0.0], scale(d_ph, omega));
+        let uu = dot4(mat_vec(&g, u), u);
+        if uu >= 0.0 {
+            return 0.0;
+        }
+        let u_t = 1.0 / (-uu).sqrt();
+        let p = mat_vec(&g, x1);
+        let (e, l) = (-p[0], dot4(p, d_ph));
+
+        // The tetrad makes the observed frequency 1 for the starting tangent, whose energy is
+        // this. `e` is the energy after the rescaling in `normalize_null_geodesic`.
+        let e_camera = -mat_vec(&self.g(x0_camera), x1_camera)[0];
+        1.0 / (u_t * e_camera.max(1e-4) * (1.0 - omega * l / e))
+    }
+
+    fn heading_out(&self, x0: Vec4, x1: Vec4) -> bool {
+        if self.is_spherical() {
+            -x1[1] > 0.0
+        } else {
+            -dot3([x0[1], x0[2], x0[3]], [x1[1], x1[2], x1[3]]) > 0.0
+        }
+    }
+
+    /// Event at the rectilinear `position`, at `t = 0`.
+    pub fn event(&self, position: Vec3) -> Vec4 {
+        let [x, y, z] = if self.is_spherical() {
+            rectilinear_to_spherical(position)
+        } else {
+            position
+        };
+        [0.0, x, y, z]
+    }
+
+    /// Inverse of [`Spacetime::event`], dropping the time.
+    pub fn position(&self, x: Vec4) -> Vec3 {
+        if self.is_spherical() {
+            spherical_to_rectilinear([x[1], x[2], x[3]])
+        } else {
+            [x[1], x[2], x[3]]
+        }
+    }
+
+    /// `g(v, w)` at `x`.
+    pub fn dot(&self, x: Vec4, v: Vec4, w: Vec4) -> f64 {
+        dot4(mat_vec(&self.g(x), v), w)
+    }
+
+    /// Scale the timelike `u` to a 4-velocity, `g(u, u) = -1`.
+    pub fn normalize_timelike(&self, x: Vec4, u: Vec4) -> Vec4 {
+        scale(u, 1.0 / (-self.dot(x, u, u)).sqrt())
+    }
+
+    /// 4-velocity of an observer hovering at `x`. Nothing can hover inside the ergosphere, there
+    /// it is the observer with zero angular momentum instead. `None` inside the horizon.
+    pub fn static_observer(&self, x: Vec4) -> Option<Vec4> {
+        let t = [1.0, 0.0, 0.0, 0.0];
+        let u = if self.dot(x, t, t) < 0.0 {
+            t
+        } else {
+            let d_ph = self.d_ph(x);
+            let omega = -self.dot(x, t, d_ph) / self.dot(x, d_ph, d_ph);
+            add(t, scale(d_ph, omega))
+        };
+        (self.dot(x, u, u) < 0.0).then(|| self.normalize_timelike(x, u))
+    }
+
+    /// Rectilinear unit vectors along the radial, polar and azimuthal directions at `x`.
+    fn flat_axes(&self, x: Vec4) -> [Vec3; 3] {
+        let [_, th, ph] = if self.is_spherical() {
+            [x[1], x[2], x[3]]
+        } else {
+            rectilinear_to_spherical([x[1], x[2], x[3]])
+        };
+        [
+            spherical_to_rectilinear([1.0, th, ph]),
+            spherical_to_rectilinear([1.0, th + std::f64::consts::FRAC_PI_2, ph]),
+            [-ph.sin(), ph.cos(), 0.0],
+        ]
+    }
+
+    /// Coordinate vectors pointing along the (flat) radial, polar and azimuthal directions at `x`,
+    /// each one unit of flat length long.
+    fn flat_directions(&self, x: Vec4) -> [Vec4; 3] {
+        if self.is_spherical() {
+            let (r, sin_th) = (x[1], x[2].sin());
+            [[0.0, 1.0, 0.0, 0.0], [0.0, 0.0, 1.0 / r, 0.0], [0.0, 0.0, 0.0, 1.0 / (r * sin_th)]]
+        } else {
+            self.flat_axes(x).map(|[x, y, z]| [0.0, x, y, z])
+        }
+    }
+
+    /// Frame of an observer with 4-velocity `u` at `x`, as columns: `u` followed by the radial,
+    /// polar and azimuthal legs, orthonormalized in that order. Without an observer it falls back
+    /// to the bare coordinate directions with a zero time leg, what the shader used to do.
+    pub fn local_frame(&self, x: Vec4, u: Option<Vec4>) -> Mat4 {
+        let directions = self.flat_directions(x);
+        let Some(u) = u else {
+            return [[0.0; 4], directions[0], directions[1], directions[2]];
+        };
+        let mut frame = [u, [0.0; 4], [0.0; 4], [0.0; 4]];
+        for (i, v) in directions.into_iter().enumerate() {
+            // Gram-Schmidt, `g(u, u) = -1` flips the sign of the first projection.
+            let mut w = add(v, scale(u, self.dot(x, v, u)));
+            for leg in &frame[1..=i] {
+                w = add(w, scale(*leg, -self.dot(x, w, *leg)));
+            }
+            frame[i + 1] = scale(w, 1.0 / self.dot(x, w, w).sqrt());
+        }
+        frame
+    }
+
+    /// `camera.tetrad`: [`Spacetime::local_frame`] with the spatial legs turned to point along the
+    /// rectilinear x, y and z axes, so `tetrad * (1, -d)` is the photon arriving from direction
+    /// `d` with unit frequency.
+    pub fn tetrad(&self, x: Vec4, u: Option<Vec4>) -> Mat4 {
+        let [u, e_r, e_th, e_ph] = self.local_frame(x, u);
+        let axes = self.flat_axes(x);
+        let leg = |i: usize| combine(1.0, &[(axes[0][i], e_r), (axes[1][i], e_th), (axes[2][i], e_ph)]);
+        [u, leg(0), leg(1), leg(2)]
+    }
+
+    /// Initial event and tangent for a ray arriving at a static observer at `position` from
+    /// direction `dir` (both rectilinear), set up the same way `main` in the shader does.
+    #[cfg(test)]
+    pub fn init_ray(&self, position: Vec3, dir: Vec3) -> (Vec4, Vec4) {
+        let x0 = self.event(position);
+        let tetrad = self.tetrad(x0, self.static_observer(x0));
+        (x0, ray_tangent(&tetrad, dir))
+    }
+
+    /// Direction a ray came from, given the final state of [`Tracer::trace`].
+    pub fn escape_direction(&self, x0: Vec4, x1: Vec4) -> Vec3 {
+        if self.is_spherical() {
+            tangent_spherical_to_rectilinear([x0[1], x0[2], x0[3]], [x1[1], x1[2], x1[3]])
+        } else {
+            [-x1[1], -x1[2], -x1[3]]
+        }
+    }
+
+    fn g_kerr_bl(&self, p: Vec4) -> Mat4 {
+        let (a, r, th) = (self.a, p[1], p[2]);
+        let (sin_th, cos_th) = th.sin_cos();
+        let sigma = r * r + a * a * cos_th * cos_th;
+        let delta = r * r - RS * r + a * a;
+
+        let t_t = -(1.0 - RS * r / sigma);
+        let r_r = sigma / delta;
+        let th_th = sigma;
+        let ph_ph = (r * r + a * a + RS * r * a * a / sigma * sin_th * sin_th) * sin_th * sin_th;
+        let t_ph = -RS * r * a * sin_th * sin_th / sigma;
+
+        [
+            [t_t, 0.0, 0.0, t_ph],
+            [0.0, r_r, 0.0, 0.0],
+            [0.0, 0.0, th_th, 0.0],
+            [t_ph, 0.0, 0.0, ph_ph],
+        ]
+    }
+
+    fn dgdx_kerr_bl(&self, p: Vec4) -> [Mat4; 4] {
+        let (a, r, th) = (self.a, p[1], p[2]);
+        let (s, c) = th.sin_cos();
+        let ss = s * s;
+        let sigma = r * r + a * a * c * c;
+        let delta = r * r - RS * r + a * a;
+        let dsigma_dr = 2.0 * r;
+        let dsigma_dth = -2.0 * a * a * c * s;
+        let ddelta_dr = 2.0 * r - RS;
+
+        let t_t_r = RS * (sigma - r * dsigma_dr) / sq(sigma);
+        let t_ph_r = -RS * a * ss * (sigma - r * dsigma_dr) / sq(sigma);
+        let r_r_r = (dsigma_dr * delta - sigma * ddelta_dr) / sq(delta);
+        let th_th_r = dsigma_dr;
+        let ph_ph_r = (2.0 * r + RS * a * a * ss * (sigma - r * dsigma_dr) / sq(sigma)) * ss;
+
+        let f = r * r + a * a + RS * r * a * a * ss / sigma;
+        let df_dth = RS * r * a * a * (2.0 * s * c * sigma - ss * dsigma_dth) / sq(sigma);
+        let t_t_th = -RS * r * dsigma_dth / sq(sigma);
+        let t_ph_th = -RS * r * a * (2.0 * s * c * sigma - ss * dsigma_dth) / sq(sigma);
+        let r_r_th = dsigma_dth / delta;
+        let th_th_th = dsigma_dth;
+        let ph_ph_th = df_dth * ss + f * 2.0 * s * c;
+
+        [
+            ZERO,
+            [
+                [t_t_r, 0.0, 0.0, t_ph_r],
+                [0.0, r_r_r, 0.0, 0.0],
+                [0.0, 0.0, th_th_r, 0.0],
+                [t_ph_r, 0.0, 0.0, ph_ph_r],
+            ],
+            [
+                [t_t_th, 0.0, 0.0, t_ph_th],
+                [0.0, r_r_th, 0.0, 0.0],
+                [0.0, 0.0, th_th_th, 0.0],
+                [t_ph_th, 0.0, 0.0, ph_ph_th],
+            ],
+            ZERO,
+        ]
+    }
+
+    fn r_kerr_ks(&self, p: Vec3) -> f64 {
+        let aa = sq(self.a);
+        let b = dot3(p, p) - aa;
+        ((b + (b * b + 4.0 * aa * sq(p[2])).sqrt()) / 2.0).sqrt()
+    }
+
+    /// Outgoing Kerr-Schild `f` and `k`, see the comment above `r_kerr_ks` in the shader.
+    fn kerr_ks_fk(&self, p: Vec4) -> (f64, Vec4) {
+        let (a, x, y, z) = (self.a, p[1], p[2], p[3]);
+        let r = self.r_kerr_ks([x, y, z]);
+        let rr = r * r;
+        let f = RS * r * rr / (rr * rr + sq(a * z));
+        let k = [
+            1.0,
+            -(r * x - a * y) / (rr + sq(a)),
+            -(r * y + a * x) / (rr + sq(a)),
+            -z / r,
+        ];
+        (f, k)
+    }
+
+    fn g_kerr_ks(&self, p: Vec4) -> Mat4 {
+        let (f, k) = self.kerr_ks_fk(p);
+        mat_add(&G_MINKOWSKY, &outer(k, k), f)
+    }
+
+    fn g_inv_kerr_ks(&self, p: Vec4) -> Mat4 {
+        let (f, mut k) = self.kerr_ks_fk(p);
+        k[0] = -k[0];
+        mat_add(&G_MINKOWSKY, &outer(k, k), -f)
+    }
+
+    fn dgdx_kerr_ks(&self, p: Vec4) -> [Mat4; 4] {
+        // Much smaller than the shader's step, f64 can afford it.
+        const DX: f64 = 1e-6;
+        std::array::from_fn(|i| {
+            if i == 0 {
+                return ZERO;
+            }
+            let mut plus = p;
+            let mut minus = p;
+            plus[i] += DX;
+            minus[i] -= DX;
+            mat_add(&self.g_kerr_ks(plus), &self.g_kerr_ks(minus), -1.0)
+                .map(|col| col.map(|v| v / (2.0 * DX)))
+        })
+    }
+}
+
+/// A spacetime plus how to integrate geodesics through it, see `camera.integrator`.
+#[derive(Debug, Clone, Copy)]
+pub struct Tracer {
+    pub spacetime: Spacetime,
+    pub integrator: Integrator,
+    /// Relative error per step for [`Integrator::DormandPrince`].
+    pub tolerance: f64,
+    pub disk: Option<Disk>,
+}
+
+impl Tracer {
+    pub fn from_camera(camera: &CameraData) -> Self {
+        Self {
+            spacetime: Spacetime::from_camera(camera),
+            integrator: Integrator::from_u32(camera.integrator),
+            tolerance: camera.tolerance as f64,
+            disk: (camera.disk != 0).then_some(Disk {
+                inner: camera.disk_inner as f64,
+                outer: camera.disk_outer as f64,
+                temperature: camera.disk_temperature as f64,
+            }),
+        }
+    }
+
+    /// Step size of the fixed step integrators at radius `r`, negative since rays are traced back
+    /// in time.
+    pub fn fixed_step(r: f64) -> f64 {
+        -0.025 * (0.1 * r / RS).sqrt().max(1.0)
+    }
+
+    /// Mirrors `integrate`, `dt` carries the adaptive step size over between calls.
+    pub fn integrate(&self, dt: &mut f64, r: f64, x0: &mut Vec4, x1: &mut Vec4) {
+        match self.integrator {
+            Integrator::Leapfrog => {
+                *dt = Self::fixed_step(r);
+                self.spacetime.step_leapfrog(*dt, x0, x1);
+            }
+            Integrator::RungeKutta4 => {
+                *dt = Self::fixed_step(r);
+                self.spacetime.step_rk4(*dt, x0, x1);
+            }
+            Integrator::DormandPrince => {
+                // A rejected step leaves the ray where it is and only shrinks dt.
+                let (x0_next, x1_next, err) = self.spacetime.step_dopri(*dt, *x0, *x1);
+                let err = err / self.tolerance;
+                let accepted = err <= 1.0 || -*dt <= DOPRI_MIN_STEP;
+                *dt *= (0.9 * err.max(1e-6).powf(-0.2)).clamp(0.2, 5.0);
+                *dt = -(-*dt).clamp(DOPRI_MIN_STEP, 0.1 * r);
+                if accepted {
+                    *x0 = x0_next;
+                    *x1 = x1_next;
+                }
+            }
+        }
+    }
+
+    /// Mirrors `trace`: follow the ray back until it falls in, hits the disk or heads out past
+    /// 25 RS.
+    pub fn trace(&self, mut x0: Vec4, mut x1: Vec4) -> RayOutcome {
+        let spacetime = &self.spacetime;
+        let (x0_camera, x1_camera) = (x0, x1);
+        let r_stop = spacetime.r_stop();
+        let mut r = spacetime.r(x0);
+        let mut dt = Self::fixed_step(r);
+        let escaped = |x0, x1| RayOutcome::Escaped(spacetime.escape_direction(x0, x1));
+        for _ in 0..MAX_STEPS {
+            spacetime.normalize_null_geodesic(x0, &mut x1);
+            let x0_prev = x0;
+            self.integrate(&mut dt, r, &mut x0, &mut x1);
+            if let Some(disk) = &self.disk {
+                let side_prev = spacetime.equator_side(x0_prev);
+                let side = spacetime.equator_side(x0);
+                if side_prev * side <= 0.0 && side_prev != side {
+                    let f = side_prev / (side_prev - side);
+                    let crossing = add(x0_prev, scale(add(x0, scale(x0_prev, -1.0)), f));
+                    let r_crossing = spacetime.r(crossing);
+                    if (disk.inner..=disk.outer).contains(&r_crossing) {
+                        return RayOutcome::Disk {
+                            r: r_crossing,
+                            redshift: spacetime.disk_redshift(x0_camera, x1_camera, crossing, x1),
+                        };
+                    }
+                }
+            }
+            r = spacetime.r(x0);
+            if r < r_stop {
+                return RayOutcome::Captured;
+            }
+            if r > ESCAPE_RADIUS && spacetime.heading_out(x0, x1) {
+                return escaped(x0, x1);
+            }
+        }
+        escaped(x0, x1)
+    }
+
+    #[cfg(test)]
+    pub fn trace_ray(&self, position: Vec3, dir: Vec3) -> RayOutcome {
+        let (x0, x1) = self.spacetime.init_ray(position, dir);
+        self.trace(x0, x1)
+    }
+
+    /// Color the shader gives a ray with this outcome.
+    pub fn shade(&self, outcome: RayOutcome) -> [f64; 4] {
+        match (outcome, &self.disk) {
+            (RayOutcome::Escaped(dir), _) => background(dir),
+            (RayOutcome::Disk { r, redshift }, Some(disk)) => disk.color(r, redshift),
+            _ => [0.0; 4],
+        }
+    }
+}
+
+/// Mirrors `disk_temperature_profile`, the temperature relative to the inner edge at
+/// `x = r / inner`.
+pub fn disk_temperature_profile(x: f64) -> f64 {
+    x.powf(-0.75) * (1.0 - 1.0 / x.sqrt()).max(0.0).powf(0.25)
+}
+const DISK_PROFILE_MAX: f64 = 0.48818;
+
+/// Mirrors `blackbody`, the color of a black body at `temperature` Kelvin with the brightest
+/// channel at 1.
+pub fn blackbody(temperature: f64) -> Vec3 {
+    let c = [0.610, 0.550, 0.465].map(|lambda: f64| {
+        1.0 / (lambda.powi(5) * ((14388.0 / (lambda * temperature.max(500.0))).exp() - 1.0))
+    });
+    let max = c[0].max(c[1]).max(c[2]);
+    c.map(|it| it / max)
+}
+
+impl Disk {
+    /// Mirrors `disk_color`.
+    pub fn color(&self, r: f64, redshift: f64) -> [f64; 4] {
+        let temperature =
+            self.temperature * disk_temperature_profile(r / self.inner) / DISK_PROFILE_MAX;
+        let observed = redshift * temperature;
+        let intensity = (observed / self.temperature).powi(4);
+        let [r, g, b] = blackbody(observed).map(|it| 1.0 - (-2.0 * intensity * it).exp());
+        [r, g, b, 1.0]
+    }
+}
+
+/// Radius of the innermost stable circular orbit of a prograde disk around a black hole with
+/// spin `a` (Bardeen, Press & Teukolsky 1972).
+pub fn isco(a: f64) -> f64 {
+    let m = RS / 2.0;
+    let chi = (a / m).clamp(-1.0, 1.0);
+    let z1 = 1.0 + (1.0 - chi * chi).cbrt() * ((1.0 + chi).cbrt() + (1.0 - chi).cbrt());
+    let z2 = (3.0 * chi * chi + z1 * z1).sqrt();
+    m * (3.0 + z2 - chi.signum() * ((3.0 - z1) * (3.0 + z1 + 2.0 * z2)).sqrt())
+}
+
+/// Diagonal of the Schwarzschild metric.
+pub fn g_schwarzschild(x: Vec4) -> Vec4 {
+    let (r, th) = (x[1], x[2]);
+    let sin_th = th.sin();
+    [-(1.0 - RS / r), 1.0 / (1.0 - RS / r), r * r, r * r * sin_th * sin_th]
+}
+
+fn dgdx_schwarzschild(x: Vec4) -> [Vec4; 4] {
+    let (r, th) = (x[1], x[2]);
+    let (sin_th, cos_th) = th.sin_cos();
+    [
+        [0.0; 4],
+        [-RS / (r * r), -RS / sq(RS - r), 2.0 * r, 2.0 * r * sin_th * sin_th],
+        [0.0, 0.0, 0.0, 2.0 * r * r * sin_th * cos_th],
+        [0.0; 4],
+    ]
+}
+
+fn g_inv_kerr_bl(g: &Mat4) -> Mat4 {
+    let det = g[0][0] * g[3][3] - g[0][3] * g[0][3];
+    [
+        [g[3][3] / det, 0.0, 0.0, -g[0][3] / det],
+        [0.0, 1.0 / g[1][1], 0.0, 0.0],
+        [0.0, 0.0, 1.0 / g[2][2], 0.0],
+        [-g[0][3] / det, 0.0, 0.0, g[0][0] / det],
+    ]
+}
+
+/// `cs[l][i][j] = 1/2 g^lk (d(i) g[j,k] + d(j) g[i,k] - d(k) g[i,j])`
+pub fn christoffel_symbols(g_inv: &Mat4, dgdx: &[Mat4; 4]) -> [Mat4; 4] {
+    std::array::from_fn(|l| {
+        std::array::from_fn(|i| {
+            std::array::from_fn(|j| {
+                (0..4)
+                    .map(|k| 0.5 * g_inv[l][k] * (dgdx[i][j][k] + dgdx[j][i][k] - dgdx[k][i][j]))
+                    .sum()
+            })
+        })
+    })
+}
+
+pub fn geodesic_acceleration(cs: &[Mat4; 4], x1: Vec4) -> Vec4 {
+    std::array::from_fn(|l| -dot4(mat_vec(&cs[l], x1), x1))
+}
+
+/// Trace the ray through `uv` the way the shader does for an activated camera.
+pub fn trace_camera_ray(camera: &CameraData, uv: [f64; 2]) -> RayOutcome {
+    let tracer = Tracer::from_camera(camera);
+    let x0 = tracer.spacetime.event(camera.position.map(|it| it as f64));
+    let tetrad = camera.tetrad.map(|col| col.map(|it| it as f64));
+    tracer.trace(x0, ray_tangent(&tetrad, init_raydir(camera, uv)))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blackhole_gtx::camera::Quat;
+
+    fn schwarzschild() -> Spacetime {
+        Spacetime {
+            metric: Metric::Schwarzschild,
+            a: 0.0,
+        }
+    }
+
+    fn tracer(spacetime: Spacetime, integrator: Integrator) -> Tracer {
+        Tracer {
+            spacetime,
+            integrator,
+            tolerance: 1e-4,
+            disk: None,
+        }
+    }
+
+    fn with_disk(tracer: Tracer) -> Tracer {
+        Tracer {
+            disk: Some(Disk {
+                inner: isco(tracer.spacetime.a),
+                outer: 12.0 * RS,
+                temperature: 8000.0,
+            }),
+            ..tracer
+        }
+    }
+
+    /// Impact parameter `L/E` of the ray leaving `position` in direction `dir`.
+    fn impact_parameter(spacetime: &Spacetime, position: Vec3, dir: Vec3) -> f64 {
+        let (x0, mut x1) = spacetime.init_ray(position, dir);
+        spacetime.normalize_null_geodesic(x0, &mut x1);
+        let p = mat_vec(&spacetime.g(x0), x1);
+        let l_z = if spacetime.is_spherical() {
+            p[3]
+        } else {
+            x0[1] * p[2] - x0[2] * p[1]
+        };
+        (l_z / p[0]).abs()
+    }
+
+    #[test]
+    fn null_normalization() {
+        for metric in Metric::ALL {
+            let spacetime = Spacetime { metric, a: 0.3 };
+            let (x0, mut x1) = spacetime.init_ray([1.0, -4.0, 0.5], normalize3([0.2, 1.0, -0.1]));
+            spacetime.normalize_null_geodesic(x0, &mut x1);
+            let norm = dot4(mat_vec(&spacetime.g(x0), x1), x1);
+            assert!(norm.abs() < 1e-9, "{:?}: g(x1, x1) = {}", metric, norm);
+            assert!(x1[0] > 0.0);
+        }
+    }
+
+    /// Orbit a tangential photon at r = 1.5 RS for `steps` steps of -0.01, returns the final r.
+    fn photon_sphere_drift(integrator: Integrator, steps: usize) -> f64 {
+        let tracer = Tracer {
+            tolerance: 1e-8,
+            ..tracer(schwarzschild(), integrator)
+        };
+        let mut x0 = [0.0, 1.5 * RS, std::f64::consts::FRAC_PI_2, 0.0];
+        let mut x1 = [0.0, 0.0, 0.0, 1.0];
+        let mut dt = -0.01;
+        for _ in 0..steps {
+            tracer.spacetime.normalize_null_geodesic(x0, &mut x1);
+            match integrator {
+                Integrator::Leapfrog => tracer.spacetime.step_leapfrog(dt, &mut x0, &mut x1),
+                Integrator::RungeKutta4 => tracer.spacetime.step_rk4(dt, &mut x0, &mut x1),
+                Integrator::DormandPrince => tracer.integrate(&mut dt, x0[1], &mut x0, &mut x1),
+            }
+        }
+        assert!(x0[3].abs() > 0.5, "photon did not orbit, ph = {}", x0[3]);
+        (x0[1] - 1.5 * RS).abs()
+    }
+
+    #[test]
+    fn photon_sphere_orbit() {
+        // A tangential photon at r = 1.5 RS stays there (until the orbit's instability kicks in).
+        for integrator in Integrator::ALL {
+            let drift = photon_sphere_drift(integrator, 100);
+            assert!(drift < 1e-3, "{:?} drifted by {}", integrator, drift);
+        }
+    }
+
+    #[test]
+    fn rk4_beats_leapfrog() {
+        // The impact parameter L/E is conserved along a geodesic (and unaffected by the rescaling
+        // in `normalize_null_geodesic`), so its drift measures the integration error.
+        let spacetime = schwarzschild();
+        let impact_parameter = |x0: Vec4, x1: Vec4| {
+            let g = g_schwarzschild(x0);
+            (g[3] * x1[3] / (g[0] * x1[0])).abs()
+        };
+        let drift = |rk4: bool| {
+            let mut x0 = [0.0, 3.0 * RS, std::f64::consts::FRAC_PI_2, 0.0];
+            let mut x1 = [0.0, 0.3, 0.0, 1.0];
+            spacetime.normalize_null_geodesic(x0, &mut x1);
+            let b = impact_parameter(x0, x1);
+            for _ in 0..100 {
+                spacetime.normalize_null_geodesic(x0, &mut x1);
+                if rk4 {
+                    spacetime.step_rk4(-0.05, &mut x0, &mut x1);
+                } else {
+                    spacetime.step_leapfrog(-0.05, &mut x0, &mut x1);
+                }
+            }
+            spacetime.normalize_null_geodesic(x0, &mut x1);
+            (impact_parameter(x0, x1) - b).abs()
+        };
+        let (leapfrog, rk4) = (drift(false), drift(true));
+        assert!(rk4 < leapfrog / 100.0, "rk4 drift {}, leapfrog drift {}", rk4, leapfrog);
+    }
+
+    #[test]
+    fn shadow_radius() {
+        // Rays with impact parameter below sqrt(27)/2 RS are captured, above it they escape.
+        let critical = 27f64.sqrt() / 2.0 * RS;
+        let position = [0.0, -20.0 * RS, 0.0];
+        for metric in Metric::ALL {
+            let spacetime = Spacetime { metric, a: 0.0 };
+            for integrator in Integrator::ALL {
+                let tracer = tracer(spacetime, integrator);
+                for (i, angle) in (0..50).map(|i| (i, 0.1 + i as f64 * 0.004)) {
+                    let dir = [angle.sin(), angle.cos(), 0.0];
+                    let b = impact_parameter(&spacetime, position, dir);
+                    if (b / critical - 1.0).abs() < 0.02 {
+                        continue;
+                    }
+                    let captured = tracer.trace_ray(position, dir) == RayOutcome::Captured;
+                    assert_eq!(
+                        captured,
+                        b < critical,
+                        "{:?} / {:?} ray {} with b = {}",
+                        metric,
+                        integrator,
+                        i,
+                        b
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn far_rays_pass_undeflected() {
+        let tracer = tracer(schwarzschild(), Integrator::DormandPrince);
+        let position = [0.0, -20.0 * RS, 0.0];
+        let dir = normalize3([0.0, -1.0, 0.05]);
+        match tracer.trace_ray(position, dir) {
+            RayOutcome::Escaped(d) => {
+                let d = normalize3(d);
+                assert!(dot3(d, dir) > 0.999, "deflected to {:?}", d);
+            }
+            outcome => panic!("outgoing ray didn't escape: {:?}", outcome),
+        }
+    }
+
+    #[test]
+    fn kerr_metrics_agree_without_spin() {
+        let camera = CameraData {
+            dims: [64.0, 48.0],
+            jitter: [0.0; 2],
+            rotation: Quat::from_axis_angle([1.0, 0.0, 0.0], -std::f32::consts::FRAC_PI_2).0,
+            position: [0.0, -3.0, 0.0],
+            fov_y: std::f32::consts::FRAC_PI_2,
+            activated: 1,
+            a: 0.0,
+            metric: Metric::Schwarzschild as u32,
+            integrator: Integrator::Leapfrog as u32,
+            tolerance: 1e-4,
+            disk: 0,
+            disk_inner: 3.0,
+            disk_outer: 12.0,
+            disk_temperature: 8000.0,
+            skybox: 0,
+            lut_pass: 0,
+            _spacer1: [0; 1],
+            tetrad: [[0.0; 4]; 4],
+            tile: [0.0, 0.0, 64.0, 48.0],
+        };
+        let static_camera = |metric: Metric| {
+            let spacetime = Spacetime { metric, a: 0.0 };
+            let x = spacetime.event([0.0, -3.0, 0.0]);
+            let tetrad = spacetime.tetrad(x, spacetime.static_observer(x));
+            CameraData {
+                metric: metric as u32,
+                tetrad: tetrad.map(|col| col.map(|it| it as f32)),
+                ..camera
+            }
+        };
+        for pixel in [(0, 0), (10, 20), (32, 24), (60, 5), (56, 44)] {
+            let camera = static_camera(Metric::Schwarzschild);
+            let reference = trace_camera_ray(&camera, pixel_uv(&camera, pixel));
+            for metric in [Metric::KerrBoyerLindquist, Metric::KerrSchild] {
+                let camera = static_camera(metric);
+                let outcome = trace_camera_ray(&camera, pixel_uv(&camera, pixel));
+                match (reference, outcome) {
+                    (RayOutcome::Captured, RayOutcome::Captured) => {}
+                    (RayOutcome::Escaped(a), RayOutcome::Escaped(b)) => {
+                        let cos = dot3(normalize3(a), normalize3(b));
+                        assert!(cos > 0.99, "{:?} pixel {:?}: {:?} vs {:?}", metric, pixel, a, b);
+                    }
+                    _ => panic!("{:?} pixel {:?}: {:?} vs {:?}", metric, pixel, reference, outcome),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn isco_radius() {
+        let m = RS / 2.0;
+        assert!((isco(0.0) - 6.0 * m).abs() < 1e-9);
+        assert!((isco(m) - m).abs() < 1e-6, "extremal prograde: {}", isco(m));
+        assert!((isco(-m) - 9.0 * m).abs() < 1e-6, "extremal retrograde: {}", isco(-m));
+        assert!(isco(0.3) < isco(0.0) && isco(-0.3) > isco(0.0));
+    }
+
+    #[test]
+    fn face_on_disk_redshift() {
+        // Seen from above the photons carry no angular momentum, so only the gravitational and
+        // transverse Doppler shift remain: g = sqrt(1 - 3M/r) / sqrt(1 - RS/r_camera).
+        let tracer = with_disk(tracer(schwarzschild(), Integrator::DormandPrince));
+        // Slightly off the axis, where spherical coordinates are singular. The rays stay in the
+        // x-z plane so they still have no angular momentum around the z axis.
+        let position = [0.01 * RS, 0.0, 20.0 * RS];
+        for offset in [0.2, 0.3, 0.4] {
+            let dir = normalize3([offset, 0.0, -1.0]);
+            match tracer.trace_ray(position, dir) {
+                RayOutcome::Disk { r, redshift } => {
+                    let expected = (1.0 - 1.5 * RS / r).sqrt() / (1.0 - RS / 20.0).sqrt();
+                    assert!(
+                        (redshift / expected - 1.0).abs() < 1e-3,
+                        "r = {}: redshift {} instead of {}",
+                        r,
+                        redshift,
+                        expected
+                    );
+                }
+                outcome => panic!("ray at {} missed the disk: {:?}", offset, outcome),
+            }
+        }
+    }
+
+    #[test]
+    fn disk_doppler_beaming() {
+        // Seen edge-on the disk (rotating counterclockwise seen from +z) comes towards the camera
+        // on the left and moves away on the right.
+        for metric in Metric::ALL {
+            let spacetime = Spacetime { metric, a: 0.3 };
+            let tracer = with_disk(tracer(spacetime, Integrator::DormandPrince));
+            let position = [0.0, -20.0 * RS, 0.5 * RS];
+            let redshift = |x: f64| match tracer.trace_ray(position, normalize3([x, 1.0, -0.025])) {
+                RayOutcome::Disk { redshift, .. } => redshift,
+                outcome => panic!("{:?}: ray at {} missed the disk: {:?}", metric, x, outcome),
+            };
+            let (approaching, receding) = (redshift(-0.3), redshift(0.3));
+            assert!(approaching > 1.0, "{:?}: approaching side at {}", metric, approaching);
+            assert!(receding < 0.8, "{:?}: receding side at {}", metric, receding);
+        }
+    }
+}
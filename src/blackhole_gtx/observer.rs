@@ -0,0 +1,264 @@
+//! Observers the camera rides along with.
+//!
+//! The observer decides where the camera is and how it moves, its tetrad goes to the shader as
+//! `camera.tetrad` and that is where aberration comes in. Moving observers follow timelike
+//! geodesics, integrated here in proper time with the same `Spacetime` the CPU tracer uses.
+
+use super::{
+    core::Metric,
+    geodesic::{isco, Mat4, Spacetime, Vec3, Vec4, RS},
+};
+
+/// Largest proper time step, relative to the radius.
+const MAX_STEP: f64 = 0.01;
+/// Moving observers stop this far out, relative to the horizon, before the coordinates give out.
+const STOP_FACTOR: f64 = 1.05;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ObserverMode {
+    /// Hovering wherever the camera is flown to.
+    Static,
+    /// Circular geodesic orbit in the equatorial plane.
+    Orbit,
+    /// Radial free-fall, dropped from rest.
+    Infall,
+    /// Thrown along the view direction, fast enough it comes back out on a hyperbolic orbit.
+    Flyby,
+}
+impl ObserverMode {
+    pub const ALL: [ObserverMode; 4] = [Self::Static, Self::Orbit, Self::Infall, Self::Flyby];
+    pub fn next(self) -> Self {
+        let i = Self::ALL.iter().position(|it| *it == self).unwrap_or(0);
+        Self::ALL[(i + 1) % Self::ALL.len()]
+    }
+}
+impl std::str::FromStr for ObserverMode {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "static" => Ok(Self::Static),
+            "orbit" => Ok(Self::Orbit),
+            "infall" => Ok(Self::Infall),
+            "flyby" => Ok(Self::Flyby),
+            _ => Err("expected one of static, orbit, infall, flyby".to_string()),
+        }
+    }
+}
+
+/// Settings of the moving modes.
+#[derive(Debug, Clone, Copy)]
+pub struct ObserverSettings {
+    /// Radius of the circular orbit, the distance at the start if not set.
+    pub orbit_radius: Option<f64>,
+    /// Initial speed of a flyby relative to the static observer, as a fraction of c.
+    pub flyby_speed: f64,
+}
+
+pub struct Observer {
+    pub mode: ObserverMode,
+    spacetime: Spacetime,
+    x: Vec4,
+    /// 4-velocity, `None` for a static observer inside the horizon where there is no such thing.
+    u: Option<Vec4>,
+    start_azimuth: f64,
+    /// Whether it came too close to the horizon and stopped.
+    stopped: bool,
+}
+
+impl Observer {
+    /// Static observer at the rectilinear `position`.
+    pub fn fixed(spacetime: Spacetime, position: Vec3) -> Self {
+        let x = spacetime.event(position);
+        Self {
+            mode: ObserverMode::Static,
+            spacetime,
+            x,
+            u: spacetime.static_observer(x),
+            start_azimuth: position[1].atan2(position[0]),
+            stopped: false,
+        }
+    }
+
+    /// Start an observer in `mode` at the rectilinear `position`, looking along `forward`.
+    pub fn new(
+        mode: ObserverMode,
+        spacetime: Spacetime,
+        position: Vec3,
+        forward: Vec3,
+        settings: &ObserverSettings,
+    ) -> anyhow::Result<Self> {
+        let fixed = Self::fixed(spacetime, position);
+        if mode != ObserverMode::Static && fixed.u.is_none() {
+            anyhow::bail!("a {:?} observer can't start inside the horizon", mode);
+        }
+        let mut this = Self { mode, ..fixed };
+        match mode {
+            ObserverMode::Static | ObserverMode::Infall => {}
+            ObserverMode::Orbit => this.start_orbit(settings.orbit_radius)?,
+            ObserverMode::Flyby => this.start_flyby(forward, settings.flyby_speed)?,
+        }
+        Ok(this)
+    }
+
+    fn start_orbit(&mut self, radius: Option<f64>) -> anyhow::Result<()> {
+        let spacetime = &self.spacetime;
+        let radius = radius.unwrap_or_else(|| spacetime.r(self.x));
+        let a = if spacetime.metric == Metric::Schwarzschild { 0.0 } else { spacetime.a };
+        let phi = self.start_azimuth;
+        self.x = if spacetime.is_spherical() {
+            [0.0, radius, std::f64::consts::FRAC_PI_2, phi]
+        } else {
+            // Kerr-Schild's r is the Boyer-Lindquist one, in the equator it sits at sqrt(r^2 + a^2).
+            let rho = (radius * radius + a * a).sqrt();
+            [0.0, rho * phi.cos(), rho * phi.sin(), 0.0]
+        };
+
+        let m = RS / 2.0;
+        let omega = m.sqrt() / (radius.powf(1.5) + a * m.sqrt());
+        let u = [1.0, 0.0, 0.0, 0.0];
+        let d_ph = spacetime.d_ph(self.x);
+        let u = std::array::from_fn(|i| u[i] + omega * d_ph[i]);
+        if spacetime.dot(self.x, u, u) >= 0.0 {
+            anyhow::bail!("there is no circular orbit at r = {:.2}, it's inside the photon orbit", radius);
+        }
+        if radius < isco(a) {
+            log::warn!("r = {:.2} is inside the ISCO at {:.2}, the orbit is unstable", radius, isco(a));
+        }
+        self.u = Some(spacetime.normalize_timelike(self.x, u));
+        Ok(())
+    }
+
+    fn start_flyby(&mut self, forward: Vec3, speed: f64) -> anyhow::Result<()> {
+        if !(0.0..1.0).contains(&speed) {
+            anyhow::bail!("the flyby speed must be within [0, 1), got {}", speed);
+        }
+        let tetrad = self.tetrad();
+        let gamma = 1.0 / (1.0 - speed * speed).sqrt();
+        let u = std::array::from_fn(|i| {
+            gamma * (tetrad[0][i] + speed * (0..3).map(|k| forward[k] * tetrad[k + 1][i]).sum::<f64>())
+        });
+        // Energy per unit mass, at most 1 means bound.
+        let energy = -self.spacetime.dot(self.x, u, [1.0, 0.0, 0.0, 0.0]);
+        if energy <= 1.0 {
+            log::warn!("Flyby at {} c is too slow to escape again (E = {:.3})", speed, energy);
+        }
+        self.u = Some(u);
+        Ok(())
+    }
+
+    /// Rectilinear position, for `camera.position`.
+    pub fn position(&self) -> Vec3 {
+        self.spacetime.position(self.x)
+    }
+
+    /// For `camera.tetrad`.
+    pub fn tetrad(&self) -> Mat4 {
+        self.spacetime.tetrad(self.x, self.u)
+    }
+
+    /// How far it went around the z axis since the start. The camera turns along, so an orbiting
+    /// camera keeps facing the same way relative to the hole.
+    pub fn azimuth_change(&self) -> f64 {
+        let [x, y, _] = self.position();
+        y.atan2(x) - self.start_azimuth
+    }
+
+    /// Velocity relative to the static observer, in its radial, polar and azimuthal legs.
+    pub fn local_velocity(&self) -> Vec3 {
+        let (Some(u), Some(u_static)) = (self.u, self.spacetime.static_observer(self.x)) else {
+            return [0.0; 3];
+        };
+        let frame = self.spacetime.local_frame(self.x, Some(u_static));
+        let gamma = -self.spacetime.dot(self.x, u, u_static);
+        std::array::from_fn(|i| self.spacetime.dot(self.x, u, frame[i + 1]) / gamma)
+    }
+
+    /// Follow the geodesic for `dtau` of proper time. Static observers stay put.
+    pub fn advance(&mut self, dtau: f64) {
+        let Some(mut u) = self.u else {
+            return;
+        };
+        if self.mode == ObserverMode::Static || self.stopped || dtau == 0.0 {
+            return;
+        }
+        let spacetime = &self.spacetime;
+        let steps = (dtau.abs() / (MAX_STEP * spacetime.r(self.x))).ceil() as usize;
+        let h = dtau / steps as f64;
+        for _ in 0..steps {
+            spacetime.step_rk4(h, &mut self.x, &mut u);
+            u = spacetime.normalize_timelike(self.x, u);
+            if spacetime.r(self.x) < STOP_FACTOR * spacetime.r_horizon() {
+                log::info!("The observer reached the horizon and stopped");
+                self.stopped = true;
+                break;
+            }
+        }
+        self.u = Some(u);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn circular_orbit_keeps_its_radius() {
+        let settings = ObserverSettings {
+            orbit_radius: Some(4.0 * RS),
+            flyby_speed: 0.0,
+        };
+        for (metric, a) in [(Metric::Schwarzschild, 0.0), (Metric::KerrBoyerLindquist, 0.4), (Metric::KerrSchild, 0.4)] {
+            let spacetime = Spacetime { metric, a };
+            let mut observer =
+                Observer::new(ObserverMode::Orbit, spacetime, [0.0, -3.0, 0.0], [0.0, 1.0, 0.0], &settings).unwrap();
+            let velocity = observer.local_velocity();
+            for _ in 0..100 {
+                observer.advance(1.0);
+            }
+            let r = spacetime.r(spacetime.event(observer.position()));
+            assert!((r / (4.0 * RS) - 1.0).abs() < 1e-3, "{:?} drifted to r = {}", metric, r);
+            let drift = std::array::from_fn::<_, 3, _>(|i| observer.local_velocity()[i] - velocity[i]);
+            assert!(drift.iter().all(|it| it.abs() < 1e-3), "{:?} velocity drifted by {:?}", metric, drift);
+        }
+    }
+
+    #[test]
+    fn tetrad_is_orthonormal() {
+        let eta = [-1.0, 1.0, 1.0, 1.0];
+        let settings = ObserverSettings {
+            orbit_radius: Some(3.0 * RS),
+            flyby_speed: 0.7,
+        };
+        for metric in Metric::ALL {
+            let spacetime = Spacetime { metric, a: 0.45 };
+            for mode in ObserverMode::ALL {
+                // Inside the ergosphere at the equator, where the static observer has to rotate.
+                for position in [[1.0, -4.0, 0.5], [0.0, -0.98, 0.0]] {
+                    if metric == Metric::Schwarzschild && position[1] > -RS {
+                        // Inside the horizon there.
+                        continue;
+                    }
+                    let observer = Observer::new(mode, spacetime, position, [0.6, 0.8, 0.0], &settings).unwrap();
+                    let tetrad = observer.tetrad();
+                    let x = spacetime.event(observer.position());
+                    for i in 0..4 {
+                        for j in 0..4 {
+                            let expected = if i == j { eta[i] } else { 0.0 };
+                            let dot = spacetime.dot(x, tetrad[i], tetrad[j]);
+                            assert!(
+                                (dot - expected).abs() < 1e-9,
+                                "{:?} / {:?} at {:?}: g(e{}, e{}) = {}",
+                                metric,
+                                mode,
+                                position,
+                                i,
+                                j,
+                                dot
+                            );
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
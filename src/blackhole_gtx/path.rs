@@ -0,0 +1,160 @@
+//! Keyframed camera paths for `BlackholeGtx`, see `--path`.
+//!
+//! A path is a script in any of the formats of [`keyframes::load`], in JSON like
+//!
+//! ```json
+//! {
+//!     "interpolation": "catmull-rom",
+//!     "keyframes": [
+//!         { "time": 0, "position": [0, -15, 2], "look_at": [0, 0, 0], "metric": "kerr-bl", "a": 0 },
+//!         { "time": 8, "position": [12, -8, 1], "a": 0.45, "disk": true }
+//!     ]
+//! }
+//! ```
+//!
+//! Keyframes set any of the `CameraData` values in [`Keyframe`], with the same names and units.
+//! Orientations are either given as `rotation` quaternions or as a point to `look_at`.
+
+use serde::Deserialize;
+
+use super::{
+    camera::Quat,
+    core::{Integrator, Metric},
+};
+use crate::keyframes::{self, Interpolation, Track};
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Keyframe {
+    /// In seconds from the start.
+    time: f32,
+    position: Option<[f32; 3]>,
+    /// Quaternion `[x, y, z, w]`, see `camera::Quat`.
+    rotation: Option<[f32; 4]>,
+    /// Point to look at, with the scene's z axis up.
+    look_at: Option<[f32; 3]>,
+    /// In radians.
+    fov_y: Option<f32>,
+    a: Option<f32>,
+    metric: Option<Metric>,
+    integrator: Option<Integrator>,
+    tolerance: Option<f32>,
+    disk: Option<bool>,
+    disk_inner: Option<f32>,
+    disk_outer: Option<f32>,
+    disk_temperature: Option<f32>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PathFile {
+    #[serde(default)]
+    interpolation: Interpolation,
+    /// Start over after the last keyframe.
+    #[serde(default, rename = "loop")]
+    looped: bool,
+    keyframes: Vec<Keyframe>,
+}
+
+/// The camera at one point of a path, `None` for what the path leaves alone.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PathSample {
+    pub position: Option<[f32; 3]>,
+    pub rotation: Option<Quat>,
+    pub fov_y: Option<f32>,
+    pub a: Option<f32>,
+    pub metric: Option<Metric>,
+    pub integrator: Option<Integrator>,
+    pub tolerance: Option<f32>,
+    pub disk: Option<bool>,
+    pub disk_inner: Option<f32>,
+    pub disk_outer: Option<f32>,
+    pub disk_temperature: Option<f32>,
+}
+
+pub struct CameraPath {
+    interpolation: Interpolation,
+    looped: bool,
+    duration: f32,
+    position: Track<[f32; 3]>,
+    rotation: Track<Quat>,
+    look_at: Track<[f32; 3]>,
+    fov_y: Track<f32>,
+    a: Track<f32>,
+    metric: Track<Metric>,
+    integrator: Track<Integrator>,
+    tolerance: Track<f32>,
+    disk: Track<bool>,
+    disk_inner: Track<f32>,
+    disk_outer: Track<f32>,
+    disk_temperature: Track<f32>,
+}
+
+impl CameraPath {
+    pub fn load(path: &str) -> anyhow::Result<Self> {
+        let file: PathFile = keyframes::load(path)?;
+        let k = &file.keyframes;
+        if k.is_empty() {
+            anyhow::bail!("{} has no keyframes", path);
+        }
+        let time = |k: &Keyframe| k.time;
+        let this = Self {
+            interpolation: file.interpolation,
+            looped: file.looped,
+            duration: k.iter().map(time).fold(0.0, f32::max),
+            position: Track::new(k, time, |k| k.position),
+            rotation: Track::new(k, time, |k| k.rotation.map(|it| Quat(it).normalize())),
+            look_at: Track::new(k, time, |k| k.look_at),
+            fov_y: Track::new(k, time, |k| k.fov_y),
+            a: Track::new(k, time, |k| k.a),
+            metric: Track::new(k, time, |k| k.metric),
+            integrator: Track::new(k, time, |k| k.integrator),
+            tolerance: Track::new(k, time, |k| k.tolerance),
+            disk: Track::new(k, time, |k| k.disk),
+            disk_inner: Track::new(k, time, |k| k.disk_inner),
+            disk_outer: Track::new(k, time, |k| k.disk_outer),
+            disk_temperature: Track::new(k, time, |k| k.disk_temperature),
+        };
+        if !this.rotation.is_empty() && !this.look_at.is_empty() {
+            anyhow::bail!("{} mixes `rotation` and `look_at` keyframes, use one or the other", path);
+        }
+        Ok(this)
+    }
+
+    /// Time of the last keyframe.
+    pub fn duration(&self) -> f32 {
+        self.duration
+    }
+
+    /// The camera `t` seconds into the path. `position` is where the camera is, for looking at a
+    /// point from a path that doesn't move.
+    pub fn sample(&self, t: f32, position: [f32; 3]) -> PathSample {
+        let t = if self.looped && self.duration > 0.0 { t % self.duration } else { t };
+        let interpolation = self.interpolation;
+        let sample_position = self.position.sample(t, interpolation);
+        let rotation = if self.look_at.is_empty() {
+            match interpolation {
+                Interpolation::Step => self.rotation.step(t),
+                _ => self.rotation.sample_with(t, Quat::slerp),
+            }
+        } else {
+            let position = sample_position.unwrap_or(position);
+            self.look_at
+                .sample(t, interpolation)
+                .map(|target| Quat::look_along(std::array::from_fn(|i| target[i] - position[i])))
+        };
+        PathSample {
+            position: sample_position,
+            rotation,
+            fov_y: self.fov_y.sample(t, interpolation),
+            a: self.a.sample(t, interpolation),
+            metric: self.metric.step(t),
+            integrator: self.integrator.step(t),
+            tolerance: self.tolerance.sample(t, interpolation),
+            disk: self.disk.step(t),
+            disk_inner: self.disk_inner.sample(t, interpolation),
+            disk_outer: self.disk_outer.sample(t, interpolation),
+            disk_temperature: self.disk_temperature.sample(t, interpolation),
+        }
+    }
+}
@@ -0,0 +1,33 @@
+#version 450 core
+
+layout(location = 0) in vec2 uv;
+// Running average of the samples so far, read back as `previous` next frame.
+layout(location = 0) out vec4 average;
+layout(location = 1) out vec4 FragColor;
+
+//INJECT// layout(set = 0, binding = 0) uniform texture2D new_sample;
+//INJECT// layout(set = 0, binding = 1) uniform texture2D previous;
+//INJECT// layout(set = 0, binding = 2) uniform sampler nearest;
+
+layout (set = 0, binding = 3) uniform AccumulateData {
+    // 1/n for the n-th sample, 0 once there are enough.
+    float weight;
+} accumulation;
+
+vec4 fetch_sample(ivec2 p) {
+    return vec4(0, 0, 0, 0); //REPLACE// return texelFetch(sampler2D(new_sample, nearest), p, 0);
+}
+vec4 fetch_previous(ivec2 p) {
+    return vec4(0, 0, 0, 0); //REPLACE// return texelFetch(sampler2D(previous, nearest), p, 0);
+}
+
+void main() {
+    ivec2 p = ivec2(gl_FragCoord.xy);
+    // The first sample replaces whatever was there before the reset.
+    if (accumulation.weight >= 1) {
+        average = fetch_sample(p);
+    } else {
+        average = mix(fetch_previous(p), fetch_sample(p), accumulation.weight);
+    }
+    FragColor = average;
+}
@@ -5,15 +5,53 @@ layout(location = 0) out vec4 FragColor;
 
 layout (set = 0, binding = 0) uniform UniformBufferObject {
     vec2 view_dim;
-    vec2 rotation;
+    vec2 jitter; // in pixels, x right and y down
+    vec4 rotation; // quaternion, camera space to the scene
     vec3 position;
     float fov_y;
     int activated;
     float a;
+    int metric;
+    int integrator;
+    float tolerance;
+    int disk;
+    float disk_inner;
+    float disk_outer;
+    float disk_temperature;
+    int skybox;
+    int lut_pass;
+    // Observer's 4-velocity followed by the legs along x, y and z, in the metric's coordinates.
+    mat4 tetrad;
+    vec4 tile; // offset and size of the part of the image the view shows, in pixels
 } camera;
 
+//INJECT// layout(set = 1, binding = 0) uniform texture2D skybox_equirect;
+//INJECT// layout(set = 1, binding = 1) uniform textureCube skybox_cube;
+//INJECT// layout(set = 1, binding = 2) uniform sampler skybox_sampler;
+
+const int SKYBOX_GRID = 0;
+const int SKYBOX_EQUIRECT = 1;
+const int SKYBOX_CUBE = 2;
+
+const float PI = 3.14159265;
+
+// The scene is z-up. Longitude runs right to left across the equirectangular image, as seen from
+// the inside, and the cube map's +Y face is up.
+vec4 sample_equirect(vec3 d) {
+    vec2 coord = vec2(0.5 - atan(d.y, d.x) / (2 * PI), acos(clamp(d.z, -1, 1)) / PI);
+    return vec4(0, 0, 0, 0); //REPLACE// return textureLod(sampler2D(skybox_equirect, skybox_sampler), coord, 0);
+}
+vec4 sample_cube(vec3 d) {
+    return vec4(0, 0, 0, 0); //REPLACE// return textureLod(samplerCube(skybox_cube, skybox_sampler), d.xzy, 0);
+}
+
 vec4 background(vec3 dir) {
     vec3 d = normalize(dir);
+    if (camera.skybox == SKYBOX_EQUIRECT) {
+        return vec4(sample_equirect(d).rgb, 1.0);
+    } else if (camera.skybox == SKYBOX_CUBE) {
+        return vec4(sample_cube(d).rgb, 1.0);
+    }
     float grid = 0;
     if ((mod(d.x, 0.1) > 0.05 == mod(d.y, 0.1) > 0.05) == mod(d.z, 0.1) > 0.05) {
         grid = 1;
@@ -26,43 +64,39 @@ vec4 background(vec3 dir) {
     );
 }
 
-mat3 rotateX(float t) {
-    float s = sin(t);
-    float c = cos(t);
-    return mat3(
-        1, 0, 0,
-        0, c, -s,
-        0, s, c
-    );
-}
-mat3 rotateY(float t) {
-    float s = sin(t);
-    float c = cos(t);
-    return mat3(
-        c, 0, s,
-        0, 1, 0,
-        -s, 0, c
-    );
-}
-mat3 rotateZ(float t) {
-    float s = sin(t);
-    float c = cos(t);
-    return mat3(
-        c, -s, 0,
-        s, c, 0,
-        0, 0, 1
-    );
+// Rotate v by the unit quaternion q = (xyz, w).
+vec3 quat_rotate(vec4 q, vec3 v) {
+    vec3 t = 2 * cross(q.xyz, v);
+    return v + q.w * t + cross(q.xyz, t);
 }
 
 vec3 init_raydir() {
+    // Pixel in the whole image, with y down.
+    vec2 pixel = camera.tile.xy + vec2(uv.x + 1, 1 - uv.y) / 2 * camera.tile.zw + camera.jitter;
+    vec2 st = vec2(2, -2) * pixel / camera.view_dim + vec2(-1, 1);
     vec3 p = normalize(vec3(
-        uv.x * camera.view_dim.x / camera.view_dim.y,
-        -uv.y,
+        st.x * camera.view_dim.x / camera.view_dim.y,
+        -st.y,
         1 / tan(camera.fov_y / 2)
     ));
-    p = rotateX(camera.rotation[1]) * p; // pitch
-    p = rotateZ(camera.rotation[0]) * p; // yaw
-    return p;
+    return quat_rotate(camera.rotation, p);
+}
+
+// With camera.lut_pass set, main renders the lookup table of draw-presolved-geodesics.frag instead
+// of the screen. Texel (alpha, beta) holds the ray leaving at angle alpha from the direction towards
+// the hole, turned by beta around it. The frame only depends on the position and the spin axis, so
+// turning the camera doesn't invalidate the table.
+mat3 lut_frame(vec3 p) {
+    vec3 n = -normalize(p);
+    vec3 up = abs(n.z) > 0.999 ? vec3(1, 0, 0) : vec3(0, 0, 1);
+    vec3 e1 = normalize(up - dot(up, n) * n);
+    return mat3(n, e1, cross(n, e1));
+}
+vec3 lut_raydir() {
+    // Rows run top to bottom, so beta follows the texture coordinate rather than uv.
+    float alpha = (0.5 + 0.5 * uv.x) * PI;
+    float beta = (0.5 - 0.5 * uv.y) * 2 * PI;
+    return lut_frame(camera.position) * vec3(cos(alpha), sin(alpha) * cos(beta), sin(alpha) * sin(beta));
 }
 
 ///////// ----- COORDINATE UTILITIES ----- /////////
@@ -183,187 +217,67 @@ mat4[4] christoffelsymbols_shwarzschild(vec4 x) {
 
     return cs;
 }
-void step_geodesic_schwarzschild(float dt, inout vec4 x0, inout vec4 x1) {
-    x0 += dt * x1 * 0.5;
-    mat4[4] cs = christoffelsymbols_shwarzschild(x0);
-    vec4 x2 = -vec4(
-        dot(cs[0]*x1, x1),
-        dot(cs[1]*x1, x1),
-        dot(cs[2]*x1, x1),
-        dot(cs[3]*x1, x1)
-    );
-    x1 += dt * x2;
-    x0 += dt * x1 * 0.5;
-    // x0 += dt * x1;
-}
 void normalize_null_geodesic_schwarzschild(vec4 x0, inout vec4 x1) {
     vec4 g = g_schwarzschild(x0);
 
     x1.yzw = normalize(x1.yzw);
     x1[0] = sqrt(abs((dot(x1, g*x1) - g[0]*x1[0]*x1[0]) / (-g[0])));
 }
-bool trace_schwarzschild(inout vec4 x0, inout vec4 x1) {
-    for (int i = 0; i < 1000; i++) {
-        // float sin_th = sin(x0[2]);
-        // float dt = min(0.01,-0.1/(1-2*x0[1])) * sin_th*sin_th;
-        float dt = -0.025 * max(1.0, pow(0.1 * x0[1]/RS, 0.5) );
-        normalize_null_geodesic_schwarzschild(x0, x1);
-        step_geodesic_schwarzschild(dt, x0, x1);
-        if (x0[1] < RS * 1.03) {
-            return false;
-        }
-        if (x0[1] > 25*RS && x0[1]*-x1[1] > 0) {
-            return true;
+
+//// KERR
+
+const int METRIC_SCHWARZSCHILD = 0;
+const int METRIC_KERR_BL = 1;
+const int METRIC_KERR_KS = 2;
+
+float r_horizon_kerr() {
+    return (RS + sqrt(max(0, sq(RS) - 4*sq(camera.a)))) / 2;
+}
+
+// Christoffel symbols of the second kind from the inverse metric and the metric derivatives:
+// cs[l][i][j] = 1/2 g^lk (d(i) g[j,k] + d(j) g[i,k] - d(k) g[i,j])
+mat4[4] christoffelsymbols(mat4 g_inv, mat4[4] dgdx) {
+    mat4[4] cs;
+    for (int l = 0; l < 4; l++) {
+        mat4 m = mat4(
+            0, 0, 0, 0,
+            0, 0, 0, 0,
+            0, 0, 0, 0,
+            0, 0, 0, 0
+        );
+        for (int k = 0; k < 4; k++) {
+            float g_inv_lk = g_inv[l][k];
+            for (int i = 0; i < 4; i++) {
+                for (int j = 0; j < 4; j++) {
+                    m[i][j] += 0.5 * g_inv_lk * (dgdx[i][j][k] + dgdx[j][i][k] - dgdx[k][i][j]);
+                }
+            }
         }
+        cs[l] = m;
     }
-    return true;
+    return cs;
+}
+vec4 geodesic_acceleration(mat4[4] cs, vec4 x1) {
+    return -vec4(
+        dot(cs[0]*x1, x1),
+        dot(cs[1]*x1, x1),
+        dot(cs[2]*x1, x1),
+        dot(cs[3]*x1, x1)
+    );
+}
+// Solve g(x1,x1) = 0 for x1[0], picking the future-directed root. Only valid where g[0][0] < 0
+// (outside the ergosphere), elsewhere x1 is left untouched.
+void normalize_null_geodesic(mat4 g, inout vec4 x1) {
+    float A = g[0][0];
+    if (A >= 0) {
+        return;
+    }
+    float B = 2 * dot(g[0].yzw, x1.yzw);
+    float C = dot(mat3(g[1].yzw,g[2].yzw,g[3].yzw) * x1.yzw, x1.yzw);
+    x1[0] = (-B - sqrt(max(0, B*B - 4*A*C)))/(2*A);
 }
 
-//// KERR
-
-// mat4 g_kerr_ks(vec4 p) {
-//     float x = p[1], y = p[2], z = p[3];
-//     // float x = -sq(p[1]), y = -sq(p[2]), z = -sq(p[3]);
-
-//     float
-//         xx = x*x,
-//         yy = y*y,
-//         zz = z*z,
-//         RR = dot(p.yzw,p.yzw),
-//         aa = a * a;
-
-//     float r = sqrt(
-//         RR - aa + sqrt(
-//             RR*RR + aa*aa - 2*aa*(xx + yy - zz)
-//         )
-//     ) / sqrt(2), rr = r*r;
-    
-//     // float f = RS * r*rr / (rr*rr + aa*zz);
-
-//     // vec4 k = vec4(
-//     //     1,
-//     //     (r * x + a * y) / (rr + aa),
-//     //     (r * y - a * x) / (rr + aa),
-//     //     z / r
-//     // );
-
-//     // return mat4(
-        
-//     // );
-
-    
-//     // // force a = 0
-//     // float r = length(vec3(x,y,z));
-//     // float f = RS / r;
-//     // vec4 k = vec4(0, vec3(x,y,z) / r);
-//     // // vec4 k = vec4(1, vec3(1,1,1));
-
-//     // return g_minkowsky - f * outerProduct(k, k);
-//     float A = 1-RS/r;
-
-//     // float k = sq(1/A);
-//     float k = 1/A;
-
-//     return mat4(
-//         - A, 0, 0, 0,
-//         0, k, 0 , 0,
-//         0, 0, k, 0,
-//         0, 0,0, k
-//     );
-// }
-// mat4[4] dgdx_kerr_ks(vec4 p) {
-//     const float DX = 1.52587891E-5;
-//     mat4[4] dgdx;
-//     dgdx[0] = mat4(
-//         vec4(0, 0, 0, 0),
-//         vec4(0, 0, 0, 0),
-//         vec4(0, 0, 0, 0),
-//         vec4(0, 0, 0, 0)
-//     );
-//     dgdx[1] = (g_kerr_ks(p + vec4(0,DX,0,0)) - g_kerr_ks(p - vec4(0,DX,0,0))) / (2 * DX);
-//     dgdx[2] = (g_kerr_ks(p + vec4(0,0,DX,0)) - g_kerr_ks(p - vec4(0,0,DX,0))) / (2 * DX);
-//     dgdx[3] = (g_kerr_ks(p + vec4(0,0,0,DX)) - g_kerr_ks(p - vec4(0,0,0,DX))) / (2 * DX);
-//     return dgdx;
-// }
-// mat4[4] christoffelsymbols_kerr_ks(vec4 x) {
-//     mat4[4] cs;
-
-//     mat4 g = g_kerr_ks(x);
-//     mat4 g_inv = inverse(g);
-//     mat4[4] dgdx = dgdx_kerr_ks(x);
-
-//     for (int l = 0; l < 4; l++) {
-//         mat4 m = mat4(
-//             0, 0, 0, 0,
-//             0, 0, 0, 0,
-//             0, 0, 0, 0,
-//             0, 0, 0, 0
-//         );
-//         for (int k = 0; k < 4; k++) {
-//             mat4 m_inner = mat4(
-//                 0, 0, 0, 0,
-//                 0, 0, 0, 0,
-//                 0, 0, 0, 0,
-//                 0, 0, 0, 0
-//             );
-//             for (int i = 0; i < 4; i++) {
-//                 for (int j = 0; j < 4; j++) {
-//                     // + d(i) g[j,k]
-//                     m_inner[i][k] += dgdx[i] [j][k];
-//                     // + d(j) g[i,k]
-//                     m_inner[k][j] += dgdx[j] [i][k];
-//                     // - d(k) g[i,j]
-//                     m_inner[i][j] -= dgdx[k] [i][j]; // correct
-//                 }
-//             }
-
-//             m += m_inner / 2 * g_inv[k][l];
-//         }
-//         cs[l] = m;
-//     }
-
-//     return cs;
-// }
-// void step_geodesic_kerr_ks(float dt, inout vec4 x0, inout vec4 x1) {
-//     x0 += dt * x1 * 0.5;
-//     mat4[4] cs = christoffelsymbols_kerr_ks(x0);
-//     vec4 x2 = -vec4(
-//         dot(cs[0]*x1, x1),
-//         dot(cs[1]*x1, x1),
-//         dot(cs[2]*x1, x1),
-//         dot(cs[3]*x1, x1)
-//     );
-//     x1 += dt * x2;
-//     x0 += dt * x1 * 0.5;
-//     // x0 += dt * x1;
-// }
-
-// void normalize_null_geodesic_kerr_ks(vec4 x0, inout vec4 x1) {
-//     mat4 g = g_kerr_ks(x0);
-
-//     // x1.yzw = normalize(x1.yzw);
-//     float A = g[0][0];
-//     float B = 2 * dot(g[0].yzw, x1.yzw);
-//     float C = dot(mat3(g[1].yzw,g[2].yzw,g[3].yzw) * x1.yzw, x1.yzw);
-//     x1[0] = (-B - sqrt(B*B - 4*A*C))/(2*A); // -sqrt in quadratic formula selects for the time-reversed version.
-// }
-
-// bool trace_kerr_ks(inout vec4 x0, inout vec4 x1) {
-//     for (int i = 0; i < 1000; i++) {
-//         float dt = 0.05;
-//         normalize_null_geodesic_kerr_ks(x0, x1);
-//         step_geodesic_kerr_ks(dt, x0, x1);
-
-//         float r = length(x0.yzw);
-//         if (r < RS) {
-//             return false;
-//         }
-//         // if (r > 25*RS) {
-//         //     return true;
-//         // }
-//     }
-//     return true;
-// }
+/// KERR (Boyer-Lindquist)
 
 mat4 g_kerr_bl(vec4 p) {
     float
@@ -372,154 +286,462 @@ mat4 g_kerr_bl(vec4 p) {
         th = p[2],
         cos_th = cos(th),
         sin_th = sin(th),
-        ph = p[3],
         sigma = r*r + a*a*cos_th*cos_th,
         delta = r*r-RS*r+a*a;
-    
-    const float C = 1;
 
     float
-        t_t = -(1-RS*r/sigma)*C*C,
+        t_t = -(1-RS*r/sigma),
         r_r = sigma/delta,
         th_th = sigma,
         ph_ph = (r*r+a*a+RS*r*a*a/sigma*sin_th*sin_th)*sin_th*sin_th,
-        t_ph = -2*C*RS*r*a*sin_th*sin_th/sigma;
+        t_ph = -RS*r*a*sin_th*sin_th/sigma;
 
     return mat4(
       t_t, 0, 0, t_ph,
       0, r_r, 0, 0,
       0, 0, th_th, 0,
-      t_ph, 0, 0, ph_ph 
+      t_ph, 0, 0, ph_ph
+    );
+}
+mat4 g_inv_kerr_bl(mat4 g) {
+    // (t,ph) block inverted by hand, the rest is diagonal.
+    float det = g[0][0]*g[3][3] - g[0][3]*g[0][3];
+    return mat4(
+        g[3][3]/det, 0, 0, -g[0][3]/det,
+        0, 1/g[1][1], 0, 0,
+        0, 0, 1/g[2][2], 0,
+        -g[0][3]/det, 0, 0, g[0][0]/det
     );
 }
 mat4[4] dgdx_kerr_bl(vec4 p) {
-    const float DX = 1.52587891E-5;
+    float
+        a = camera.a,
+        r = p[1],
+        th = p[2],
+        c = cos(th),
+        s = sin(th),
+        ss = s*s,
+        sigma = r*r + a*a*c*c,
+        delta = r*r-RS*r+a*a,
+        dsigma_dr = 2*r,
+        dsigma_dth = -2*a*a*c*s,
+        ddelta_dr = 2*r-RS;
+
+    float
+        t_t_r = RS*(sigma - r*dsigma_dr)/sq(sigma),
+        t_ph_r = -RS*a*ss*(sigma - r*dsigma_dr)/sq(sigma),
+        r_r_r = (dsigma_dr*delta - sigma*ddelta_dr)/sq(delta),
+        th_th_r = dsigma_dr,
+        ph_ph_r = (2*r + RS*a*a*ss*(sigma - r*dsigma_dr)/sq(sigma))*ss;
+
+    float
+        f = r*r + a*a + RS*r*a*a*ss/sigma,
+        df_dth = RS*r*a*a*(2*s*c*sigma - ss*dsigma_dth)/sq(sigma),
+        t_t_th = -RS*r*dsigma_dth/sq(sigma),
+        t_ph_th = -RS*r*a*(2*s*c*sigma - ss*dsigma_dth)/sq(sigma),
+        r_r_th = dsigma_dth/delta,
+        th_th_th = dsigma_dth,
+        ph_ph_th = df_dth*ss + f*2*s*c;
+
     mat4[4] dgdx;
     dgdx[0] = mat4(
-        vec4(0, 0, 0, 0),
-        vec4(0, 0, 0, 0),
-        vec4(0, 0, 0, 0),
-        vec4(0, 0, 0, 0)
+        0, 0, 0, 0,
+        0, 0, 0, 0,
+        0, 0, 0, 0,
+        0, 0, 0, 0
+    );
+    dgdx[1] = mat4(
+        t_t_r, 0, 0, t_ph_r,
+        0, r_r_r, 0, 0,
+        0, 0, th_th_r, 0,
+        t_ph_r, 0, 0, ph_ph_r
+    );
+    dgdx[2] = mat4(
+        t_t_th, 0, 0, t_ph_th,
+        0, r_r_th, 0, 0,
+        0, 0, th_th_th, 0,
+        t_ph_th, 0, 0, ph_ph_th
     );
-    dgdx[1] = (g_kerr_bl(p + vec4(0,DX,0,0)) - g_kerr_bl(p - vec4(0,DX,0,0))) / (2 * DX);
-    dgdx[2] = (g_kerr_bl(p + vec4(0,0,DX,0)) - g_kerr_bl(p - vec4(0,0,DX,0))) / (2 * DX);
-    dgdx[3] = (g_kerr_bl(p + vec4(0,0,0,DX)) - g_kerr_bl(p - vec4(0,0,0,DX))) / (2 * DX);
+    dgdx[3] = dgdx[0];
     return dgdx;
 }
-mat4[4] christoffelsymbols_kerr_bl(vec4 x) {
-    mat4[4] cs;
 
-    mat4 g = g_kerr_bl(x);
-    mat4 g_inv = inverse(g);
-    mat4[4] dgdx = dgdx_kerr_bl(x);
+/// KERR (Cartesian Kerr-Schild)
+// g = minkowski + f * k (x) k, which has an exact inverse g^-1 = minkowski - f * k^ (x) k^,
+// with k^ = (-k[0], k[1], k[2], k[3]).
+// This is the outgoing form (the usual ingoing one with t -> -t, a -> -a). Rays are traced back
+// in time, and only in these coordinates can a past-directed ray actually cross the horizon
+// instead of creeping up to it forever.
 
-    for (int l = 0; l < 4; l++) {
-        mat4 m = mat4(
-            0, 0, 0, 0,
-            0, 0, 0, 0,
-            0, 0, 0, 0,
-            0, 0, 0, 0
-        );
-        for (int k = 0; k < 4; k++) {
-            mat4 m_inner = mat4(
-                0, 0, 0, 0,
-                0, 0, 0, 0,
-                0, 0, 0, 0,
-                0, 0, 0, 0
-            );
-            for (int i = 0; i < 4; i++) {
-                for (int j = 0; j < 4; j++) {
-                    // + d(i) g[j,k]
-                    m_inner[k][i] += dgdx[i] [j][k];
-                    // + d(j) g[i,k]
-                    m_inner[j][k] += dgdx[j] [i][k];
-                    // - d(k) g[i,j]
-                    m_inner[i][j] -= dgdx[k] [i][j]; // correct
-                }
-            }
+float r_kerr_ks(vec3 p) {
+    float
+        aa = sq(camera.a),
+        RR = dot(p, p),
+        b = RR - aa;
+    return sqrt((b + sqrt(b*b + 4*aa*sq(p.z))) / 2);
+}
+void kerr_ks_fk(vec4 p, out float f, out vec4 k) {
+    float
+        a = camera.a,
+        x = p[1], y = p[2], z = p[3],
+        r = r_kerr_ks(p.yzw),
+        rr = r*r;
+    f = RS * r*rr / (rr*rr + sq(a*z));
+    k = vec4(
+        1,
+        -(r*x - a*y) / (rr + sq(a)),
+        -(r*y + a*x) / (rr + sq(a)),
+        -z / r
+    );
+}
+mat4 g_kerr_ks(vec4 p) {
+    float f;
+    vec4 k;
+    kerr_ks_fk(p, f, k);
+    return g_minkowsky + f * outerProduct(k, k);
+}
+mat4 g_inv_kerr_ks(vec4 p) {
+    float f;
+    vec4 k;
+    kerr_ks_fk(p, f, k);
+    k[0] = -k[0];
+    return g_minkowsky - f * outerProduct(k, k);
+}
+mat4[4] dgdx_kerr_ks(vec4 p) {
+    // The metric components are all O(1) here, so central differences hold up in f32.
+    const float DX = 1e-3;
+    mat4[4] dgdx;
+    dgdx[0] = mat4(
+        0, 0, 0, 0,
+        0, 0, 0, 0,
+        0, 0, 0, 0,
+        0, 0, 0, 0
+    );
+    dgdx[1] = (g_kerr_ks(p + vec4(0,DX,0,0)) - g_kerr_ks(p - vec4(0,DX,0,0))) / (2 * DX);
+    dgdx[2] = (g_kerr_ks(p + vec4(0,0,DX,0)) - g_kerr_ks(p - vec4(0,0,DX,0))) / (2 * DX);
+    dgdx[3] = (g_kerr_ks(p + vec4(0,0,0,DX)) - g_kerr_ks(p - vec4(0,0,0,DX))) / (2 * DX);
+    return dgdx;
+}
 
-            m += m_inner / 2 * g_inv[k][l];
-        }
-        cs[l] = m;
+//// INTEGRATION
+// Everything below works for any metric and integrator. They are passed as arguments instead of
+// read from the uniform, and only ever as constants (see trace_with), so every combination gets its
+// own loop with the other branches folded away. Software rasterizers run both sides of a branch
+// inside a loop. Each integrator calls acceleration() only once (the RK stages are loops) to keep
+// the 9 inlined copies from taking forever to compile.
+
+const int INTEGRATOR_LEAPFROG = 0;
+const int INTEGRATOR_RK4 = 1;
+const int INTEGRATOR_DOPRI = 2;
+
+const float DOPRI_MIN_STEP = 1e-4;
+
+// Classic RK4, C are the stage offsets and B the weights.
+const float RK4_C[4] = float[](0, 0.5, 0.5, 1);
+const float RK4_B[4] = float[](1.0/6, 1.0/3, 1.0/3, 1.0/6);
+// Dormand-Prince 5(4) Butcher tableau, row-major A[stage][j] with j < stage. The last row is the
+// 5th order solution.
+const float DOPRI_A[49] = float[](
+    0, 0, 0, 0, 0, 0, 0,
+    1.0/5, 0, 0, 0, 0, 0, 0,
+    3.0/40, 9.0/40, 0, 0, 0, 0, 0,
+    44.0/45, -56.0/15, 32.0/9, 0, 0, 0, 0,
+    19372.0/6561, -25360.0/2187, 64448.0/6561, -212.0/729, 0, 0, 0,
+    9017.0/3168, -355.0/33, 46732.0/5247, 49.0/176, -5103.0/18656, 0, 0,
+    35.0/384, 0, 500.0/1113, 125.0/192, -2187.0/6784, 11.0/84, 0
+);
+// Difference between the 5th and the embedded 4th order weights.
+const float DOPRI_E[7] = float[](71.0/57600, 0, -71.0/16695, 71.0/1920, -17253.0/339200, 22.0/525, -1.0/40);
+
+
+mat4 metric_g(int metric, vec4 x0) {
+    if (metric == METRIC_KERR_KS) {
+        return g_kerr_ks(x0);
+    } else if (metric == METRIC_KERR_BL) {
+        return g_kerr_bl(x0);
+    }
+    vec4 g = g_schwarzschild(x0);
+    return mat4(
+        g[0], 0, 0, 0,
+        0, g[1], 0, 0,
+        0, 0, g[2], 0,
+        0, 0, 0, g[3]
+    );
+}
+float radius(int metric, vec4 x0) {
+    if (metric == METRIC_KERR_KS) {
+        return r_kerr_ks(x0.yzw);
+    }
+    return x0[1];
+}
+float r_stop(int metric) {
+    if (metric == METRIC_KERR_KS) {
+        return r_horizon_kerr();
+    } else if (metric == METRIC_KERR_BL) {
+        // BL coordinates are singular at the horizon, stop slightly outside it.
+        return r_horizon_kerr() + 0.02 * RS;
+    }
+    return RS * 1.03;
+}
+bool heading_out(int metric, vec4 x0, vec4 x1) {
+    if (metric == METRIC_KERR_KS) {
+        return dot(x0.yzw, -x1.yzw) > 0;
+    }
+    return -x1[1] > 0;
+}
+void normalize_null(int metric, vec4 x0, inout vec4 x1) {
+    if (metric == METRIC_KERR_KS) {
+        normalize_null_geodesic(g_kerr_ks(x0), x1);
+    } else if (metric == METRIC_KERR_BL) {
+        normalize_null_geodesic(g_kerr_bl(x0), x1);
+    } else {
+        normalize_null_geodesic_schwarzschild(x0, x1);
+    }
+}
+vec4 acceleration(int metric, vec4 x0, vec4 x1) {
+    if (metric == METRIC_KERR_KS) {
+        return geodesic_acceleration(christoffelsymbols(g_inv_kerr_ks(x0), dgdx_kerr_ks(x0)), x1);
+    } else if (metric == METRIC_KERR_BL) {
+        return geodesic_acceleration(christoffelsymbols(g_inv_kerr_bl(g_kerr_bl(x0)), dgdx_kerr_bl(x0)), x1);
+    }
+    return geodesic_acceleration(christoffelsymbols_shwarzschild(x0), x1);
+}
+// Height above the equatorial plane, only the sign is meaningful.
+float equator_side(int metric, vec4 x0) {
+    if (metric == METRIC_KERR_KS) {
+        return x0[3];
+    }
+    return cos(x0[2]);
+}
+// Euclidean length of the displacement v at x0, so errors in angles count as much as errors in r.
+float flat_norm(int metric, vec4 x0, vec4 v) {
+    if (metric == METRIC_KERR_KS) {
+        return length(v.yzw);
     }
+    return length(vec3(v[1], x0[1] * v[2], x0[1] * sin(x0[2]) * v[3]));
+}
 
-    return cs;
+// Step size of the fixed step integrators, negative since rays are traced back in time.
+float fixed_step(float r) {
+    return -0.025 * max(1.0, pow(0.1 * r/RS, 0.5) );
 }
-void step_geodesic_kerr_bl(float dt, inout vec4 x0, inout vec4 x1) {
+
+void step_leapfrog(int metric, float dt, inout vec4 x0, inout vec4 x1) {
     x0 += dt * x1 * 0.5;
-    mat4[4] cs = christoffelsymbols_kerr_bl(x0);
-    vec4 x2 = -vec4(
-        dot(cs[0]*x1, x1),
-        dot(cs[1]*x1, x1),
-        dot(cs[2]*x1, x1),
-        dot(cs[3]*x1, x1)
-    );
-    x1 += dt * x2;
+    x1 += dt * acceleration(metric, x0, x1);
     x0 += dt * x1 * 0.5;
-    // x0 += dt * x1;
+}
+void step_rk4(int metric, float dt, inout vec4 x0, inout vec4 x1) {
+    // Every stage only depends on the previous one, no need to keep them all around.
+    vec4 kx = x1;
+    vec4 kv = vec4(0);
+    vec4 x0_next = x0;
+    vec4 x1_next = x1;
+    for (int stage = 0; stage < 4; stage++) {
+        float c = RK4_C[stage] * dt;
+        vec4 stage_x0 = x0 + c * kx;
+        kx = x1 + c * kv;
+        kv = acceleration(metric, stage_x0, kx);
+        x0_next += dt * RK4_B[stage] * kx;
+        x1_next += dt * RK4_B[stage] * kv;
+    }
+    x0 = x0_next;
+    x1 = x1_next;
+}
+// One Dormand-Prince 5(4) step from (x0, x1), returns the relative error of the step.
+float step_dopri(int metric, float dt, vec4 x0, vec4 x1, out vec4 x0_next, out vec4 x1_next) {
+    vec4 kx[7];
+    vec4 kv[7];
+    for (int stage = 0; stage < 7; stage++) {
+        x0_next = x0;
+        x1_next = x1;
+        for (int j = 0; j < stage; j++) {
+            float a = dt * DOPRI_A[stage * 7 + j];
+            x0_next += a * kx[j];
+            x1_next += a * kv[j];
+        }
+        kx[stage] = x1_next;
+        kv[stage] = acceleration(metric, x0_next, x1_next);
+    }
+    // The last stage was evaluated at the 5th order solution, which is where x0/x1_next are now.
+    vec4 err_x = vec4(0);
+    vec4 err_v = vec4(0);
+    for (int j = 0; j < 7; j++) {
+        err_x += dt * DOPRI_E[j] * kx[j];
+        err_v += dt * DOPRI_E[j] * kv[j];
+    }
+    return max(
+        flat_norm(metric, x0, err_x) / radius(metric, x0),
+        flat_norm(metric, x0, err_v) / flat_norm(metric, x0, x1)
+    );
 }
 
-void normalize_null_geodesic_kerr_bl(vec4 x0, inout vec4 x1) {
-    mat4 g = g_kerr_bl(x0);
-
-    x1.yzw = normalize(x1.yzw);
-    float A = g[0][0];
-    float B = 2 * dot(g[0].yzw, x1.yzw);
-    float C = dot(mat3(g[1].yzw,g[2].yzw,g[3].yzw) * x1.yzw, x1.yzw);
-    x1[0] = (-B - sqrt(B*B - 4*A*C))/(2*A); // -sqrt in quadratic formula selects for the time-reversed version.
+// Advance the ray by one step. dt carries the adaptive step size over between calls, it's capped
+// at 0.1 r so the step grows with the distance from the hole. A rejected Dormand-Prince step
+// leaves the ray where it is and only shrinks dt.
+void integrate(int metric, int integrator, inout float dt, float r, inout vec4 x0, inout vec4 x1) {
+    if (integrator == INTEGRATOR_DOPRI) {
+        vec4 x0_next, x1_next;
+        float err = step_dopri(metric, dt, x0, x1, x0_next, x1_next) / camera.tolerance;
+        bool accepted = err <= 1 || -dt <= DOPRI_MIN_STEP;
+        dt *= clamp(0.9 * pow(max(err, 1e-6), -0.2), 0.2, 5.0);
+        dt = -clamp(-dt, DOPRI_MIN_STEP, 0.1 * r);
+        if (accepted) {
+            x0 = x0_next;
+            x1 = x1_next;
+        }
+    } else if (integrator == INTEGRATOR_RK4) {
+        dt = fixed_step(r);
+        step_rk4(metric, dt, x0, x1);
+    } else {
+        dt = fixed_step(r);
+        step_leapfrog(metric, dt, x0, x1);
+    }
 }
 
-bool trace_kerr_bl(inout vec4 x0, inout vec4 x1) {
+const int RAY_CAPTURED = 0;
+const int RAY_ESCAPED = 1;
+const int RAY_DISK = 2;
+
+// Follow the ray back until it falls in, hits the disk or heads out past 25 RS. x0 and x1 are
+// left where that happened.
+int trace(int metric, int integrator, inout vec4 x0, inout vec4 x1) {
+    float stop = r_stop(metric);
+    float r = radius(metric, x0);
+    float dt = fixed_step(r);
     for (int i = 0; i < 1000; i++) {
-        float dt = 0.025;
-        // float dt = 0.025;
-        normalize_null_geodesic_kerr_bl(x0, x1);
-        step_geodesic_kerr_bl(dt, x0, x1);
-
-        float R = (RS+sqrt(sq(RS) - 4*sq(camera.a*sin(x0[2]))))/2;
-        if (x0[1] < R) {
-            return false;
+        x1 /= length(x1.yzw);
+        normalize_null(metric, x0, x1);
+        vec4 x0_prev = x0;
+        integrate(metric, integrator, dt, r, x0, x1);
+        if (camera.disk != 0) {
+            float side_prev = equator_side(metric, x0_prev);
+            float side = equator_side(metric, x0);
+            if (side_prev * side <= 0 && side_prev != side) {
+                vec4 crossing = mix(x0_prev, x0, side_prev / (side_prev - side));
+                float r_crossing = radius(metric, crossing);
+                if (r_crossing >= camera.disk_inner && r_crossing <= camera.disk_outer) {
+                    x0 = crossing;
+                    return RAY_DISK;
+                }
+            }
+        }
+        r = radius(metric, x0);
+        if (r < stop) {
+            return RAY_CAPTURED;
         }
-        if (x0[1] > 5*RS) {
-            return true;
+        if (r > 25*RS && heading_out(metric, x0, x1)) {
+            return RAY_ESCAPED;
         }
     }
-    return false;
+    return RAY_ESCAPED;
+}
+int trace_with(int metric, inout vec4 x0, inout vec4 x1) {
+    if (camera.integrator == INTEGRATOR_DOPRI) {
+        return trace(metric, INTEGRATOR_DOPRI, x0, x1);
+    } else if (camera.integrator == INTEGRATOR_RK4) {
+        return trace(metric, INTEGRATOR_RK4, x0, x1);
+    }
+    return trace(metric, INTEGRATOR_LEAPFROG, x0, x1);
+}
+
+//// ACCRETION DISK
+// Thin, opaque disk in the equatorial plane, made of gas on prograde circular orbits.
+
+// Disk temperature relative to the inner edge, x = r / disk_inner. Novikov-Thorne without the
+// relativistic corrections, the flux ~ x^-3 (1 - x^-1/2) with T ~ flux^1/4.
+float disk_temperature_profile(float x) {
+    return pow(x, -0.75) * pow(max(0.0, 1 - inversesqrt(x)), 0.25);
+}
+// Maximum of disk_temperature_profile, at x = 49/36.
+const float DISK_PROFILE_MAX = 0.48818;
+
+// Planck's law sampled at roughly the R, G and B wavelengths (in um), scaled so the brightest is 1.
+vec3 blackbody(float temperature) {
+    vec3 lambda = vec3(0.610, 0.550, 0.465);
+    vec3 c = 1 / (pow(lambda, vec3(5)) * (exp(14388 / (lambda * max(temperature, 500))) - 1));
+    return c / max(c.r, max(c.g, c.b));
+}
+
+// Observed frequency over emitted frequency for a photon with tangent x1 leaving the disk at x0,
+// seen by the observer the ray started out from with tangent x1_camera at x0_camera. Both the gravitational redshift and the Doppler shift
+// of the orbiting gas come out of the photon's conserved E and L. 0 where there is no circular
+// orbit.
+float disk_redshift(int metric, vec4 x0_camera, vec4 x1_camera, vec4 x0, vec4 x1) {
+    float r = radius(metric, x0);
+    float a = metric == METRIC_SCHWARZSCHILD ? 0.0 : camera.a;
+    float m = RS / 2;
+    // Keplerian angular velocity, exact for Kerr.
+    float omega = sqrt(m) / (pow(r, 1.5) + a * sqrt(m));
+    vec4 d_ph = metric == METRIC_KERR_KS ? vec4(0, -x0[2], x0[1], 0) : vec4(0, 0, 0, 1);
+
+    mat4 g = metric_g(metric, x0);
+    vec4 u = vec4(1, 0, 0, 0) + omega * d_ph; // 4-velocity of the gas, up to normalization
+    float uu = dot(g * u, u);
+    if (uu >= 0) {
+        return 0.0;
+    }
+    float u_t = inversesqrt(-uu);
+    vec4 p = g * x1;
+    float e = -p[0];
+    float l = dot(p, d_ph);
+
+    // The tetrad makes the observed frequency 1 for the starting tangent, whose energy is this.
+    // e is the energy after the rescaling in normalize_null_geodesic.
+    float e_camera = -(metric_g(metric, x0_camera) * x1_camera)[0];
+    return 1 / (u_t * max(e_camera, 1e-4) * (1 - omega * l / e));
+}
+
+vec4 disk_color(float r, float redshift) {
+    float temperature = camera.disk_temperature * disk_temperature_profile(r / camera.disk_inner) / DISK_PROFILE_MAX;
+    float observed = redshift * temperature;
+    // Bolometric intensity goes as T^4, I/nu^3 being invariant adds the g^4.
+    float intensity = pow(observed / camera.disk_temperature, 4);
+    return vec4(1 - exp(-2 * intensity * blackbody(observed)), 1);
 }
 
 void main() {
-    vec3 d = init_raydir();
+    vec3 d = camera.lut_pass != 0 ? lut_raydir() : init_raydir();
     vec3 p = camera.position;
 
     if (camera.activated != 0
     // ||true
     ) {
-        // Schwarzschild
-        vec4 x0 = vec4(0, rectilinear_to_spherical(p));
-        vec4 x1 = vec4(0, tangent_rectilinear_to_spherical_mat(x0.yzw) * d);
-        if (trace_schwarzschild(x0, x1)) {
-            FragColor = background(tangent_spherical_to_rectilinear_mat(x0.yzw) * x1.yzw);
+        int metric = camera.metric;
+        // Kerr-Schild coordinates are Cartesian, Schwarzschild and Boyer-Lindquist spherical.
+        vec4 x0 = metric == METRIC_KERR_KS ? vec4(0, p) : vec4(0, rectilinear_to_spherical(p));
+        // The photon arriving from d, seen by the observer the tetrad belongs to.
+        vec4 x1 = camera.tetrad * vec4(1, -d);
+        vec4 x0_camera = x0;
+        vec4 x1_camera = x1;
+
+        int outcome;
+        if (metric == METRIC_KERR_KS) {
+            outcome = trace_with(METRIC_KERR_KS, x0, x1);
+        } else if (metric == METRIC_KERR_BL) {
+            outcome = trace_with(METRIC_KERR_BL, x0, x1);
         } else {
-            FragColor = vec4(0,0,0,0);
+            outcome = trace_with(METRIC_SCHWARZSCHILD, x0, x1);
         }
 
-        // // Kerr (Boyer-Lindquist)  :: NOT CURRENTLY WORKING
-        // vec4 x0 = vec4(0, rectilinear_to_spherical(p));
-        // vec4 x1 = -vec4(0, tangent_rectilinear_to_spherical_mat(x0.yzw) * d);
-        // if (trace_kerr_bl(x0, x1)) {
-        //     FragColor = background(tangent_spherical_to_rectilinear_mat(x0.yzw) * -x1.yzw);
-        // } else {
-        //     FragColor = vec4(0,0,0,0);
-        // }
-
-        // // Kerr (Kerr-Schild coordinates)
-        // vec4 x0 = vec4(0, p);
-        // vec4 x1 = -vec4(0, d);
-        // if (trace_kerr_ks(x0, x1)) {
-        //     FragColor = background(-x1.yzw);
-        // } else {
-        //     FragColor = vec4(0,0,0,0);
-        // }
+        if (outcome == RAY_ESCAPED) {
+            vec3 escape_dir;
+            if (metric == METRIC_KERR_KS) {
+                escape_dir = -x1.yzw;
+            } else {
+                escape_dir = tangent_spherical_to_rectilinear_mat(x0.yzw) * x1.yzw;
+            }
+            if (camera.lut_pass != 0) {
+                FragColor = vec4(normalize(escape_dir) * lut_frame(p), 0);
+            } else {
+                FragColor = background(escape_dir);
+            }
+        } else if (outcome == RAY_DISK) {
+            FragColor = disk_color(radius(metric, x0), disk_redshift(metric, x0_camera, x1_camera, x0, x1));
+        } else {
+            // The table marks captured rays in alpha.
+            FragColor = vec4(0, 0, 0, camera.lut_pass != 0 ? 1.0 : 0.0);
+        }
 
         // vec2 v = vec2(uv.x * camera.view_dim.x / camera.view_dim.y, -uv.y);
         // vec4 x0 = vec4(0,v.x,0,v.y)*5 + vec4(0,p);
@@ -0,0 +1,384 @@
+use std::{io::Write, path::Path};
+
+use anyhow::{bail, Context};
+
+use crate::new_abstractions::read_texture_rows;
+
+/// A frame read back from the GPU.
+pub enum FrameData {
+    /// 8-bit color, already encoded for display (sRGB).
+    Ldr(image::RgbaImage),
+    /// Linear floating point color.
+    Hdr(image::Rgba32FImage),
+}
+
+impl FrameData {
+    pub fn to_ldr(&self) -> image::RgbaImage {
+        match self {
+            Self::Ldr(img) => img.clone(),
+            Self::Hdr(img) => {
+                let (width, height) = img.dimensions();
+                image::RgbaImage::from_fn(width, height, |x, y| {
+                    let [r, g, b, a] = img.get_pixel(x, y).0;
+                    image::Rgba([
+                        unorm_to_u8(linear_to_srgb(r)),
+                        unorm_to_u8(linear_to_srgb(g)),
+                        unorm_to_u8(linear_to_srgb(b)),
+                        unorm_to_u8(a),
+                    ])
+                })
+            }
+        }
+    }
+
+    pub fn to_hdr(&self) -> image::Rgba32FImage {
+        match self {
+            Self::Hdr(img) => img.clone(),
+            Self::Ldr(img) => {
+                let (width, height) = img.dimensions();
+                image::Rgba32FImage::from_fn(width, height, |x, y| {
+                    let [r, g, b, a] = img.get_pixel(x, y).0;
+                    image::Rgba([
+                        srgb_to_linear(r as f32 / 255.0),
+                        srgb_to_linear(g as f32 / 255.0),
+                        srgb_to_linear(b as f32 / 255.0),
+                        a as f32 / 255.0,
+                    ])
+                })
+            }
+        }
+    }
+
+    /// A transparent black frame of the same kind, `size` pixels large.
+    pub fn blank(&self, (width, height): (u32, u32)) -> Self {
+        match self {
+            Self::Ldr(_) => Self::Ldr(image::RgbaImage::new(width, height)),
+            Self::Hdr(_) => Self::Hdr(image::Rgba32FImage::new(width, height)),
+        }
+    }
+
+    /// Copy `tile` in with its top left corner at `offset`, cutting off what doesn't fit.
+    pub fn paste(&mut self, tile: &FrameData, (x, y): (u32, u32)) -> anyhow::Result<()> {
+        match (self, tile) {
+            (Self::Ldr(img), Self::Ldr(tile)) => image::imageops::replace(img, tile, x as i64, y as i64),
+            (Self::Hdr(img), Self::Hdr(tile)) => image::imageops::replace(img, tile, x as i64, y as i64),
+            _ => bail!("can't paste frames of different formats together"),
+        }
+        Ok(())
+    }
+
+    /// Write the frame to disk, picking the encoding from the file extension.
+    /// `.exr` is written as 32-bit float, everything else as 8-bit. Alpha is dropped, matching
+    /// what an opaque window surface shows.
+    pub fn save(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        if let Some(parent) = path.parent() {
+            if !parent.as_os_str().is_empty() {
+                std::fs::create_dir_all(parent)
+                    .with_context(|| format!("creating directory {}", parent.display()))?;
+            }
+        }
+        let is_exr = path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("exr"));
+        if is_exr {
+            image::DynamicImage::ImageRgba32F(self.to_hdr()).into_rgb32f().save(path)
+        } else {
+            image::DynamicImage::ImageRgba8(self.to_ldr()).into_rgb8().save(path)
+        }
+        .with_context(|| format!("saving frame to {}", path.display()))
+    }
+}
+
+/// Writes frames as an uncompressed YUV4MPEG2 (Y4M) stream, which ffmpeg and most players read
+/// directly, e.g. `ffmpeg -i frames.y4m out.mp4`.
+pub struct Y4mWriter {
+    out: Box<dyn Write>,
+    width: u32,
+    height: u32,
+}
+
+impl Y4mWriter {
+    /// Start a stream at `path`, `-` is stdout so it can be piped.
+    pub fn create(path: &str, (width, height): (u32, u32), fps: f32) -> anyhow::Result<Self> {
+        let out: Box<dyn Write> = if path == "-" {
+            Box::new(std::io::stdout().lock())
+        } else {
+            let file = std::fs::File::create(path).with_context(|| format!("creating {}", path))?;
+            Box::new(std::io::BufWriter::new(file))
+        };
+        let mut this = Self { out, width, height };
+        // 4:4:4 keeps the full chroma resolution, the frame rate is in thousandths.
+        writeln!(
+            this.out,
+            "YUV4MPEG2 W{} H{} F{}:1000 Ip A1:1 C444",
+            width,
+            height,
+            (fps * 1000.0).round() as u32
+        )?;
+        Ok(this)
+    }
+
+    pub fn write_frame(&mut self, frame: &FrameData) -> anyhow::Result<()> {
+        let img = frame.to_ldr();
+        if img.dimensions() != (self.width, self.height) {
+            bail!(
+                "frame is {:?}, the stream was started with {:?}",
+                img.dimensions(),
+                (self.width, self.height)
+            );
+        }
+        // BT.601 in limited range, what players assume for a Y4M without a colour space.
+        let to_byte = |it: f32| it.round().clamp(0.0, 255.0) as u8;
+        let pixels = img.pixels().map(|px| px.0.map(|it| it as f32 / 255.0));
+        let (mut y, mut u, mut v) = (Vec::new(), Vec::new(), Vec::new());
+        for [r, g, b, _] in pixels {
+            y.push(to_byte(16.0 + 65.481 * r + 128.553 * g + 24.966 * b));
+            u.push(to_byte(128.0 - 37.797 * r - 74.203 * g + 112.0 * b));
+            v.push(to_byte(128.0 + 112.0 * r - 93.786 * g - 18.214 * b));
+        }
+        self.out.write_all(b"FRAME\n")?;
+        for plane in [y, u, v] {
+            self.out.write_all(&plane)?;
+        }
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> anyhow::Result<()> {
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+/// An offscreen color target that simulations can render into instead of the surface, and that
+/// can be read back afterwards.
+pub struct OffscreenTarget {
+    pub texture: wgpu::Texture,
+    pub view: wgpu::TextureView,
+}
+
+impl OffscreenTarget {
+    pub fn new(device: &wgpu::Device, format: wgpu::TextureFormat, (width, height): (u32, u32)) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("offscreen-target"),
+            size: wgpu::Extent3d {
+                width: width.max(1),
+                height: height.max(1),
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                | wgpu::TextureUsages::COPY_SRC
+                | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        Self { texture, view }
+    }
+
+    pub fn read(&self, device: &wgpu::Device, queue: &wgpu::Queue) -> anyhow::Result<FrameData> {
+        read_frame(device, queue, &self.texture)
+    }
+}
+
+/// Draws an [`OffscreenTarget`] onto a view of the same size, to show a frame that was rendered
+/// offscreen. Window surfaces can't be copied into on every backend.
+pub struct Blit {
+    pipeline: wgpu::RenderPipeline,
+    layout: wgpu::BindGroupLayout,
+}
+
+impl Blit {
+    /// For views of `format`, which should match the source's so the colors come out unchanged.
+    pub fn new(device: &wgpu::Device, format: wgpu::TextureFormat) -> Self {
+        let shader = device.create_shader_module(wgpu::include_wgsl!("shaders/blit.wgsl"));
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("blit"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            }],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("blit"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("blit"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(format.into())],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+        Self { pipeline, layout }
+    }
+
+    pub fn draw(&self, source: &OffscreenTarget, view: &wgpu::TextureView, device: &wgpu::Device, queue: &wgpu::Queue) {
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("blit"),
+            layout: &self.layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&source.view),
+            }],
+        });
+        let mut enc = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: Some("blit") });
+        {
+            let mut pass = enc.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("blit"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: None,
+            });
+            pass.set_pipeline(&self.pipeline);
+            pass.set_bind_group(0, &bind_group, &[]);
+            pass.draw(0..3, 0..1);
+        }
+        queue.submit(Some(enc.finish()));
+    }
+}
+
+/// Copy a 2D texture back to the CPU, blocking until the copy is done.
+/// The texture must have been created with `COPY_SRC`.
+pub fn read_frame(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    texture: &wgpu::Texture,
+) -> anyhow::Result<FrameData> {
+    use wgpu::TextureFormat as F;
+
+    let format = texture.format();
+    let wgpu::Extent3d { width, height, .. } = texture.size();
+    let data = read_texture_rows(device, queue, texture)?;
+
+    Ok(match format {
+        F::Rgba8Unorm | F::Rgba8UnormSrgb => FrameData::Ldr(
+            image::RgbaImage::from_raw(width, height, data).context("frame size mismatch")?,
+        ),
+        F::Bgra8Unorm | F::Bgra8UnormSrgb => {
+            let mut data = data;
+            for px in data.chunks_exact_mut(4) {
+                px.swap(0, 2);
+            }
+            FrameData::Ldr(
+                image::RgbaImage::from_raw(width, height, data).context("frame size mismatch")?,
+            )
+        }
+        F::Rgba16Float => {
+            let data = data
+                .chunks_exact(2)
+                .map(|v| f16_to_f32(u16::from_le_bytes([v[0], v[1]])))
+                .collect();
+            FrameData::Hdr(
+                image::Rgba32FImage::from_raw(width, height, data).context("frame size mismatch")?,
+            )
+        }
+        F::Rgba32Float => {
+            let data = data
+                .chunks_exact(4)
+                .map(|v| f32::from_le_bytes([v[0], v[1], v[2], v[3]]))
+                .collect();
+            FrameData::Hdr(
+                image::Rgba32FImage::from_raw(width, height, data).context("frame size mismatch")?,
+            )
+        }
+        _ => bail!("frame capture from {:?} textures is not supported", format),
+    })
+}
+
+fn unorm_to_u8(v: f32) -> u8 {
+    (v.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+fn srgb_to_linear(v: f32) -> f32 {
+    if v <= 0.04045 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(v: f32) -> f32 {
+    if v <= 0.0031308 {
+        v * 12.92
+    } else {
+        1.055 * v.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+fn f16_to_f32(bits: u16) -> f32 {
+    let sign = if bits & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = ((bits >> 10) & 0x1f) as i32;
+    let mantissa = (bits & 0x3ff) as f32;
+    match exponent {
+        0 => sign * mantissa * 2f32.powi(-24),
+        0x1f if mantissa == 0.0 => sign * f32::INFINITY,
+        0x1f => f32::NAN,
+        _ => sign * (1.0 + mantissa / 1024.0) * 2f32.powi(exponent - 15),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{engine_base, test_gpu::core::TestGPU};
+
+    #[test]
+    fn blit_keeps_the_colors() {
+        let Some((_, device, queue)) = engine_base::fallback_device::<TestGPU>() else {
+            eprintln!("No fallback adapter, skipping");
+            return;
+        };
+        let format = wgpu::TextureFormat::Rgba8UnormSrgb;
+        let (source, target) = (
+            OffscreenTarget::new(&device, format, (7, 3)),
+            OffscreenTarget::new(&device, format, (7, 3)),
+        );
+        let mut enc = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        enc.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: None,
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &source.view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color { r: 0.5, g: 0.0, b: 1.0, a: 1.0 }),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+        queue.submit(Some(enc.finish()));
+
+        Blit::new(&device, format).draw(&source, &target.view, &device, &queue);
+        let expected = source.read(&device, &queue).unwrap().to_ldr();
+        assert_eq!(expected.get_pixel(0, 0).0, [188, 0, 255, 255]);
+        assert_eq!(target.read(&device, &queue).unwrap().to_ldr(), expected);
+    }
+}
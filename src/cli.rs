@@ -0,0 +1,280 @@
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, HashSet},
+    str::FromStr,
+};
+
+use anyhow::{anyhow, bail, Context};
+
+use crate::{
+    engine_base::{HeadlessCapture, HeadlessConfig},
+    registry,
+};
+
+pub const USAGE: &str = "\
+usage: the-sim [<simulation>] [options] [simulation options]
+       the-sim --list
+
+options:
+  --list                 list the available simulations
+  --width <px>           window / output width
+  --height <px>          window / output height
+  --headless             render offscreen without opening a window
+  --frames <n>           number of frames to render in headless mode
+  --fallback-adapter     use the software fallback adapter (headless only)
+  --hdr                  render to a float target (headless only), for .exr captures
+  --capture <path>       save frames to <path>, `{frame}` is replaced by the frame number.
+                         A .y4m path (or - for stdout) records a video of every frame instead
+  --capture-every <n>    capture every n-th frame instead of only the last one
+  --fps <n>              advance simulated time by 1/n seconds per frame (headless only), also
+                         the frame rate of videos
+  --tile-size <px>       render the image in tiles of at most <px> a side and stitch them
+                         together, for sizes past the device's texture limit (headless only).
+                         Every tile gets --frames frames, only the whole image is captured
+  --help                 show this message and the options and keys of the simulations, or
+                         only those of the one given
+
+F12 saves the window's frame as a PNG. Float EXR frames need --headless --hdr --capture <path>.exr.
+
+Simulation options that take a file, like --path, --sweep, --params, --emitters and --initial,
+read RON from .ron files, TOML from .toml files and JSON from anything else.
+";
+
+/// Options of the launcher that never take a value, see [`EngineBase::flags`] for the simulations'.
+///
+/// [`EngineBase::flags`]: crate::engine_base::EngineBase::flags
+const FLAGS: &[&str] = &["list", "help", "headless", "fallback-adapter", "hdr"];
+
+/// Whether `--name` is a flag of the launcher or of any simulation. The simulation may come after
+/// it on the command line, so all of them are asked.
+fn is_flag(name: &str) -> bool {
+    FLAGS.contains(&name) || registry::SIMULATIONS.iter().any(|sim| (sim.flags)().contains(&name))
+}
+
+/// Command line arguments left over for the simulation itself, see `EngineBase::configure`.
+///
+/// Values are looked up by name without the leading `--`. Lookups are recorded so options that
+/// nothing asked for can be reported afterwards.
+#[derive(Debug, Default)]
+pub struct SimArgs {
+    values: BTreeMap<String, Option<String>>,
+    used: RefCell<HashSet<String>>,
+}
+
+impl SimArgs {
+    /// Parse the value of `--name <value>`, `None` if the option wasn't given.
+    pub fn get<T: FromStr>(&self, name: &str) -> anyhow::Result<Option<T>>
+    where
+        T::Err: std::fmt::Display,
+    {
+        self.used.borrow_mut().insert(name.to_string());
+        match self.values.get(name) {
+            None => Ok(None),
+            Some(None) => bail!("option --{} expects a value", name),
+            Some(Some(value)) => value
+                .parse()
+                .map(Some)
+                .map_err(|err| anyhow!("invalid value {:?} for --{}: {}", value, name, err)),
+        }
+    }
+
+    /// Whether the flag `--name` was given, it mustn't have a value.
+    pub fn flag(&self, name: &str) -> anyhow::Result<bool> {
+        self.used.borrow_mut().insert(name.to_string());
+        match self.values.get(name) {
+            None => Ok(false),
+            Some(None) => Ok(true),
+            Some(Some(value)) => bail!("--{} is a flag and takes no value, got {:?}", name, value),
+        }
+    }
+
+    /// Options that were given but never looked up.
+    pub fn unused(&self) -> Vec<&str> {
+        let used = self.used.borrow();
+        self.values
+            .keys()
+            .filter(|key| !used.contains(*key))
+            .map(String::as_str)
+            .collect()
+    }
+
+    fn insert(&mut self, name: String, value: Option<String>) {
+        self.values.insert(name, value);
+    }
+}
+
+/// Everything needed to start a simulation, minus which simulation it is.
+#[derive(Debug, Default)]
+pub struct LaunchOptions {
+    pub size: Option<(u32, u32)>,
+    pub headless: Option<HeadlessConfig>,
+    pub sim_args: SimArgs,
+}
+
+#[derive(Debug, Default)]
+pub struct Args {
+    pub simulation: Option<String>,
+    pub list: bool,
+    pub help: bool,
+    pub launch: LaunchOptions,
+}
+
+impl Args {
+    pub fn parse(args: impl IntoIterator<Item = String>) -> anyhow::Result<Self> {
+        let mut this = Self::default();
+        let mut options = SimArgs::default();
+
+        let mut args = args.into_iter().peekable();
+        while let Some(arg) = args.next() {
+            if let Some(name) = arg.strip_prefix("--") {
+                let (name, value) = match name.split_once('=') {
+                    Some((name, value)) => (name.to_string(), Some(value.to_string())),
+                    None if is_flag(name) => (name.to_string(), None),
+                    None => {
+                        let value = args.next_if(|next| !next.starts_with("--"));
+                        (name.to_string(), value)
+                    }
+                };
+                options.insert(name, value);
+            } else if this.simulation.is_none() {
+                this.simulation = Some(arg);
+            } else {
+                bail!("unexpected argument {:?}", arg);
+            }
+        }
+
+        this.list = options.flag("list")?;
+        this.help = options.flag("help")?;
+
+        let width: Option<u32> = options.get("width")?;
+        let height: Option<u32> = options.get("height")?;
+        let capture = options.get::<String>("capture")?;
+        let capture_every = options.get::<u32>("capture-every")?;
+        let frames = options.get::<u32>("frames")?;
+        let force_fallback_adapter = options.flag("fallback-adapter")?;
+        let hdr = options.flag("hdr")?;
+        let fps = options.get::<f32>("fps")?;
+        if fps.is_some_and(|fps| fps <= 0.0) {
+            bail!("--fps must be positive");
+        }
+        let tile_size = options.get::<u32>("tile-size")?;
+        if tile_size == Some(0) {
+            bail!("--tile-size must be positive");
+        }
+        if tile_size.is_some() && (fps.is_some() || capture_every.is_some()) {
+            bail!("--tile-size renders a single still, it can't be combined with --fps or --capture-every");
+        }
+
+        this.launch.size = match (width, height) {
+            (None, None) => None,
+            (width, height) => Some((
+                width.or(height).context("missing size")?,
+                height.or(width).context("missing size")?,
+            )),
+        };
+
+        if options.flag("headless")? {
+            let default = HeadlessConfig::default();
+            let (width, height) = this.launch.size.unwrap_or((default.width, default.height));
+            this.launch.headless = Some(HeadlessConfig {
+                width,
+                height,
+                frames: frames.unwrap_or(default.frames),
+                force_fallback_adapter,
+                hdr,
+                fps,
+                tile_size,
+                capture: capture.map(|path| HeadlessCapture {
+                    path,
+                    every: capture_every.unwrap_or(0),
+                }),
+            });
+        } else if capture.is_some()
+            || capture_every.is_some()
+            || frames.is_some()
+            || force_fallback_adapter
+            || hdr
+            || fps.is_some()
+            || tile_size.is_some()
+        {
+            bail!(
+                "--frames, --capture, --capture-every, --fallback-adapter, --hdr, --fps and --tile-size only apply with --headless"
+            );
+        }
+
+        this.launch.sim_args = options;
+        Ok(this)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(line: &str) -> anyhow::Result<Args> {
+        Args::parse(line.split_whitespace().map(str::to_string))
+    }
+
+    #[test]
+    fn values_after_a_space_or_an_equals_sign() {
+        let args = parse("wave --grid 64x32 --c=0.5").unwrap();
+        assert_eq!(args.simulation.as_deref(), Some("wave"));
+        let sim_args = &args.launch.sim_args;
+        assert_eq!(sim_args.get::<String>("grid").unwrap().as_deref(), Some("64x32"));
+        assert_eq!(sim_args.get::<f32>("c").unwrap(), Some(0.5));
+        assert_eq!(sim_args.get::<f32>("dt").unwrap(), None);
+        assert!(sim_args.get::<f32>("grid").is_err());
+        assert!(sim_args.unused().is_empty());
+    }
+
+    #[test]
+    fn flags_leave_the_simulation_alone() {
+        let args = parse("--headless wave --frames 3").unwrap();
+        assert_eq!(args.simulation.as_deref(), Some("wave"));
+        assert_eq!(args.launch.headless.unwrap().frames, 3);
+
+        let args = parse("--lut --trace blackhole").unwrap();
+        assert_eq!(args.simulation.as_deref(), Some("blackhole"));
+        assert!(args.launch.sim_args.flag("lut").unwrap());
+        assert!(!args.launch.sim_args.flag("disk").unwrap());
+
+        let args = parse("blackhole --lut=yes").unwrap();
+        assert!(args.launch.sim_args.flag("lut").is_err());
+        assert!(parse("--headless=yes").is_err());
+        // Anything a simulation doesn't declare takes the word after it.
+        let args = parse("--made-up blackhole").unwrap();
+        assert_eq!(args.simulation, None);
+    }
+
+    #[test]
+    fn values_starting_with_a_dash() {
+        let args = parse("blackhole --spin -0.3 --headless --capture - --capture-every 2").unwrap();
+        assert_eq!(args.launch.sim_args.get::<f32>("spin").unwrap(), Some(-0.3));
+        let capture = args.launch.headless.unwrap().capture.unwrap();
+        assert_eq!(capture.path, "-");
+        assert_eq!(capture.every, 2);
+    }
+
+    #[test]
+    fn mistakes() {
+        assert!(parse("wave blackhole").is_err());
+        assert!(parse("--width wide").is_err());
+        assert!(parse("--frames 10").is_err());
+        assert!(parse("--capture-every 2").is_err());
+        assert!(parse("--headless --capture").is_err());
+
+        let args = parse("blackhole --spinn 0.3").unwrap();
+        assert_eq!(args.launch.sim_args.get::<f32>("spin").unwrap(), None);
+        assert_eq!(args.launch.sim_args.unused(), ["spinn"]);
+    }
+
+    #[test]
+    fn flags_are_in_the_usage() {
+        for sim in registry::SIMULATIONS {
+            for flag in (sim.flags)() {
+                let line = format!("  --{} ", flag);
+                assert!((sim.usage)().contains(&line), "{} doesn't describe --{}", sim.name, flag);
+            }
+        }
+    }
+}
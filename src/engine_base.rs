@@ -7,6 +7,9 @@ use std::str::FromStr;
 use std::time::Instant;
 #[cfg(target_arch = "wasm32")]
 use web_sys::{ImageBitmapRenderingContext, OffscreenCanvas};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::capture::{Blit, FrameData, OffscreenTarget, Y4mWriter};
+use crate::cli::SimArgs;
 use winit::{
     event::{self, WindowEvent},
     event_loop::{ControlFlow, EventLoop},
@@ -21,6 +24,14 @@ pub fn cast_slice<T>(data: &[T]) -> &[u8] {
 
 pub trait EngineBase: 'static + Sized {
     fn title() -> &'static str;
+    /// Options of `configure` that never take a value, so a word after them is left alone.
+    fn flags() -> &'static [&'static str] {
+        &[]
+    }
+    /// The options of `configure` and the keys of `update`, printed by `--help`.
+    fn usage() -> &'static str {
+        ""
+    }
     fn optional_features() -> wgpu::Features {
         wgpu::Features::empty()
     }
@@ -49,7 +60,24 @@ pub trait EngineBase: 'static + Sized {
         device: &wgpu::Device,
         queue: &wgpu::Queue,
     );
+    /// Apply simulation specific command line options, called once right after `init`.
+    fn configure(
+        &mut self,
+        _args: &SimArgs,
+        _device: &wgpu::Device,
+        _queue: &wgpu::Queue,
+    ) -> anyhow::Result<()> {
+        Ok(())
+    }
     fn update(&mut self, event: WindowEvent);
+    /// Advance simulated time by `dt` seconds every frame rather than following the wall clock,
+    /// so offline renders come out the same however long each frame takes. See [`FrameClock`].
+    fn set_fixed_timestep(&mut self, _dt: f32) {}
+    /// Render only `tile` of a larger image into the view from now on. Returns whether the
+    /// simulation supports that, see [`Tile`].
+    fn set_tile(&mut self, _tile: Tile) -> bool {
+        false
+    }
     fn render(
         &mut self,
         view: &wgpu::TextureView,
@@ -59,6 +87,47 @@ pub trait EngineBase: 'static + Sized {
     );
 }
 
+/// Part of an image too large to render at once, like a poster past `max_texture_dimension_2d`.
+/// The tile is as large as the view, headless runs stitch the tiles back together on the CPU.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Tile {
+    /// Size of the whole image.
+    pub image: (u32, u32),
+    /// Top left corner of the tile in the image.
+    pub offset: (u32, u32),
+}
+
+/// Time between frames, from the wall clock unless a fixed timestep was set.
+pub struct FrameClock {
+    last_frame: std::time::Instant,
+    fixed: Option<f32>,
+}
+
+impl FrameClock {
+    /// Longest step taken from the wall clock, so a slow frame (like the first one, compiling the
+    /// shaders) doesn't leap ahead.
+    const MAX_DT: f32 = 0.1;
+
+    pub fn new() -> Self {
+        Self {
+            last_frame: std::time::Instant::now(),
+            fixed: None,
+        }
+    }
+
+    pub fn set_fixed(&mut self, dt: f32) {
+        self.fixed = Some(dt);
+    }
+
+    /// Seconds since the last call.
+    pub fn tick(&mut self) -> f32 {
+        let now = std::time::Instant::now();
+        let elapsed = (now - self.last_frame).as_secs_f32().min(Self::MAX_DT);
+        self.last_frame = now;
+        self.fixed.unwrap_or(elapsed)
+    }
+}
+
 struct Setup {
     window: winit::window::Window,
     event_loop: EventLoop<()>,
@@ -78,15 +147,13 @@ struct OffscreenCanvasSetup {
     bitmap_renderer: ImageBitmapRenderingContext,
 }
 
-async fn setup<E: EngineBase>() -> Setup {
-    #[cfg(not(target_arch = "wasm32"))]
-    {
-        env_logger::init();
-    };
-
+async fn setup<E: EngineBase>(size: Option<(u32, u32)>) -> Setup {
     let event_loop = EventLoop::new();
     let mut builder = winit::window::WindowBuilder::new();
     builder = builder.with_title(E::title());
+    if let Some((width, height)) = size {
+        builder = builder.with_inner_size(winit::dpi::PhysicalSize::new(width, height));
+    }
     #[cfg(windows_OFF)] // TODO
     {
         use winit::platform::windows::WindowBuilderExtWindows;
@@ -149,13 +216,7 @@ async fn setup<E: EngineBase>() -> Setup {
 
     log::info!("Initializing the surface...");
 
-    let backends = wgpu::util::backend_bits_from_env().unwrap_or_else(wgpu::Backends::all);
-    let dx12_shader_compiler = wgpu::util::dx12_shader_compiler_from_env().unwrap_or_default();
-
-    let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
-        backends,
-        dx12_shader_compiler,
-    });
+    let instance = create_instance();
     let (size, surface) = unsafe {
         let size = window.inner_size();
 
@@ -187,6 +248,33 @@ async fn setup<E: EngineBase>() -> Setup {
         println!("Using {} ({:?})", adapter_info.name, adapter_info.backend);
     }
 
+    let (device, queue) = request_device::<E>(&adapter).await;
+
+    Setup {
+        window,
+        event_loop,
+        instance,
+        size,
+        surface,
+        adapter,
+        device,
+        queue,
+        #[cfg(target_arch = "wasm32")]
+        offscreen_canvas_setup,
+    }
+}
+
+fn create_instance() -> wgpu::Instance {
+    let backends = wgpu::util::backend_bits_from_env().unwrap_or_else(wgpu::Backends::all);
+    let dx12_shader_compiler = wgpu::util::dx12_shader_compiler_from_env().unwrap_or_default();
+
+    wgpu::Instance::new(wgpu::InstanceDescriptor {
+        backends,
+        dx12_shader_compiler,
+    })
+}
+
+async fn request_device<E: EngineBase>(adapter: &wgpu::Adapter) -> (wgpu::Device, wgpu::Queue) {
     let optional_features = E::optional_features();
     let required_features = E::required_features();
     let adapter_features = adapter.features();
@@ -215,7 +303,7 @@ async fn setup<E: EngineBase>() -> Setup {
     let needed_limits = E::required_limits().using_resolution(adapter.limits());
 
     let trace_dir = std::env::var("WGPU_TRACE");
-    let (device, queue) = adapter
+    adapter
         .request_device(
             &wgpu::DeviceDescriptor {
                 label: None,
@@ -225,20 +313,7 @@ async fn setup<E: EngineBase>() -> Setup {
             trace_dir.ok().as_ref().map(std::path::Path::new),
         )
         .await
-        .expect("Unable to find a suitable GPU adapter!");
-
-    Setup {
-        window,
-        event_loop,
-        instance,
-        size,
-        surface,
-        adapter,
-        device,
-        queue,
-        #[cfg(target_arch = "wasm32")]
-        offscreen_canvas_setup,
-    }
+        .expect("Unable to find a suitable GPU adapter!")
 }
 
 fn start<E: EngineBase>(
@@ -263,6 +338,7 @@ fn start<E: EngineBase>(
         queue,
         offscreen_canvas_setup,
     }: Setup,
+    args: SimArgs,
 ) {
     let spawner = Spawner::new();
     let mut config = surface
@@ -274,11 +350,16 @@ fn start<E: EngineBase>(
 
     log::info!("Initializing the example...");
     let mut example = E::init(&config, &adapter, &device, &queue);
+    configure_or_exit(&mut example, &args, &device, &queue);
 
     #[cfg(not(target_arch = "wasm32"))]
     let mut last_frame_inst = Instant::now();
     #[cfg(not(target_arch = "wasm32"))]
     let (mut frame_count, mut accum_time) = (0, 0.0);
+    #[cfg(not(target_arch = "wasm32"))]
+    let mut capture_requested = false;
+    #[cfg(not(target_arch = "wasm32"))]
+    let mut blit: Option<Blit> = None;
 
     log::info!("Entering render loop...");
     event_loop.run(move |event, _, control_flow| {
@@ -291,7 +372,11 @@ fn start<E: EngineBase>(
         match event {
             event::Event::RedrawEventsCleared => {
                 #[cfg(not(target_arch = "wasm32"))]
-                spawner.run_until_stalled();
+                {
+                    // Let pending buffer mappings (`read_async`) resolve before running the futures awaiting them.
+                    device.poll(wgpu::Maintain::Poll);
+                    spawner.run_until_stalled();
+                }
 
                 window.request_redraw();
             }
@@ -346,6 +431,19 @@ fn start<E: EngineBase>(
                 } => {
                     println!("{:#?}", instance.generate_report());
                 }
+                #[cfg(not(target_arch = "wasm32"))]
+                WindowEvent::KeyboardInput {
+                    input:
+                        event::KeyboardInput {
+                            virtual_keycode: Some(event::VirtualKeyCode::F12),
+                            state: event::ElementState::Pressed,
+                            ..
+                        },
+                    ..
+                } => {
+                    // A PNG of what the surface shows, float EXRs need `--headless --hdr`.
+                    capture_requested = true;
+                }
                 _ => {
                     example.update(event);
                 }
@@ -380,6 +478,32 @@ fn start<E: EngineBase>(
                     ..wgpu::TextureViewDescriptor::default()
                 });
 
+                #[cfg(not(target_arch = "wasm32"))]
+                if std::mem::take(&mut capture_requested) {
+                    // Surface textures can't be read back everywhere, so draw this frame offscreen,
+                    // show it through a blit and save it from there.
+                    let target = OffscreenTarget::new(
+                        &device,
+                        surface_view_format,
+                        (config.width, config.height),
+                    );
+                    example.render(&target.view, &device, &queue, &spawner);
+                    blit.get_or_insert_with(|| Blit::new(&device, surface_view_format))
+                        .draw(&target, &view, &device, &queue);
+                    frame.present();
+
+                    let timestamp = std::time::SystemTime::now()
+                        .duration_since(std::time::UNIX_EPOCH)
+                        .unwrap_or_default()
+                        .as_millis();
+                    let path = format!("capture-{}-{}.png", E::title().replace(' ', "_"), timestamp);
+                    match target.read(&device, &queue).and_then(|it| it.save(&path)) {
+                        Ok(()) => log::info!("Saved frame to {}", path),
+                        Err(err) => log::error!("Failed to capture frame: {:#}", err),
+                    }
+                    return;
+                }
+
                 example.render(&view, &device, &queue, &spawner);
 
                 frame.present();
@@ -411,7 +535,7 @@ pub struct Spawner<'a> {
 
 #[cfg(not(target_arch = "wasm32"))]
 impl<'a> Spawner<'a> {
-    fn new() -> Self {
+    pub fn new() -> Self {
         Self {
             executor: async_executor::LocalExecutor::new(),
         }
@@ -442,10 +566,317 @@ impl Spawner {
     }
 }
 
+fn configure_or_exit<E: EngineBase>(
+    example: &mut E,
+    args: &SimArgs,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+) {
+    let configured = example.configure(args, device, queue).and_then(|()| match args.unused()[..] {
+        [] => Ok(()),
+        ref unused => anyhow::bail!("unknown option --{}", unused.join(", --")),
+    });
+    if let Err(err) = configured {
+        eprintln!("error: {:#}", err);
+        std::process::exit(2);
+    }
+}
+
 #[cfg(not(target_arch = "wasm32"))]
-pub fn run<E: EngineBase>() {
-    let setup = pollster::block_on(setup::<E>());
-    start::<E>(setup);
+pub fn run<E: EngineBase>(size: Option<(u32, u32)>, args: SimArgs) {
+    let setup = pollster::block_on(setup::<E>(size));
+    start::<E>(setup, args);
+}
+
+/// Settings for [`run_headless`].
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Debug, Clone)]
+pub struct HeadlessConfig {
+    pub width: u32,
+    pub height: u32,
+    /// Number of frames to render before returning.
+    pub frames: u32,
+    /// Ask for wgpu's fallback adapter (a software rasterizer such as lavapipe or WARP),
+    /// for machines without a usable GPU.
+    pub force_fallback_adapter: bool,
+    /// Render into a `Rgba16Float` target instead of 8-bit sRGB, so EXR captures keep the full range.
+    pub hdr: bool,
+    /// Step simulations by `1 / fps` seconds per frame, see [`EngineBase::set_fixed_timestep`].
+    /// Also the frame rate written into video captures.
+    pub fps: Option<f32>,
+    /// Render the image in tiles of at most this many pixels a side, see [`Tile`]. Images larger
+    /// than the device allows are tiled even without it.
+    pub tile_size: Option<u32>,
+    pub capture: Option<HeadlessCapture>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Default for HeadlessConfig {
+    fn default() -> Self {
+        Self {
+            width: 800,
+            height: 600,
+            frames: 100,
+            force_fallback_adapter: false,
+            hdr: false,
+            fps: None,
+            tile_size: None,
+            capture: None,
+        }
+    }
+}
+
+/// Which frames of a headless run get written to disk, and where.
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Debug, Clone)]
+pub struct HeadlessCapture {
+    /// Output path, `{frame}` is replaced by the zero-padded frame number.
+    /// The extension picks the format (see [`crate::capture::FrameData::save`]). A `.y4m` path,
+    /// or `-` for stdout, collects all captured frames into one video stream instead.
+    pub path: String,
+    /// Capture every `every`-th frame, counting so that the last frame of the run is included
+    /// when `frames` is a multiple of it. `0` captures only the last frame, or every frame of a
+    /// video.
+    pub every: u32,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl HeadlessCapture {
+    /// Frame rate of video captures without `--fps`.
+    const DEFAULT_VIDEO_FPS: f32 = 30.0;
+
+    fn is_video(&self) -> bool {
+        self.path == "-"
+            || std::path::Path::new(&self.path)
+                .extension()
+                .is_some_and(|ext| ext.eq_ignore_ascii_case("y4m"))
+    }
+    fn should_capture(&self, frame: u32, frames: u32) -> bool {
+        match self.every {
+            0 if self.is_video() => true,
+            0 => frame + 1 == frames,
+            every => (frame + 1).is_multiple_of(every),
+        }
+    }
+    fn path_for(&self, frame: u32) -> String {
+        self.path.replace("{frame}", &format!("{:05}", frame))
+    }
+}
+
+/// Run a simulation without a window, rendering `config.frames` frames into an offscreen texture.
+///
+/// Goes through the same `init`/`resize`/`render` hooks as [`run`]; `update` is never called since
+/// there are no window events.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn run_headless<E: EngineBase>(config: HeadlessConfig, args: SimArgs) {
+    pollster::block_on(run_headless_async::<E>(config, args));
+}
+
+/// A device for `E` on the fallback adapter, for tests that run shaders. `None` on machines
+/// without one.
+#[cfg(test)]
+pub fn fallback_device<E: EngineBase>() -> Option<(wgpu::Adapter, wgpu::Device, wgpu::Queue)> {
+    pollster::block_on(async {
+        let adapter = create_instance()
+            .request_adapter(&wgpu::RequestAdapterOptions {
+                power_preference: wgpu::PowerPreference::default(),
+                force_fallback_adapter: true,
+                compatible_surface: None,
+            })
+            .await?;
+        let (device, queue) = request_device::<E>(&adapter).await;
+        Some((adapter, device, queue))
+    })
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+async fn run_headless_async<E: EngineBase>(headless_config: HeadlessConfig, args: SimArgs) {
+    let instance = create_instance();
+    let adapter = instance
+        .request_adapter(&wgpu::RequestAdapterOptions {
+            power_preference: wgpu::util::power_preference_from_env().unwrap_or_default(),
+            force_fallback_adapter: headless_config.force_fallback_adapter,
+            compatible_surface: None,
+        })
+        .await
+        .expect("No suitable GPU adapters found on the system!");
+
+    // Stdout may be carrying video, see `HeadlessCapture`.
+    let adapter_info = adapter.get_info();
+    eprintln!(
+        "Using {} ({:?}, {:?}) headless",
+        adapter_info.name, adapter_info.backend, adapter_info.device_type
+    );
+
+    let (device, queue) = request_device::<E>(&adapter).await;
+    let spawner = Spawner::new();
+
+    let format = if headless_config.hdr {
+        wgpu::TextureFormat::Rgba16Float
+    } else {
+        wgpu::TextureFormat::Rgba8UnormSrgb
+    };
+    let image_size = (headless_config.width.max(1), headless_config.height.max(1));
+    let max_size = device.limits().max_texture_dimension_2d;
+    let tile_size = match headless_config.tile_size {
+        Some(size) if size > max_size => {
+            eprintln!("error: --tile-size is limited to {} on this device", max_size);
+            std::process::exit(2);
+        }
+        Some(size) => Some(size),
+        None if image_size.0 > max_size || image_size.1 > max_size => {
+            log::info!("{}x{} is larger than the device allows, rendering in tiles", image_size.0, image_size.1);
+            Some(max_size)
+        }
+        None => None,
+    };
+    let target_size = match tile_size {
+        Some(size) => (image_size.0.min(size), image_size.1.min(size)),
+        None => image_size,
+    };
+    let config = wgpu::SurfaceConfiguration {
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+        format,
+        width: target_size.0,
+        height: target_size.1,
+        present_mode: wgpu::PresentMode::Fifo,
+        alpha_mode: wgpu::CompositeAlphaMode::Opaque,
+        view_formats: vec![format],
+    };
+    let target = OffscreenTarget::new(&device, format, (config.width, config.height));
+
+    log::info!("Initializing the example...");
+    let mut example = E::init(&config, &adapter, &device, &queue);
+    if let Some(fps) = headless_config.fps {
+        example.set_fixed_timestep(1.0 / fps);
+    }
+    configure_or_exit(&mut example, &args, &device, &queue);
+    example.resize(&config, &device, &queue);
+
+    if tile_size.is_some() {
+        let tiles = TiledRender {
+            image_size,
+            tile_size: target_size,
+            frames: headless_config.frames,
+        };
+        if let Err(err) = tiles.run(&mut example, headless_config.capture.as_ref(), &target, &device, &queue, &spawner) {
+            eprintln!("error: {:#}", err);
+            std::process::exit(2);
+        }
+        return;
+    }
+
+    let mut video = headless_config.capture.as_ref().filter(|it| it.is_video()).map(|capture| {
+        let fps = headless_config.fps.unwrap_or(HeadlessCapture::DEFAULT_VIDEO_FPS);
+        Y4mWriter::create(&capture.path, (config.width, config.height), fps).unwrap_or_else(|err| {
+            eprintln!("error: {:#}", err);
+            std::process::exit(2);
+        })
+    });
+
+    log::info!("Rendering {} frames...", headless_config.frames);
+    let start_inst = Instant::now();
+    for frame in 0..headless_config.frames {
+        example.render(&target.view, &device, &queue, &spawner);
+        spawner.run_until_stalled();
+
+        match &headless_config.capture {
+            Some(capture) if capture.should_capture(frame, headless_config.frames) => {
+                let written = match &mut video {
+                    Some(video) => target.read(&device, &queue).and_then(|it| video.write_frame(&it)),
+                    None => target.read(&device, &queue).and_then(|it| it.save(capture.path_for(frame))),
+                };
+                match written {
+                    Ok(()) => log::info!("Captured frame {} to {}", frame, capture.path_for(frame)),
+                    Err(err) => log::error!("Failed to capture frame {}: {:#}", frame, err),
+                }
+            }
+            _ => {
+                device.poll(wgpu::Maintain::Wait);
+            }
+        }
+    }
+
+    if let Some(video) = video {
+        if let Err(err) = video.finish() {
+            log::error!("Failed to finish the video: {:#}", err);
+        }
+    }
+    if headless_config.frames > 0 {
+        eprintln!(
+            "Avg frame time {}ms",
+            start_inst.elapsed().as_secs_f32() * 1000.0 / headless_config.frames as f32
+        );
+    }
+}
+
+/// A headless render in tiles, see [`Tile`].
+#[cfg(not(target_arch = "wasm32"))]
+struct TiledRender {
+    image_size: (u32, u32),
+    tile_size: (u32, u32),
+    /// Rendered for every tile, the last one is kept.
+    frames: u32,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl TiledRender {
+    fn run<E: EngineBase>(
+        &self,
+        example: &mut E,
+        capture: Option<&HeadlessCapture>,
+        target: &OffscreenTarget,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        spawner: &Spawner,
+    ) -> anyhow::Result<()> {
+        use anyhow::Context;
+
+        let capture = capture.context("tiled renders need --capture")?;
+        let (columns, rows) = (
+            self.image_size.0.div_ceil(self.tile_size.0),
+            self.image_size.1.div_ceil(self.tile_size.1),
+        );
+        // Every tile has to show the same moment.
+        example.set_fixed_timestep(0.0);
+
+        log::info!(
+            "Rendering {}x{} in {} tiles of {}x{}, {} frames each...",
+            self.image_size.0,
+            self.image_size.1,
+            columns * rows,
+            self.tile_size.0,
+            self.tile_size.1,
+            self.frames
+        );
+        let start_inst = Instant::now();
+        let mut image: Option<FrameData> = None;
+        for row in 0..rows {
+            for column in 0..columns {
+                let tile = Tile {
+                    image: self.image_size,
+                    offset: (column * self.tile_size.0, row * self.tile_size.1),
+                };
+                if !example.set_tile(tile) {
+                    anyhow::bail!("{} can't render in tiles", E::title());
+                }
+                for _ in 0..self.frames.max(1) {
+                    example.render(&target.view, device, queue, spawner);
+                    spawner.run_until_stalled();
+                }
+                let frame = target.read(device, queue)?;
+                image
+                    .get_or_insert_with(|| frame.blank(self.image_size))
+                    .paste(&frame, tile.offset)?;
+                log::info!("Rendered tile {} of {}", row * columns + column + 1, columns * rows);
+            }
+        }
+
+        let path = capture.path_for(0);
+        image.context("no tiles rendered")?.save(&path)?;
+        eprintln!("Rendered {} in {:.1} s", path, start_inst.elapsed().as_secs_f32());
+        Ok(())
+    }
 }
 
 #[cfg(target_arch = "wasm32")]
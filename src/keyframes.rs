@@ -0,0 +1,217 @@
+//! Keyframed values for scripted playback, like camera paths and parameter sweeps.
+//!
+//! Scripts are JSON, RON or TOML files, see [`load`]. Keyframes carry a `time` in seconds and any
+//! subset of the animated values, each value is interpolated between the keyframes that set it and
+//! held before the first and after the last one.
+
+use std::path::Path;
+
+use anyhow::Context;
+use serde::{de::DeserializeOwned, Deserialize};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum Interpolation {
+    /// Jump to each keyframe's value when its time comes.
+    Step,
+    #[default]
+    Linear,
+    /// Smooth curve through the keyframes (uniform Catmull-Rom).
+    CatmullRom,
+}
+impl std::str::FromStr for Interpolation {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "step" => Ok(Self::Step),
+            "linear" => Ok(Self::Linear),
+            "catmull-rom" => Ok(Self::CatmullRom),
+            _ => Err("expected one of step, linear, catmull-rom".to_string()),
+        }
+    }
+}
+impl TryFrom<String> for Interpolation {
+    type Error = String;
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// Values that can be interpolated, as weighted sums of keyframe values.
+pub trait Keyable: Copy {
+    fn weighted(terms: &[(f32, Self)]) -> Self;
+}
+impl Keyable for f32 {
+    fn weighted(terms: &[(f32, Self)]) -> Self {
+        terms.iter().map(|(w, v)| w * v).sum()
+    }
+}
+impl<const N: usize> Keyable for [f32; N] {
+    fn weighted(terms: &[(f32, Self)]) -> Self {
+        std::array::from_fn(|i| terms.iter().map(|(w, v)| w * v[i]).sum())
+    }
+}
+
+/// One value over time, from the keyframes that set it.
+#[derive(Debug, Clone)]
+pub struct Track<T> {
+    /// Sorted by time.
+    keys: Vec<(f32, T)>,
+}
+
+impl<T: Copy> Track<T> {
+    pub fn new<K>(keyframes: &[K], time: impl Fn(&K) -> f32, value: impl Fn(&K) -> Option<T>) -> Self {
+        let mut keys: Vec<_> = keyframes.iter().filter_map(|k| Some((time(k), value(k)?))).collect();
+        keys.sort_by(|a, b| a.0.total_cmp(&b.0));
+        Self { keys }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// Time of the last keyframe.
+    pub fn end(&self) -> f32 {
+        self.keys.last().map_or(0.0, |(t, _)| *t)
+    }
+
+    /// Neighbouring keyframe indices around `t` and how far `t` is between them. `None` if the
+    /// track is empty.
+    fn segment(&self, t: f32) -> Option<(usize, usize, f32)> {
+        let last = self.keys.len().checked_sub(1)?;
+        let next = self.keys.partition_point(|(key_t, _)| *key_t <= t);
+        Some(match next {
+            0 => (0, 0, 0.0),
+            _ if next > last => (last, last, 0.0),
+            _ => {
+                let (t0, t1) = (self.keys[next - 1].0, self.keys[next].0);
+                (next - 1, next, (t - t0) / (t1 - t0))
+            }
+        })
+    }
+
+    /// The value of the last keyframe at or before `t`.
+    pub fn step(&self, t: f32) -> Option<T> {
+        let (i, _, _) = self.segment(t)?;
+        Some(self.keys[i].1)
+    }
+
+    /// Interpolate between the neighbouring keyframes with `interpolate(a, b, fraction)`.
+    pub fn sample_with(&self, t: f32, interpolate: impl Fn(T, T, f32) -> T) -> Option<T> {
+        let (i, j, f) = self.segment(t)?;
+        Some(interpolate(self.keys[i].1, self.keys[j].1, f))
+    }
+}
+
+impl<T: Keyable> Track<T> {
+    pub fn sample(&self, t: f32, interpolation: Interpolation) -> Option<T> {
+        let (i, j, f) = self.segment(t)?;
+        let value = |i: usize| self.keys[i].1;
+        Some(match interpolation {
+            Interpolation::Step => value(i),
+            Interpolation::Linear => T::weighted(&[(1.0 - f, value(i)), (f, value(j))]),
+            Interpolation::CatmullRom => {
+                let before = i.saturating_sub(1);
+                let after = (j + 1).min(self.keys.len() - 1);
+                let (f2, f3) = (f * f, f * f * f);
+                T::weighted(&[
+                    (0.5 * (-f3 + 2.0 * f2 - f), value(before)),
+                    (0.5 * (3.0 * f3 - 5.0 * f2 + 2.0), value(i)),
+                    (0.5 * (-3.0 * f3 + 4.0 * f2 + f), value(j)),
+                    (0.5 * (f3 - f2), value(after)),
+                ])
+            }
+        })
+    }
+}
+
+/// Read a script, as RON or TOML for `.ron` and `.toml` files and as JSON otherwise. RON values
+/// that may be left out don't need a `Some(...)` around them.
+pub fn load<T: DeserializeOwned>(path: impl AsRef<Path>) -> anyhow::Result<T> {
+    let path = path.as_ref();
+    let text = std::fs::read_to_string(path).with_context(|| format!("failed to read {}", path.display()))?;
+    let ext = path.extension().and_then(|it| it.to_str()).map(str::to_ascii_lowercase);
+    match ext.as_deref() {
+        Some("ron") => ron::Options::default()
+            .with_default_extension(ron::extensions::Extensions::IMPLICIT_SOME)
+            .from_str(&text)
+            .map_err(anyhow::Error::from),
+        Some("toml") => toml::from_str(&text).map_err(anyhow::Error::from),
+        _ => serde_json::from_str(&text).map_err(anyhow::Error::from),
+    }
+    .with_context(|| format!("failed to parse {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tracks_interpolate_between_their_own_keyframes() {
+        // The middle keyframe doesn't set the value, so it doesn't take part.
+        let keyframes = [(0.0, Some(1.0)), (1.0, None), (2.0, Some(3.0)), (4.0, Some(3.0))];
+        let track = Track::new(&keyframes, |k| k.0, |k| k.1);
+        assert_eq!(track.end(), 4.0);
+
+        assert_eq!(track.sample(-1.0, Interpolation::Linear), Some(1.0));
+        assert_eq!(track.sample(1.0, Interpolation::Linear), Some(2.0));
+        assert_eq!(track.sample(1.0, Interpolation::Step), Some(1.0));
+        assert_eq!(track.sample(9.0, Interpolation::CatmullRom), Some(3.0));
+        // Catmull-Rom passes through the keyframes, and overshoots a little before the plateau.
+        assert_eq!(track.sample(2.0, Interpolation::CatmullRom), Some(3.0));
+        assert!(track.sample(2.5, Interpolation::CatmullRom).unwrap() > 3.0);
+
+        let empty = Track::<f32>::new(&keyframes, |k| k.0, |_| None);
+        assert_eq!(empty.sample(0.0, Interpolation::Linear), None);
+    }
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    #[serde(deny_unknown_fields)]
+    struct Script {
+        interpolation: Interpolation,
+        keyframes: Vec<Keyframe>,
+    }
+    #[derive(Debug, PartialEq, Deserialize)]
+    #[serde(deny_unknown_fields)]
+    struct Keyframe {
+        time: f32,
+        position: Option<[f32; 3]>,
+        c: Option<f32>,
+    }
+
+    #[test]
+    fn json_ron_and_toml_read_the_same() {
+        let json = r#"{
+            "interpolation": "catmull-rom",
+            "keyframes": [{ "time": 0, "position": [0, -15, 2] }, { "time": 8.5, "c": 0.3 }]
+        }"#;
+        let ron = r#"(
+            interpolation: "catmull-rom",
+            keyframes: [(time: 0, position: (0, -15, 2)), (time: 8.5, c: 0.3)],
+        )"#;
+        let toml = r#"
+            interpolation = "catmull-rom"
+            [[keyframes]]
+            time = 0
+            position = [0, -15, 2]
+            [[keyframes]]
+            time = 8.5
+            c = 0.3
+        "#;
+        let expected = Script {
+            interpolation: Interpolation::CatmullRom,
+            keyframes: vec![
+                Keyframe { time: 0.0, position: Some([0.0, -15.0, 2.0]), c: None },
+                Keyframe { time: 8.5, position: None, c: Some(0.3) },
+            ],
+        };
+        let dir = std::env::temp_dir();
+        for (ext, text) in [("json", json), ("RON", ron), ("toml", toml)] {
+            let path = dir.join(format!("the-sim-script-{}.{}", std::process::id(), ext));
+            std::fs::write(&path, text).unwrap();
+            let script = load::<Script>(&path);
+            std::fs::remove_file(&path).unwrap();
+            assert_eq!(script.unwrap(), expected, "{}", ext);
+        }
+    }
+}
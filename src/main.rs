@@ -1,12 +1,28 @@
+mod capture;
+mod cli;
 mod engine_base;
+mod keyframes;
+mod registry;
+mod snapshot;
 mod test_gpu {
     pub mod core;
 }
 mod wave {
     pub mod core;
+    pub mod energy;
+    pub mod initial;
+    pub mod params;
+    #[cfg(test)]
+    pub mod reference;
+    pub mod sources;
 }
 mod blackhole_gtx {
+    pub mod accumulate;
+    pub mod camera;
     pub mod core;
+    pub mod geodesic;
+    pub mod observer;
+    pub mod path;
 }
 mod util;
 mod texture;
@@ -16,6 +32,47 @@ mod buffer;
 mod new_abstractions;
 
 fn main() {
-    // engine_base::run::<wave::core::Wave>();
-    engine_base::run::<blackhole_gtx::core::BlackholeGtx>();
+    env_logger::init();
+
+    let args = match cli::Args::parse(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(err) => {
+            eprintln!("error: {:#}\n\n{}", err, cli::USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    if args.help {
+        print!("{}", cli::USAGE);
+        // Only the named simulation's, if there is one.
+        let sims = registry::SIMULATIONS
+            .iter()
+            .filter(|sim| args.simulation.as_deref().is_none_or(|name| name == sim.name));
+        for sim in sims {
+            let usage = (sim.usage)();
+            if !usage.is_empty() {
+                print!("\nusage: the-sim {} [options]\n\n{}", sim.name, usage);
+            }
+        }
+        return;
+    }
+    if args.list {
+        for sim in registry::SIMULATIONS {
+            println!("{:<12} {}", sim.name, (sim.title)());
+        }
+        println!("\n`the-sim <simulation> --help` shows its options and keys.");
+        return;
+    }
+
+    let sim = match &args.simulation {
+        Some(name) => match registry::find(name) {
+            Some(sim) => sim,
+            None => {
+                eprintln!("error: unknown simulation {:?}, see --list", name);
+                std::process::exit(2);
+            }
+        },
+        None => &registry::SIMULATIONS[0],
+    };
+    (sim.launch)(args.launch);
 }
@@ -1,10 +1,12 @@
 use std::{
+    future::Future,
     marker::PhantomData,
     mem::size_of,
     num::{NonZeroU32, NonZeroU64},
     ops::{Bound, RangeBounds},
 };
 
+use anyhow::Context;
 use bytemuck::{Pod, Zeroable};
 use image::GenericImageView;
 use wgpu::util::DeviceExt;
@@ -137,6 +139,7 @@ macro_rules! render_pipeline_info {
                 pipeline: wgpu::RenderPipeline,
             }
             impl<'device> $name {
+                #[allow(clippy::too_many_arguments)]
                 fn new(
                     device: &wgpu::Device,
                     vertex: (wgpu::ShaderModule, &'static str),
@@ -177,7 +180,7 @@ macro_rules! render_pipeline_info {
                         }),
                     }
                 }
-                #[allow(unused)] // `i` can be unused, not much we can do tho (within reason).
+                #[allow(unused, clippy::too_many_arguments)] // `i` can be unused, not much we can do tho (within reason).
                 fn draw_indexed<
                     'data,
                     'b : 'data,
@@ -310,6 +313,31 @@ impl<T: Pod + Zeroable> Buff<T> {
     pub fn write(&self, offset: u64, data: &[T], queue: &wgpu::Queue) {
         queue.write_buffer(&self.buffer, offset, bytemuck::cast_slice(data))
     }
+    /// Read the whole buffer back to the CPU, blocking until the GPU has finished with it.
+    pub fn read(&self, device: &wgpu::Device, queue: &wgpu::Queue) -> anyhow::Result<Vec<T>> {
+        let data = self.read_async(device, queue);
+        device.poll(wgpu::Maintain::Wait);
+        pollster::block_on(data)
+    }
+    /// Queue a copy of the whole buffer to the CPU. The future resolves once the device has been
+    /// polled past the copy, e.g. by the render loop when spawned on the `Spawner`.
+    pub fn read_async(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> impl Future<Output = anyhow::Result<Vec<T>>> {
+        let size = self.buffer.size();
+        let staging = create_staging_buffer(device, size);
+        let mut enc = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        enc.copy_buffer_to_buffer(&self.buffer, 0, &staging, 0, size);
+        queue.submit(Some(enc.finish()));
+
+        map_staging_buffer(staging, |bytes| {
+            let mut data = vec![T::zeroed(); bytes.len() / size_of::<T>()];
+            bytemuck::cast_slice_mut(&mut data).copy_from_slice(bytes);
+            data
+        })
+    }
     pub fn slice<S: RangeBounds<u64>>(&self, range: S) -> BuffSlice<T> {
         let elt_size: u64 = size_of::<T>() as u64;
         let len = self.buffer.size() / elt_size;
@@ -334,6 +362,98 @@ impl<T: Pod + Zeroable> Buff<T> {
     }
 }
 
+fn create_staging_buffer(device: &wgpu::Device, size: u64) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("readback-staging"),
+        size,
+        usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
+
+/// Map a staging buffer filled by an already submitted copy and hand its contents to `f`.
+fn map_staging_buffer<R>(
+    staging: wgpu::Buffer,
+    f: impl FnOnce(&[u8]) -> R,
+) -> impl Future<Output = anyhow::Result<R>> {
+    let (sender, receiver) = futures_intrusive::channel::shared::oneshot_channel();
+    staging
+        .slice(..)
+        .map_async(wgpu::MapMode::Read, move |res| sender.send(res).unwrap_or(()));
+
+    async move {
+        receiver
+            .receive()
+            .await
+            .context("readback callback was dropped")?
+            .context("mapping readback buffer")?;
+        let out = f(&staging.slice(..).get_mapped_range());
+        staging.unmap();
+        Ok(out)
+    }
+}
+
+/// Read mip level 0 of a 2D texture back to the CPU, blocking until the copy is done. Rows are
+/// returned tightly packed, with the `COPY_BYTES_PER_ROW_ALIGNMENT` padding stripped.
+pub fn read_texture_rows(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    texture: &wgpu::Texture,
+) -> anyhow::Result<Vec<u8>> {
+    let data = read_texture_rows_async(device, queue, texture)?;
+    device.poll(wgpu::Maintain::Wait);
+    pollster::block_on(data)
+}
+
+/// Non-blocking version of [`read_texture_rows`], see [`Buff::read_async`].
+pub fn read_texture_rows_async(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    texture: &wgpu::Texture,
+) -> anyhow::Result<impl Future<Output = anyhow::Result<Vec<u8>>>> {
+    let format = texture.format();
+    let size = wgpu::Extent3d {
+        depth_or_array_layers: 1,
+        ..texture.size()
+    };
+    let bytes_per_pixel = format
+        .block_size(None)
+        .with_context(|| format!("cannot read back {:?} textures", format))?;
+    let unpadded_bytes_per_row = size.width * bytes_per_pixel;
+    let padded_bytes_per_row = unpadded_bytes_per_row
+        .div_ceil(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT)
+        * wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+
+    let staging = create_staging_buffer(device, padded_bytes_per_row as u64 * size.height as u64);
+    let mut enc = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+    enc.copy_texture_to_buffer(
+        wgpu::ImageCopyTexture {
+            texture,
+            mip_level: 0,
+            origin: wgpu::Origin3d::ZERO,
+            aspect: wgpu::TextureAspect::All,
+        },
+        wgpu::ImageCopyBuffer {
+            buffer: &staging,
+            layout: wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(padded_bytes_per_row),
+                rows_per_image: Some(size.height),
+            },
+        },
+        size,
+    );
+    queue.submit(Some(enc.finish()));
+
+    Ok(map_staging_buffer(staging, move |bytes| {
+        let mut data = Vec::with_capacity((unpadded_bytes_per_row * size.height) as usize);
+        for row in bytes.chunks_exact(padded_bytes_per_row as usize) {
+            data.extend_from_slice(&row[..unpadded_bytes_per_row as usize]);
+        }
+        data
+    }))
+}
+
 pub struct BuffSlice<'a, T: Pod + Zeroable> {
     buff: &'a Buff<T>,
     offset: u64,
@@ -381,6 +501,21 @@ impl TextureDimension for _2D {
     }
 }
 
+/// Six square layers sampled by direction, the faces are in wgpu's +X, -X, +Y, -Y, +Z, -Z order.
+pub struct _Cube;
+impl TextureDimension for _Cube {
+    type ExtentND = u32;
+    const DIMENSION: wgpu::TextureDimension = wgpu::TextureDimension::D2;
+    const VIEW_DIMENSION: wgpu::TextureViewDimension = wgpu::TextureViewDimension::Cube;
+    fn convert_extent(size: Self::ExtentND) -> wgpu::Extent3d {
+        wgpu::Extent3d {
+            width: size,
+            height: size,
+            depth_or_array_layers: 6,
+        }
+    }
+}
+
 pub trait TexInfoType {}
 pub struct TISampler;
 pub struct TITexture;
@@ -466,28 +601,55 @@ pub struct Tex<Dim: TextureDimension> {
 
 impl<Dim: TextureDimension> Tex<Dim> {
     pub fn create_uninit(label: &str, extent_nd: Dim::ExtentND, device: &wgpu::Device) -> Self {
+        Self::create_uninit_with_format(label, extent_nd, wgpu::TextureFormat::Rgba8Unorm, device)
+    }
+    /// Like [`Tex::create_uninit`], but for render targets that need more than 8 bits per channel.
+    /// Storage bindings still assume `Rgba8Unorm`.
+    pub fn create_uninit_with_format(
+        label: &str,
+        extent_nd: Dim::ExtentND,
+        format: wgpu::TextureFormat,
+        device: &wgpu::Device,
+    ) -> Self {
         let texture = device.create_texture(&wgpu::TextureDescriptor {
             label: Some(label),
             size: Dim::convert_extent(extent_nd),
             mip_level_count: 1,
             sample_count: 1,
             dimension: Dim::DIMENSION,
-            format: wgpu::TextureFormat::Rgba8Unorm,
+            format,
             usage: wgpu::TextureUsages::TEXTURE_BINDING
                 | wgpu::TextureUsages::COPY_DST
                 | wgpu::TextureUsages::COPY_SRC
-                | wgpu::TextureUsages::STORAGE_BINDING, // TODO make efficient
+                | wgpu::TextureUsages::RENDER_ATTACHMENT
+                | if format == wgpu::TextureFormat::Rgba8Unorm {
+                    wgpu::TextureUsages::STORAGE_BINDING
+                } else {
+                    wgpu::TextureUsages::empty()
+                }, // TODO make efficient
             view_formats: &[],
         });
         let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(Dim::VIEW_DIMENSION),
             ..Default::default()
         });
+        // Formats like `Rgba32Float` can't be filtered, their sampler has to be bound as
+        // `SamplerBindingType::NonFiltering`.
+        let filter = if format
+            .guaranteed_format_features(device.features())
+            .flags
+            .contains(wgpu::TextureFormatFeatureFlags::FILTERABLE)
+        {
+            wgpu::FilterMode::Linear
+        } else {
+            wgpu::FilterMode::Nearest
+        };
         let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
             address_mode_u: wgpu::AddressMode::ClampToEdge,
             address_mode_v: wgpu::AddressMode::ClampToEdge,
             address_mode_w: wgpu::AddressMode::ClampToEdge,
-            mag_filter: wgpu::FilterMode::Linear,
-            min_filter: wgpu::FilterMode::Linear,
+            mag_filter: filter,
+            min_filter: filter,
             mipmap_filter: wgpu::FilterMode::Nearest,
             ..Default::default()
         });
@@ -523,6 +685,32 @@ impl Tex<_2D> {
         this
     }
 
+    /// Read the texture back to the CPU, blocking until the GPU has finished with it.
+    pub fn read(&self, device: &wgpu::Device, queue: &wgpu::Queue) -> anyhow::Result<image::RgbaImage> {
+        let data = self.read_async(device, queue);
+        device.poll(wgpu::Maintain::Wait);
+        pollster::block_on(data)
+    }
+    /// Non-blocking version of [`Tex::read`], see [`Buff::read_async`]. Only RGBA8 textures can be
+    /// read this way, [`crate::capture::read_frame`] handles the other formats.
+    pub fn read_async(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> impl Future<Output = anyhow::Result<image::RgbaImage>> {
+        let wgpu::Extent3d { width, height, .. } = self.texture.size();
+        let data = match self.texture.format() {
+            wgpu::TextureFormat::Rgba8Unorm | wgpu::TextureFormat::Rgba8UnormSrgb => {
+                read_texture_rows_async(device, queue, &self.texture)
+            }
+            format => Err(anyhow::anyhow!("{} is {:?}, only RGBA8 textures can be read as an image", self.label, format)),
+        };
+        async move {
+            image::RgbaImage::from_raw(width, height, data?.await?)
+                .context("texture size mismatch")
+        }
+    }
+
     pub fn write_image(&self, img: image::DynamicImage, queue: &wgpu::Queue) {
         let data_raw = img.to_rgba8();
         let (width, height) = img.dimensions();
@@ -544,6 +732,55 @@ impl Tex<_2D> {
     }
 }
 
+impl Tex<_Cube> {
+    /// Create a cube map from its six faces, see [`_Cube`] for the order. The faces must all be
+    /// square and of the same size.
+    pub fn create(
+        label: &str,
+        faces: [image::DynamicImage; 6],
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> anyhow::Result<Self> {
+        let size = faces[0].width();
+        if let Some(face) = faces.iter().find(|it| it.dimensions() != (size, size)) {
+            anyhow::bail!(
+                "cube map faces must be square and the same size, got {:?} and {:?}",
+                faces[0].dimensions(),
+                face.dimensions()
+            );
+        }
+        let this = Self::create_uninit(label, size, device);
+        for (layer, face) in faces.into_iter().enumerate() {
+            this.write_face(layer as u32, face, queue);
+        }
+        Ok(this)
+    }
+
+    pub fn write_face(&self, layer: u32, img: image::DynamicImage, queue: &wgpu::Queue) {
+        let data_raw = img.to_rgba8();
+        let (width, height) = img.dimensions();
+        queue.write_texture(
+            wgpu::ImageCopyTextureBase {
+                texture: &self.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d { x: 0, y: 0, z: layer },
+                aspect: wgpu::TextureAspect::All,
+            },
+            &data_raw,
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(width * 4),
+                rows_per_image: Some(height),
+            },
+            wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+        );
+    }
+}
+
 pub trait ZSTValue {
     const IT: Self;
 }
@@ -559,3 +796,27 @@ impl<D: TextureDimension, T: TexInfoType> ZSTValue for TexInfo<D, T> {
 pub trait VertexLayoutInfo {
     const ATTRIBUTES: &'static [wgpu::VertexAttribute];
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{engine_base, test_gpu::core::TestGPU};
+
+    #[test]
+    fn texture_read_back() {
+        let Some((_, device, queue)) = engine_base::fallback_device::<TestGPU>() else {
+            eprintln!("No fallback adapter, skipping");
+            return;
+        };
+        // Rows of 37 texels need padding to the copy alignment.
+        let img = image::RgbaImage::from_fn(37, 5, |x, y| image::Rgba([x as u8, y as u8, (x * y) as u8, 255]));
+        let tex = Tex::<_2D>::create((img.clone().into(), "read-back"), &device, &queue);
+        assert_eq!(tex.read(&device, &queue).unwrap(), img);
+
+        for format in [wgpu::TextureFormat::Rgba16Float, wgpu::TextureFormat::Bgra8Unorm] {
+            let tex = Tex::<_2D>::create_uninit_with_format("read-back", (4, 4), format, &device);
+            let err = tex.read(&device, &queue).unwrap_err();
+            assert!(err.to_string().contains("only RGBA8"), "{:#}", err);
+        }
+    }
+}
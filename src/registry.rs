@@ -0,0 +1,46 @@
+use crate::{
+    blackhole_gtx::core::BlackholeGtx,
+    cli::LaunchOptions,
+    engine_base::{self, EngineBase},
+    test_gpu::core::TestGPU,
+    wave::core::Wave,
+};
+
+/// A simulation that can be picked by name from the command line.
+pub struct Simulation {
+    pub name: &'static str,
+    pub title: fn() -> &'static str,
+    pub flags: fn() -> &'static [&'static str],
+    pub usage: fn() -> &'static str,
+    pub launch: fn(LaunchOptions),
+}
+
+impl Simulation {
+    const fn of<E: EngineBase>(name: &'static str) -> Self {
+        Self {
+            name,
+            title: E::title,
+            flags: E::flags,
+            usage: E::usage,
+            launch: launch::<E>,
+        }
+    }
+}
+
+/// All registered simulations, the first one is started when no name is given.
+pub const SIMULATIONS: &[Simulation] = &[
+    Simulation::of::<BlackholeGtx>("blackhole"),
+    Simulation::of::<Wave>("wave"),
+    Simulation::of::<TestGPU>("test-gpu"),
+];
+
+pub fn find(name: &str) -> Option<&'static Simulation> {
+    SIMULATIONS.iter().find(|sim| sim.name == name)
+}
+
+fn launch<E: EngineBase>(options: LaunchOptions) {
+    match options.headless {
+        Some(config) => engine_base::run_headless::<E>(config, options.sim_args),
+        None => engine_base::run::<E>(options.size, options.sim_args),
+    }
+}
@@ -0,0 +1,16 @@
+// Draws a texture onto a target of the same size, see `capture::Blit`.
+
+@group(0) @binding(0)
+var source: texture_2d<f32>;
+
+// One triangle covering the whole target.
+@vertex
+fn vs_main(@builtin(vertex_index) i: u32) -> @builtin(position) vec4<f32> {
+    let uv = vec2<f32>(f32((i << 1u) & 2u), f32(i & 2u));
+    return vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+}
+
+@fragment
+fn fs_main(@builtin(position) p: vec4<f32>) -> @location(0) vec4<f32> {
+    return textureLoad(source, vec2<i32>(p.xy), 0);
+}
@@ -0,0 +1,160 @@
+//! Snapshots of a simulation's state, to resume an experiment later or share it, see `--load`.
+//!
+//! A snapshot is a header on one line of JSON, naming the simulation and carrying the grid size
+//! and the parameters, followed by the raw bytes of the state buffers. The payload is whatever
+//! `Pod` type the simulation keeps its state in, so it only loads on a machine of the same
+//! endianness.
+
+use std::{fs::File, io::Write, path::Path};
+
+use anyhow::Context;
+use bytemuck::Pod;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+/// Bumped whenever the layout of a payload changes.
+const VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Header {
+    pub version: u32,
+    /// Which simulation saved it, as on the command line.
+    pub sim: String,
+    /// Grid points, or pixels, along x and y.
+    pub size: [u32; 2],
+    /// Simulated time, for the simulations that keep one.
+    #[serde(default)]
+    pub time: f32,
+    /// Up to the simulation, `null` if it has none apart from the payload.
+    #[serde(default)]
+    pub params: serde_json::Value,
+    /// Length of the payload, to tell a truncated file.
+    pub payload_bytes: u64,
+}
+
+impl Header {
+    pub fn new(sim: &str, (width, height): (u32, u32)) -> Self {
+        Self {
+            version: VERSION,
+            sim: sim.to_string(),
+            size: [width, height],
+            time: 0.0,
+            params: serde_json::Value::Null,
+            payload_bytes: 0,
+        }
+    }
+}
+
+pub struct Snapshot {
+    pub header: Header,
+    payload: Vec<u8>,
+}
+
+impl Snapshot {
+    pub fn new<T: Pod>(header: Header, payload: &[T]) -> Self {
+        let payload = bytemuck::cast_slice(payload).to_vec();
+        Self {
+            header: Header {
+                payload_bytes: payload.len() as u64,
+                ..header
+            },
+            payload,
+        }
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        let write = || -> anyhow::Result<()> {
+            let mut header = serde_json::to_vec(&self.header)?;
+            header.push(b'\n');
+            let mut file = File::create(path)?;
+            file.write_all(&header)?;
+            file.write_all(&self.payload)?;
+            Ok(())
+        };
+        write().with_context(|| format!("failed to save a snapshot to {}", path.display()))
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let bytes = std::fs::read(path).with_context(|| format!("failed to read {}", path.display()))?;
+        let end = bytes
+            .iter()
+            .position(|b| *b == b'\n')
+            .with_context(|| format!("{} is not a snapshot", path.display()))?;
+        let header: Header = serde_json::from_slice(&bytes[..end])
+            .with_context(|| format!("{} is not a snapshot", path.display()))?;
+        if header.version != VERSION {
+            anyhow::bail!(
+                "{} is a version {} snapshot, only version {} is supported",
+                path.display(),
+                header.version,
+                VERSION
+            );
+        }
+        let payload = bytes[end + 1..].to_vec();
+        if payload.len() as u64 != header.payload_bytes {
+            anyhow::bail!(
+                "{} has {} bytes of state, its header says {}",
+                path.display(),
+                payload.len(),
+                header.payload_bytes
+            );
+        }
+        Ok(Self { header, payload })
+    }
+
+    /// Fails unless `sim` saved the snapshot.
+    pub fn expect_sim(&self, sim: &str) -> anyhow::Result<()> {
+        if self.header.sim != sim {
+            anyhow::bail!("this is a snapshot of {}, not {}", self.header.sim, sim);
+        }
+        Ok(())
+    }
+
+    pub fn params<T: DeserializeOwned>(&self) -> anyhow::Result<T> {
+        serde_json::from_value(self.header.params.clone()).context("invalid parameters in the snapshot")
+    }
+
+    /// The state, copied out since the bytes may not be aligned for `T`.
+    pub fn payload<T: Pod>(&self) -> anyhow::Result<Vec<T>> {
+        if !self.payload.len().is_multiple_of(std::mem::size_of::<T>()) {
+            anyhow::bail!(
+                "the state in the snapshot is {} bytes, not a whole number of {} byte values",
+                self.payload.len(),
+                std::mem::size_of::<T>()
+            );
+        }
+        Ok(bytemuck::pod_collect_to_vec(&self.payload))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let path = std::env::temp_dir().join(format!("the-sim-snapshot-{}", std::process::id()));
+        let mut header = Header::new("test", (3, 1));
+        header.time = 1.5;
+        header.params = serde_json::json!({ "c": 0.25 });
+        let payload: [[f32; 2]; 3] = [[1.0, -1.0], [0.5, 2.0], [f32::MAX, 0.0]];
+        Snapshot::new(header, &payload).save(&path).unwrap();
+
+        let snapshot = Snapshot::load(&path).unwrap();
+        assert!(snapshot.expect_sim("test").is_ok());
+        assert!(snapshot.expect_sim("other").is_err());
+        assert_eq!(snapshot.header.size, [3, 1]);
+        assert_eq!(snapshot.header.time, 1.5);
+        assert_eq!(snapshot.params::<serde_json::Value>().unwrap()["c"], 0.25);
+        assert_eq!(snapshot.payload::<[f32; 2]>().unwrap(), payload);
+        assert!(snapshot.payload::<[f32; 4]>().is_err());
+
+        // Cut off in the middle of the payload.
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::write(&path, &bytes[..bytes.len() - 4]).unwrap();
+        assert!(Snapshot::load(&path).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -7,6 +7,10 @@ macro_rules! load_img {
     ($loc: tt) => {
         image::load_from_memory(include_bytes!($loc)).map(|it| (it, $loc))
     };
+    // Load from a path at runtime rather than embedding the image, e.g. for user supplied files.
+    (file $loc: expr) => {
+        image::open($loc).map(|it| (it, $loc))
+    };
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -1,30 +1,175 @@
+use anyhow::Context;
 use bytemuck::{Pod, Zeroable};
+use serde::Deserialize;
 use wgpu::{include_wgsl, vertex_attr_array};
 use winit::event::VirtualKeyCode;
 
 use crate::{
     bind_group_info,
-    engine_base::EngineBase,
-    include_glsl,
+    cli::SimArgs,
+    engine_base::{EngineBase, FrameClock},
+    include_glsl, load_img,
+    keyframes::{self, Interpolation, Track},
     new_abstractions::{
         Buff, BuffInfo, TISampler, TIStorageTexture, TITexture, Tex, TexInfo, VertexLayoutInfo,
         ZSTValue, _2D,
     }, compute_pipeline_info, render_pipeline_info,
+    snapshot::{Header, Snapshot},
 };
 
-const SIZE: u32 = 256;
+use super::{
+    energy::{self, Energy, EnergyLog},
+    initial::Initial,
+    params::{Boundary, ParamsFile, Scheme, Stencil, WaveParams},
+    sources::{Brush, Emitters, SourceData},
+};
+
+/// Grid points along each side, see `--grid`.
+const DEFAULT_GRID_SIZE: u32 = 256;
+/// The compute shader leaves the outermost points alone, this leaves a few to move.
+const MIN_GRID_SIZE: u32 = 4;
+/// Has to match `@workgroup_size` in `compute.wgsl`.
 const WORKGROUP_SIZE: u32 = 16;
+/// Factor the keys change `c`, `dt` and `du` by.
+const PARAM_STEP: f32 = 1.1;
+const DAMPING_STEP: f32 = 0.1;
+/// Added per frame at the middle of the brush, to the displacement and the velocity.
+const BRUSH_DISPLACEMENT: f32 = 0.1;
+const BRUSH_IMPULSE: f32 = 20.0;
+/// In grid cells, the mouse wheel scales it by `BRUSH_RADIUS_STEP`.
+const BRUSH_RADIUS: f32 = 4.0;
+const BRUSH_RADIUS_STEP: f32 = 1.25;
+/// What snapshots of the wave are saved as, and where F5 and F9 go without `--load`.
+const SNAPSHOT_SIM: &str = "wave";
+const DEFAULT_SNAPSHOT: &str = "wave.snapshot";
 
 #[repr(C)]
 #[derive(Debug, Clone, Copy, Pod, Zeroable)]
-struct WavePoint {
-    x: f32,
-    v: f32,
+pub struct WavePoint {
+    pub x: f32,
+    pub v: f32,
 }
 impl VertexLayoutInfo for WavePoint {
     const ATTRIBUTES: &'static [wgpu::VertexAttribute] = &vertex_attr_array![0 => Float32x2];
 }
 
+/// Grid resolution, `256` or `512x256` on the command line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct GridSize(u32, u32);
+impl std::str::FromStr for GridSize {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse = |v: &str| v.trim().parse::<u32>().map_err(|_| format!("expected <n> or <width>x<height>, got {:?}", s));
+        match s.split_once('x') {
+            Some((width, height)) => Ok(Self(parse(width)?, parse(height)?)),
+            None => parse(s).map(|n| Self(n, n)),
+        }
+    }
+}
+
+/// Bilinearly resample `data` from a `from` sized grid to a `to` sized one covering the same area.
+/// The edge points land on the edge points, the boundary stays the boundary.
+fn resample(data: &[WavePoint], from: GridSize, to: GridSize) -> Vec<WavePoint> {
+    let at = |x: usize, y: usize| data[x + y * from.0 as usize];
+    let coordinate = |i: u32, to: u32, from: u32| {
+        let c = i as f32 * (from - 1) as f32 / (to - 1) as f32;
+        let i0 = (c.floor() as usize).min(from as usize - 2);
+        (i0, c - i0 as f32)
+    };
+    let mut out = Vec::with_capacity((to.0 * to.1) as usize);
+    for y in 0..to.1 {
+        let (y0, fy) = coordinate(y, to.1, from.1);
+        for x in 0..to.0 {
+            let (x0, fx) = coordinate(x, to.0, from.0);
+            let lerp = |f: fn(WavePoint) -> f32| {
+                let top = f(at(x0, y0)) * (1.0 - fx) + f(at(x0 + 1, y0)) * fx;
+                let bottom = f(at(x0, y0 + 1)) * (1.0 - fx) + f(at(x0 + 1, y0 + 1)) * fx;
+                top * (1.0 - fy) + bottom * fy
+            };
+            out.push(WavePoint {
+                x: lerp(|p| p.x),
+                v: lerp(|p| p.v),
+            });
+        }
+    }
+    out
+}
+
+/// A `--sweep` file, keyframed wave parameters like
+/// `{ "interpolation": "linear", "keyframes": [{ "time": 0, "c": 0.1 }, { "time": 10, "c": 0.4 }] }`.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SweepFile {
+    #[serde(default)]
+    interpolation: Interpolation,
+    keyframes: Vec<SweepKeyframe>,
+}
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SweepKeyframe {
+    time: f32,
+    c: Option<f32>,
+    dt: Option<f32>,
+    du: Option<f32>,
+    damping: Option<f32>,
+}
+
+struct Sweep {
+    interpolation: Interpolation,
+    c: Track<f32>,
+    dt: Track<f32>,
+    du: Track<f32>,
+    damping: Track<f32>,
+    time: f32,
+    /// Whether the swept values were unstable last frame, to only warn when they get there.
+    clamped: bool,
+}
+
+impl Sweep {
+    fn load(path: &str) -> anyhow::Result<Self> {
+        let file: SweepFile = keyframes::load(path)?;
+        let k = &file.keyframes;
+        let time = |k: &SweepKeyframe| k.time;
+        let this = Self {
+            interpolation: file.interpolation,
+            c: Track::new(k, time, |k| k.c),
+            dt: Track::new(k, time, |k| k.dt),
+            du: Track::new(k, time, |k| k.du),
+            damping: Track::new(k, time, |k| k.damping),
+            time: 0.0,
+            clamped: false,
+        };
+        if [&this.c, &this.dt, &this.du, &this.damping].iter().all(|it| it.is_empty()) {
+            anyhow::bail!("{} doesn't sweep anything", path);
+        }
+        Ok(this)
+    }
+
+    fn end(&self) -> f32 {
+        [&self.c, &self.dt, &self.du, &self.damping].iter().map(|it| it.end()).fold(0.0, f32::max)
+    }
+
+    /// The parameters at the current time, then move on by `dt` seconds.
+    fn advance(&mut self, dt: f32, params: &mut WaveParams) -> anyhow::Result<()> {
+        let (t, interpolation) = (self.time, self.interpolation);
+        ParamsFile {
+            c: self.c.sample(t, interpolation),
+            dt: self.dt.sample(t, interpolation),
+            du: self.du.sample(t, interpolation),
+            damping: self.damping.sample(t, interpolation),
+            ..Default::default()
+        }
+        .apply(params)?;
+        let clamped = params.enforce_cfl().is_some();
+        if clamped && !self.clamped {
+            log::warn!("The sweep got past the stability limit at t = {:.2}, clamping dt", t);
+        }
+        self.clamped = clamped;
+        self.time += dt;
+        Ok(())
+    }
+}
+
 
 bind_group_info!(Tex2DBindGroup; wgpu::ShaderStages::FRAGMENT;
     0 => (TexInfo::<_2D,TITexture>, wgpu::TextureSampleType::Float { filterable: true }),
@@ -34,9 +179,32 @@ bind_group_info!(ComputeBindGroup; wgpu::ShaderStages::COMPUTE;
     0 => (BuffInfo::<WavePoint>, wgpu::BufferBindingType::Storage { read_only: true }),
     1 => (BuffInfo::<WavePoint>, wgpu::BufferBindingType::Storage { read_only: false }),
     2 => (TexInfo::<_2D, TIStorageTexture>, wgpu::StorageTextureAccess::WriteOnly),
+    3 => (BuffInfo::<[f32; 4]>, wgpu::BufferBindingType::Storage { read_only: false }),
+    4 => (BuffInfo::<[f32; 4]>, wgpu::BufferBindingType::Storage { read_only: false }),
+);
+bind_group_info!(ParamsBindGroup; wgpu::ShaderStages::COMPUTE;
+    0 => (BuffInfo::<WaveParams>, wgpu::BufferBindingType::Uniform),
+);
+bind_group_info!(MediumBindGroup; wgpu::ShaderStages::COMPUTE;
+    0 => (TexInfo::<_2D, TITexture>, wgpu::TextureSampleType::Float { filterable: false }),
+);
+bind_group_info!(EnergyBindGroup; wgpu::ShaderStages::COMPUTE;
+    0 => (BuffInfo::<WavePoint>, wgpu::BufferBindingType::Storage { read_only: true }),
+    1 => (BuffInfo::<[f32; 4]>, wgpu::BufferBindingType::Storage { read_only: false }),
+);
+bind_group_info!(SourcesBindGroup; wgpu::ShaderStages::COMPUTE;
+    0 => (BuffInfo::<SourceData>, wgpu::BufferBindingType::Uniform),
 );
 compute_pipeline_info!(TheComputePipeline;
     0 => ComputeBindGroupInfo<'device>,
+    1 => ParamsBindGroupInfo<'device>,
+    2 => MediumBindGroupInfo<'device>,
+    3 => SourcesBindGroupInfo<'device>,
+);
+compute_pipeline_info!(EnergyPipeline;
+    0 => EnergyBindGroupInfo<'device>,
+    1 => ParamsBindGroupInfo<'device>,
+    2 => MediumBindGroupInfo<'device>,
 );
 render_pipeline_info!(TheRenderPipeline;
     0 => Tex2DBindGroupInfo<'device>,
@@ -44,27 +212,385 @@ render_pipeline_info!(TheRenderPipeline;
     0 => ([f32; 2], wgpu::VertexStepMode::Vertex),
 );
 
-pub struct Wave {
-    compute_pipeline: TheComputePipeline,
-    render_pipeline: TheRenderPipeline,
+/// The passes of each [`Scheme`], entry points of `compute.wgsl`.
+struct SchemePipelines {
+    euler: TheComputePipeline,
+    verlet: [TheComputePipeline; 2],
+    rk4: [TheComputePipeline; 4],
+    cn_start: TheComputePipeline,
+    /// `cn_jacobi[i]` reads the guess in `scratch_i` and writes the other one.
+    cn_jacobi: [TheComputePipeline; 2],
+    /// `cn_finish[i]` takes the solution from `scratch_i`.
+    cn_finish: [TheComputePipeline; 2],
+}
+
+impl SchemePipelines {
+    fn new(
+        device: &wgpu::Device,
+        compute_bind_group_info: &ComputeBindGroupInfo<'_>,
+        params_bind_group_info: &ParamsBindGroupInfo<'_>,
+        medium_bind_group_info: &MediumBindGroupInfo<'_>,
+        sources_bind_group_info: &SourcesBindGroupInfo<'_>,
+    ) -> Self {
+        let pipeline = |entry_point| {
+            let shader_module = device.create_shader_module(include_wgsl!("shaders/compute.wgsl"));
+            TheComputePipeline::new(
+                device,
+                (shader_module, entry_point),
+                compute_bind_group_info,
+                params_bind_group_info,
+                medium_bind_group_info,
+                sources_bind_group_info,
+            )
+        };
+        Self {
+            euler: pipeline("euler"),
+            verlet: ["verlet_drift", "verlet_kick"].map(pipeline),
+            rk4: ["rk4_stage_1", "rk4_stage_2", "rk4_stage_3", "rk4_finish"].map(pipeline),
+            cn_start: pipeline("cn_start"),
+            cn_jacobi: ["cn_jacobi_0", "cn_jacobi_1"].map(pipeline),
+            cn_finish: ["cn_finish_0", "cn_finish_1"].map(pipeline),
+        }
+    }
+
+    /// The passes of one step, in order.
+    fn passes(&self, params: &WaveParams) -> Vec<&TheComputePipeline> {
+        match Scheme::from_u32(params.scheme) {
+            Scheme::Euler => vec![&self.euler],
+            Scheme::Verlet => self.verlet.iter().collect(),
+            Scheme::RungeKutta4 => self.rk4.iter().collect(),
+            Scheme::CrankNicolson => {
+                let iterations = params.iterations as usize;
+                std::iter::once(&self.cn_start)
+                    .chain((0..iterations).map(|i| &self.cn_jacobi[i % 2]))
+                    .chain(std::iter::once(&self.cn_finish[iterations % 2]))
+                    .collect()
+            }
+        }
+    }
+}
 
+/// Everything sized by the grid resolution.
+struct Grid {
+    size: GridSize,
     wave_data: [Buff<WavePoint>; 2],
     compute_bind_group: [ComputeBindGroup; 2],
-
     out_tex_bind_group: Tex2DBindGroup,
+    /// One sum per workgroup of `energy.wgsl`.
+    energy_partials: Buff<[f32; 4]>,
+    /// `energy_bind_group[i]` sums up `wave_data[i]`.
+    energy_bind_group: [EnergyBindGroup; 2],
+}
+
+impl Grid {
+    fn new(size: GridSize, data: &[WavePoint], device: &wgpu::Device) -> anyhow::Result<Self> {
+        let GridSize(width, height) = size;
+        let limits = device.limits();
+        if width < MIN_GRID_SIZE || height < MIN_GRID_SIZE {
+            anyhow::bail!("the grid has to be at least {0}x{0}", MIN_GRID_SIZE);
+        }
+        // The scratch buffers are the largest.
+        let bytes = width as u64 * height as u64 * std::mem::size_of::<[f32; 4]>() as u64;
+        if width.max(height) > limits.max_texture_dimension_2d
+            || bytes > limits.max_storage_buffer_binding_size as u64
+        {
+            anyhow::bail!("a {}x{} grid is larger than the device allows", width, height);
+        }
+
+        let out_tex = Tex::<_2D>::create_uninit("wave-out", (width, height), device);
+        let out_tex_bind_group =
+            Tex2DBindGroupInfo::new(device).bind(out_tex.binding_texture(), out_tex.binding_sampler());
+
+        let wave_data: [Buff<WavePoint>; 2] = std::array::from_fn(|_| Buff::new(device, &BuffInfo::IT, data));
+        // Intermediate states of the schemes with more than one pass.
+        let scratch: [Buff<[f32; 4]>; 2] = std::array::from_fn(|_| {
+            Buff::new(device, &BuffInfo::IT, &vec![[0.0; 4]; (width * height) as usize])
+        });
+        let compute_bind_group_info = ComputeBindGroupInfo::new(device);
+        let compute_bind_group = std::array::from_fn(|i| {
+            compute_bind_group_info.bind(
+                wave_data[i].slice(..),
+                wave_data[(i + 1) % 2].slice(..),
+                out_tex.binding_storage(),
+                scratch[0].slice(..),
+                scratch[1].slice(..),
+            )
+        });
+
+        let energy_partials = Buff::new(
+            device,
+            &BuffInfo::IT,
+            &vec![[0.0; 4]; energy::workgroups(width * height) as usize],
+        );
+        let energy_bind_group_info = EnergyBindGroupInfo::new(device);
+        let energy_bind_group =
+            std::array::from_fn(|i| energy_bind_group_info.bind(wave_data[i].slice(..), energy_partials.slice(..)));
+
+        Ok(Self {
+            size,
+            wave_data,
+            compute_bind_group,
+            out_tex_bind_group,
+            energy_partials,
+            energy_bind_group,
+        })
+    }
+}
+
+pub struct Wave {
+    scheme_pipelines: SchemePipelines,
+    energy_pipeline: EnergyPipeline,
+    render_pipeline: TheRenderPipeline,
+
+    grid: Grid,
+    /// Resolution to switch to on the next frame, set by the keys.
+    pending_grid_size: Option<GridSize>,
+    /// For letterboxing the grid into the view.
+    view_size: (u32, u32),
+
+    params: WaveParams,
+    params_buff: Buff<WaveParams>,
+    params_bind_group: ParamsBindGroup,
+    params_modified: bool,
+    medium_bind_group: MediumBindGroup,
+
+    sources_buff: Buff<SourceData>,
+    sources_bind_group: SourcesBindGroup,
+    emitters: Emitters,
+    emitters_on: bool,
+    /// Simulated time, what the emitters oscillate with.
+    time: f32,
+    /// In physical pixels.
+    cursor: Option<[f32; 2]>,
+    brush: Brush,
+    brush_radius: f32,
+    /// Where the brush painted last frame in grid cells, to paint the line from there.
+    brush_last: Option<[f32; 2]>,
 
     square_verts: Buff<[f32; 2]>,
     square_indices: Buff<u16>,
 
     frame_num: u8,
+    /// Frames simulated since the start, for the energy log.
+    frame: u64,
+    energy_log: Option<EnergyLog>,
 
+    /// What Space resets the field to.
+    initial: Initial,
     reset: bool,
+    /// Where F5 saves the state and F9 restores it from, see `--load`.
+    snapshot_path: String,
+    save_requested: bool,
+    load_requested: bool,
+
+    clock: FrameClock,
+    sweep: Option<Sweep>,
+}
+
+impl Wave {
+    /// Where the grid goes in the view, `[x, y, width, height]` in pixels. It fits the view with
+    /// its aspect ratio, instead of being stretched.
+    fn viewport(&self) -> [f32; 4] {
+        let (view_width, view_height) = (self.view_size.0 as f32, self.view_size.1 as f32);
+        let scale = (view_width / self.grid.size.0 as f32).min(view_height / self.grid.size.1 as f32);
+        let (width, height) = (self.grid.size.0 as f32 * scale, self.grid.size.1 as f32 * scale);
+        [(view_width - width) / 2.0, (view_height - height) / 2.0, width, height]
+    }
+
+    /// The grid position under `cursor`, in cells.
+    fn cursor_cell(&self, [x, y]: [f32; 2]) -> [f32; 2] {
+        let [left, top, width, height] = self.viewport();
+        [
+            (x - left) / width * self.grid.size.0 as f32,
+            (y - top) / height * self.grid.size.1 as f32,
+        ]
+    }
+
+    /// What the emitters and the brush do this frame.
+    fn source_data(&mut self) -> SourceData {
+        let mut data = SourceData::zeroed();
+        // The dispatch computes the state one step on.
+        data.time = self.time + self.params.dt;
+        if self.emitters_on {
+            self.emitters.in_cells((self.grid.size.0, self.grid.size.1), &mut data);
+        }
+        match self.cursor {
+            Some(cursor) if self.brush != Brush::None => {
+                let to = self.cursor_cell(cursor);
+                data.brush = self.brush as u32;
+                data.brush_radius = self.brush_radius;
+                data.brush_from = self.brush_last.unwrap_or(to);
+                data.brush_to = to;
+                data.brush_strength = match self.brush {
+                    Brush::Impulse => BRUSH_IMPULSE,
+                    _ => BRUSH_DISPLACEMENT,
+                };
+                self.brush_last = Some(to);
+            }
+            _ => self.brush_last = None,
+        }
+        data
+    }
+
+    /// Bind the medium the waves travel through, see `--medium`. The image is stretched over the
+    /// grid: red is the wave speed as a fraction of `c`, green adds up to 50 damping and blue
+    /// above one half marks obstacles that pin the field to zero. Without a medium every cell is
+    /// plain red.
+    fn load_medium(path: Option<&str>, device: &wgpu::Device, queue: &wgpu::Queue) -> anyhow::Result<MediumBindGroup> {
+        let img = match path {
+            Some(path) => load_img!(file path).with_context(|| format!("failed to load {}", path))?,
+            None => (image::RgbaImage::from_pixel(1, 1, image::Rgba([255, 0, 0, 255])).into(), "wave-medium"),
+        };
+        let limit = device.limits().max_texture_dimension_2d;
+        if img.0.width() > limit || img.0.height() > limit {
+            anyhow::bail!("{} is larger than the device allows, {} a side", img.1, limit);
+        }
+        let medium = Tex::<_2D>::create(img, device, queue);
+        Ok(MediumBindGroupInfo::new(device).bind(medium.binding_texture()))
+    }
+
+    /// The state the next step starts from, what the last one wrote.
+    fn read_state(&self, device: &wgpu::Device, queue: &wgpu::Queue) -> anyhow::Result<Vec<WavePoint>> {
+        self.grid.wave_data[(self.frame_num % 2) as usize].read(device, queue)
+    }
+
+    /// What the field starts from on a grid of `size`.
+    fn initial_state(&self, size: GridSize) -> Vec<WavePoint> {
+        self.initial.state((size.0, size.1), &self.params)
+    }
+
+    /// Save the state and the parameters to `path`.
+    fn save_snapshot(&self, path: &str, device: &wgpu::Device, queue: &wgpu::Queue) -> anyhow::Result<()> {
+        let mut header = Header::new(SNAPSHOT_SIM, (self.grid.size.0, self.grid.size.1));
+        header.time = self.time;
+        header.params = serde_json::to_value(ParamsFile::from(&self.params))?;
+        Snapshot::new(header, &self.read_state(device, queue)?).save(path)?;
+        log::info!("Saved the wave at t = {:.3} to {}", self.time, path);
+        Ok(())
+    }
+
+    /// Carry on from a snapshot, with its grid and parameters.
+    fn load_snapshot(&mut self, path: &str, device: &wgpu::Device) -> anyhow::Result<()> {
+        let snapshot = Snapshot::load(path)?;
+        snapshot.expect_sim(SNAPSHOT_SIM)?;
+        let [width, height] = snapshot.header.size;
+        let state: Vec<WavePoint> = snapshot.payload()?;
+        if state.len() != width as usize * height as usize {
+            anyhow::bail!("{} has {} points for a {}x{} grid", path, state.len(), width, height);
+        }
+        let mut params = WaveParams::new((width, height));
+        snapshot.params::<ParamsFile>()?.apply(&mut params)?;
+        self.grid = Grid::new(GridSize(width, height), &state, device)?;
+        self.pending_grid_size = None;
+        self.params = params;
+        self.params.enforce_cfl_loudly();
+        self.params_modified = true;
+        self.time = snapshot.header.time;
+        log::info!("Loaded a {}x{} wave at t = {:.3} from {}", width, height, self.time, path);
+        Ok(())
+    }
+
+    /// Switch to a grid of `size`, resampling the current state onto it. The grid covers the same
+    /// area, so `du` changes with the width.
+    fn set_grid_size(&mut self, size: GridSize, device: &wgpu::Device, queue: &wgpu::Queue) -> anyhow::Result<()> {
+        let old = self.grid.size;
+        let current = self.read_state(device, queue)?;
+        self.grid = Grid::new(size, &resample(&current, old, size), device)?;
+        self.params.width = size.0;
+        self.params.height = size.1;
+        self.params.du *= old.0 as f32 / size.0 as f32;
+        self.params.enforce_cfl_loudly();
+        self.params_modified = true;
+        log::info!("Grid {}x{}, du = {:.5}", size.0, size.1, self.params.du);
+        Ok(())
+    }
+
+    /// Change the parameters with the keys, see [`WaveParams`]. Up/Down scale `c`, Right/Left
+    /// `dt`, PageUp/PageDown `du` and =/- step the damping. B cycles the boundaries, I the schemes
+    /// and L the stencils, and [/] halve and double the grid.
+    fn adjust_params(&mut self, key: VirtualKeyCode) {
+        let params = &mut self.params;
+        match key {
+            VirtualKeyCode::Up => params.c *= PARAM_STEP,
+            VirtualKeyCode::Down => params.c /= PARAM_STEP,
+            VirtualKeyCode::Right => params.dt *= PARAM_STEP,
+            VirtualKeyCode::Left => params.dt /= PARAM_STEP,
+            VirtualKeyCode::PageUp => params.du *= PARAM_STEP,
+            VirtualKeyCode::PageDown => params.du /= PARAM_STEP,
+            VirtualKeyCode::Equals => params.damping += DAMPING_STEP,
+            VirtualKeyCode::Minus => params.damping = (params.damping - DAMPING_STEP).max(0.0),
+            VirtualKeyCode::B => params.boundary = Boundary::from_u32(params.boundary).next() as u32,
+            VirtualKeyCode::I => {
+                params.scheme = Scheme::from_u32(params.scheme).next() as u32;
+                if params.check_scheme().is_err() {
+                    params.stencil = Stencil::FivePoint as u32;
+                }
+            }
+            VirtualKeyCode::L => {
+                params.stencil = Stencil::from_u32(params.stencil).next() as u32;
+                if params.check_scheme().is_err() {
+                    params.stencil = Stencil::from_u32(params.stencil).next() as u32;
+                }
+            }
+            VirtualKeyCode::LBracket | VirtualKeyCode::RBracket => {
+                let GridSize(width, height) = self.pending_grid_size.unwrap_or(self.grid.size);
+                let (width, height) = match key {
+                    VirtualKeyCode::LBracket => (width / 2, height / 2),
+                    _ => (width * 2, height * 2),
+                };
+                self.pending_grid_size = Some(GridSize(width, height));
+                return;
+            }
+            _ => return,
+        }
+        params.enforce_cfl_loudly();
+        params.log();
+        self.params_modified = true;
+    }
 }
 
 impl EngineBase for Wave {
     fn title() -> &'static str {
         "wave"
     }
+    fn usage() -> &'static str {
+        "\
+options:
+  --grid <n>|<w>x<h>     grid points along each side, 256 by default
+  --c <speed>            wave speed
+  --dt <s>               time step, clamped to the stability limit
+  --du <length>          spacing of the grid points
+  --damping <rate>       how fast the velocity dies down
+  --boundary <kind>      dirichlet, neumann, periodic or absorbing
+  --absorb-width <n>     points of damping layer inside absorbing edges
+  --scheme <name>        euler, verlet, rk4 or crank-nicolson
+  --stencil <name>       5-point, 9-point or 4th-order
+  --iterations <n>       Jacobi iterations per Crank-Nicolson step
+  --params <file>        the parameters above from a file
+  --sweep <file>         keyframed c, dt, du and damping
+  --medium <image>       red scales the speed, green damps, blue marks obstacles
+  --emitters <file>      oscillating point and line sources
+  --initial <preset>     block, gaussian, packet, ring, noise or a file
+  --initial-x <image>    start displacement from a grayscale image
+  --initial-v <image>    start velocity from a grayscale image
+  --load <snapshot>      carry on from a snapshot, also where F5 and F9 go
+  --energy-every <n>     log the energy every n frames, 60 by default
+  --energy-csv <path>    also write the energy to a CSV file
+
+keys:
+  Up/Down                scale c
+  Right/Left             scale dt
+  PageUp/PageDown        scale du
+  =/-                    step the damping
+  B/I/L                  cycle the boundaries, schemes and stencils
+  [/]                    halve and double the grid
+  P                      next initial preset
+  Space                  start over from the initial state
+  E                      toggle the emitters
+  F5/F9                  save and load a snapshot
+  left/right mouse       push the displacement and the velocity, the wheel sizes the brush
+"
+    }
 
     fn required_limits() -> wgpu::Limits {
         wgpu::Limits::downlevel_defaults()
@@ -80,12 +606,13 @@ impl EngineBase for Wave {
         config: &wgpu::SurfaceConfiguration,
         _adapter: &wgpu::Adapter,
         device: &wgpu::Device,
-        _queue: &wgpu::Queue,
+        queue: &wgpu::Queue,
     ) -> Self {
         let tex2d_bind_group_info = Tex2DBindGroupInfo::new(&device);
         let compute_bind_group_info = ComputeBindGroupInfo::new(&device);
-
-        const WAVE_DATA: BuffInfo<WavePoint> = BuffInfo::IT;
+        let params_bind_group_info = ParamsBindGroupInfo::new(&device);
+        let medium_bind_group_info = MediumBindGroupInfo::new(device);
+        let sources_bind_group_info = SourcesBindGroupInfo::new(device);
 
         const SQUARE_VERTS: BuffInfo<[f32; 2]> = BuffInfo::IT;
         const SQUARE_INDICES: BuffInfo<u16> = BuffInfo::IT;
@@ -104,36 +631,32 @@ impl EngineBase for Wave {
                 &vertex_attr_array![0 => Float32x2];
         }
 
-        let out_tex = Tex::<_2D>::create_uninit("wave-out", (SIZE as u32, SIZE as u32), &device);
+        let size = GridSize(DEFAULT_GRID_SIZE, DEFAULT_GRID_SIZE);
+        let params = WaveParams::new((size.0, size.1));
+        let initial = Initial::default();
+        let grid = Grid::new(size, &initial.state((size.0, size.1), &params), device)
+            .expect("the default grid fits any device");
 
-        let out_tex_bind_group =
-            tex2d_bind_group_info.bind(out_tex.binding_texture(), out_tex.binding_sampler());
+        let params_buff = Buff::new(device, &BuffInfo::<WaveParams>::IT, &[params]);
+        let params_bind_group = params_bind_group_info.bind(params_buff.slice(..));
 
-        let (compute_pipeline, wave_data, compute_bind_group) = {
-            let shader_module = device.create_shader_module(include_wgsl!("shaders/compute.wgsl"));
-
-            let mut v = vec![WavePoint { x: 0.0, v: 0.0 }; (SIZE * SIZE) as usize];
-            for x in 0..20 {
-                for y in 50..100 {
-                    // v[50+x+y*SIZE as usize] = WavePoint{x:2.0*(1.0-(x as f32 / 10.0)),v:0.0};
-                    v[50 + x + y * SIZE as usize] = WavePoint { x: 2.0, v: 0.0 };
-                }
-            }
-
-            let wave_data = std::array::from_fn(|_| Buff::new(&device, &WAVE_DATA, &v[..]));
-
-            let compute_bind_group = std::array::from_fn(|i| {
-                compute_bind_group_info.bind(
-                    wave_data[i].slice(..),
-                    wave_data[(i + 1) % 2].slice(..),
-                    out_tex.binding_storage(),
-                )
-            });
-
-            let pipeline = TheComputePipeline::new(&device, (shader_module, "main"), &compute_bind_group_info);
-
-            (pipeline, wave_data, compute_bind_group)
-        };
+        let scheme_pipelines = SchemePipelines::new(
+            device,
+            &compute_bind_group_info,
+            &params_bind_group_info,
+            &medium_bind_group_info,
+            &sources_bind_group_info,
+        );
+        let energy_pipeline = EnergyPipeline::new(
+            device,
+            (device.create_shader_module(include_wgsl!("shaders/energy.wgsl")), "main"),
+            &EnergyBindGroupInfo::new(device),
+            &params_bind_group_info,
+            &medium_bind_group_info,
+        );
+        let medium_bind_group = Self::load_medium(None, device, queue).expect("the plain medium always loads");
+        let sources_buff = Buff::new(device, &BuffInfo::<SourceData>::IT, &[SourceData::zeroed()]);
+        let sources_bind_group = sources_bind_group_info.bind(sources_buff.slice(..));
 
         let (render_pipeline, square_verts, square_indices) = {
             let module_vert = device.create_shader_module(include_glsl!(
@@ -160,25 +683,136 @@ impl EngineBase for Wave {
         };
 
         Self {
-            compute_pipeline,
+            scheme_pipelines,
+            energy_pipeline,
             render_pipeline,
-            wave_data,
-            compute_bind_group,
-            out_tex_bind_group,
+            grid,
+            pending_grid_size: None,
+            view_size: (config.width, config.height),
+            params,
+            params_buff,
+            params_bind_group,
+            params_modified: false,
+            medium_bind_group,
+            sources_buff,
+            sources_bind_group,
+            emitters: Emitters::default(),
+            emitters_on: true,
+            time: 0.0,
+            cursor: None,
+            brush: Brush::None,
+            brush_radius: BRUSH_RADIUS,
+            brush_last: None,
             square_verts,
             square_indices,
             frame_num: 0,
+            frame: 0,
+            energy_log: None,
+            initial,
             reset: false,
+            snapshot_path: DEFAULT_SNAPSHOT.to_string(),
+            save_requested: false,
+            load_requested: false,
+            clock: FrameClock::new(),
+            sweep: None,
+        }
+    }
+
+    fn configure(
+        &mut self,
+        args: &SimArgs,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> anyhow::Result<()> {
+        if let Some(path) = args.get::<String>("medium")? {
+            self.medium_bind_group = Self::load_medium(Some(&path), device, queue)?;
+            log::info!("Medium from {}", path);
+        }
+        if let Some(path) = args.get::<String>("emitters")? {
+            self.emitters = Emitters::load(&path)?;
+            log::info!("{} emitters from {}", self.emitters.len(), path);
+        }
+        let load = args.get::<String>("load")?;
+        let grid_size = args.get::<GridSize>("grid")?;
+        if let Some(size) = grid_size {
+            if load.is_some() {
+                anyhow::bail!("--grid can't be combined with --load, the snapshot brings its own grid");
+            }
+            // Keeps the default spacing rather than the area, there's nothing to resample yet.
+            self.params.width = size.0;
+            self.params.height = size.1;
+        }
+        let loaded = load.is_some();
+        // Before the parameters, so they can change the snapshot's.
+        if let Some(path) = load {
+            self.load_snapshot(&path, device)?;
+            self.snapshot_path = path;
+        }
+        if let Some(path) = args.get::<String>("params")? {
+            let file: ParamsFile = keyframes::load(&path)?;
+            file.apply(&mut self.params)?;
         }
+        let values = [
+            args.get::<f32>("c")?,
+            args.get::<f32>("dt")?,
+            args.get::<f32>("du")?,
+            args.get::<f32>("damping")?,
+        ];
+        let [c, dt, du, damping] = values;
+        ParamsFile {
+            c,
+            dt,
+            du,
+            damping,
+            boundary: args.get("boundary")?,
+            absorb_width: args.get("absorb-width")?,
+            scheme: args.get("scheme")?,
+            stencil: args.get("stencil")?,
+            iterations: args.get("iterations")?,
+        }
+        .apply(&mut self.params)?;
+        self.params.enforce_cfl_loudly();
+        self.params.log();
+        self.params_modified = true;
+
+        if let Some(spec) = args.get::<String>("initial")? {
+            self.initial = Initial::load(&spec)?;
+        }
+        self.initial.set_images(
+            args.get::<String>("initial-x")?.as_deref(),
+            args.get::<String>("initial-v")?.as_deref(),
+        )?;
+        if !loaded {
+            // After the parameters, a packet moves at the final `c`.
+            let size = grid_size.unwrap_or(self.grid.size);
+            self.grid = Grid::new(size, &self.initial_state(size), device)?;
+            self.initial.log();
+        }
+
+        let energy_csv = args.get::<String>("energy-csv")?;
+        let energy_every = args.get::<u32>("energy-every")?;
+        if energy_every.is_some() || energy_csv.is_some() {
+            self.energy_log = Some(EnergyLog::new(energy_every.unwrap_or(60), energy_csv.as_deref())?);
+        }
+
+        if let Some(path) = args.get::<String>("sweep")? {
+            let sweep = Sweep::load(&path)?;
+            log::info!("Sweeping the wave parameters from {}, {:.1} s long", path, sweep.end());
+            self.sweep = Some(sweep);
+        }
+        Ok(())
     }
 
     fn resize(
         &mut self,
-        _config: &wgpu::SurfaceConfiguration,
+        config: &wgpu::SurfaceConfiguration,
         _device: &wgpu::Device,
         _queue: &wgpu::Queue,
     ) {
-        //
+        self.view_size = (config.width, config.height);
+    }
+    fn set_fixed_timestep(&mut self, dt: f32) {
+        self.clock.set_fixed(dt);
     }
     fn update(&mut self, event: winit::event::WindowEvent) {
         //
@@ -195,10 +829,58 @@ impl EngineBase for Wave {
                         VirtualKeyCode::J => {
                             // j
                         }
+                        VirtualKeyCode::P if input.state == winit::event::ElementState::Pressed => {
+                            self.initial.next_preset();
+                            self.initial.log();
+                            self.reset = true;
+                        }
+                        VirtualKeyCode::F5 if input.state == winit::event::ElementState::Pressed => {
+                            self.save_requested = true;
+                        }
+                        VirtualKeyCode::F9 if input.state == winit::event::ElementState::Pressed => {
+                            self.load_requested = true;
+                        }
+                        VirtualKeyCode::E if input.state == winit::event::ElementState::Pressed => {
+                            self.emitters_on = !self.emitters_on;
+                            if !self.emitters.is_empty() {
+                                log::info!("Emitters {}", if self.emitters_on { "on" } else { "off" });
+                            }
+                        }
+                        _ if input.state == winit::event::ElementState::Pressed => self.adjust_params(key),
                         _ => {}
                     }
                 }
             }
+            winit::event::WindowEvent::CursorMoved { position, .. } => {
+                self.cursor = Some([position.x as f32, position.y as f32]);
+            }
+            winit::event::WindowEvent::CursorLeft { .. } => {
+                self.cursor = None;
+            }
+            winit::event::WindowEvent::MouseInput { state, button, .. } => {
+                let brush = match button {
+                    winit::event::MouseButton::Left => Brush::Displacement,
+                    winit::event::MouseButton::Right => Brush::Impulse,
+                    _ => return,
+                };
+                match state {
+                    winit::event::ElementState::Pressed => self.brush = brush,
+                    winit::event::ElementState::Released if self.brush == brush => self.brush = Brush::None,
+                    winit::event::ElementState::Released => {}
+                }
+            }
+            winit::event::WindowEvent::MouseWheel { delta, .. } => {
+                let up = match delta {
+                    winit::event::MouseScrollDelta::LineDelta(_, y) => y > 0.0,
+                    winit::event::MouseScrollDelta::PixelDelta(p) => p.y > 0.0,
+                };
+                let step = if up { BRUSH_RADIUS_STEP } else { 1.0 / BRUSH_RADIUS_STEP };
+                self.brush_radius = (self.brush_radius * step).clamp(1.0, 64.0);
+            }
+            winit::event::WindowEvent::Focused(false) => {
+                // The release would go to another window.
+                self.brush = Brush::None;
+            }
             _ => {}
         }
     }
@@ -211,28 +893,80 @@ impl EngineBase for Wave {
     ) {
         let mut cmds = device.create_command_encoder(&Default::default());
 
-        if self.reset {
-            self.reset = false;
-            let mut v = vec![WavePoint { x: 0.0, v: 0.0 }; (SIZE * SIZE) as usize];
+        if let Some(size) = self.pending_grid_size.take() {
+            if let Err(err) = self.set_grid_size(size, device, queue) {
+                log::error!("{:#}", err);
+            }
+        }
+        if std::mem::take(&mut self.save_requested) {
+            if let Err(err) = self.save_snapshot(&self.snapshot_path, device, queue) {
+                log::error!("{:#}", err);
+            }
+        }
+        if std::mem::take(&mut self.load_requested) {
+            let path = self.snapshot_path.clone();
+            if let Err(err) = self.load_snapshot(&path, device) {
+                log::error!("{:#}", err);
+            }
+        }
 
-            for x in 0..20 {
-                for y in 50..100 {
-                    // v[50+x+y*SIZE as usize] = WavePoint{x:2.0*(1.0-(x as f32 / 10.0)),v:0.0};
-                    v[50 + x + y * SIZE as usize] = WavePoint { x: 2.0, v: 0.0 };
-                }
+        let dt = self.clock.tick();
+        if let Some(sweep) = &mut self.sweep {
+            let before = self.params;
+            if let Err(err) = sweep.advance(dt, &mut self.params) {
+                log::error!("{:#}, stopping the sweep", err);
+                self.sweep = None;
             }
+            self.params_modified |= bytemuck::bytes_of(&before) != bytemuck::bytes_of(&self.params);
+        }
+        if self.params_modified {
+            self.params_modified = false;
+            self.params_buff.write(0, &[self.params], queue);
+        }
 
-            for data in &self.wave_data {
+        if self.reset {
+            self.reset = false;
+            let v = self.initial_state(self.grid.size);
+
+            for data in &self.grid.wave_data {
                 data.write(0, &v[..], &queue);
             }
+            self.time = 0.0;
         }
+        let sources = self.source_data();
+        self.sources_buff.write(0, &[sources], queue);
 
         {
             let mut pass = cmds.begin_compute_pass(&wgpu::ComputePassDescriptor { label: None });
-            self.compute_pipeline.dispatch(
+            for pipeline in self.scheme_pipelines.passes(&self.params) {
+                pipeline.dispatch(
+                    &mut pass,
+                    (
+                        self.grid.size.0.div_ceil(WORKGROUP_SIZE),
+                        self.grid.size.1.div_ceil(WORKGROUP_SIZE),
+                        1,
+                    ),
+                    &self.grid.compute_bind_group[(self.frame_num % 2) as usize],
+                    &self.params_bind_group,
+                    &self.medium_bind_group,
+                    &self.sources_bind_group,
+                );
+            }
+        }
+        self.time += self.params.dt;
+        self.frame += 1;
+
+        let log_energy = self.energy_log.as_ref().is_some_and(|it| it.is_due(self.frame));
+        if log_energy {
+            let mut pass = cmds.begin_compute_pass(&wgpu::ComputePassDescriptor { label: Some("energy") });
+            let points = self.grid.size.0 * self.grid.size.1;
+            self.energy_pipeline.dispatch(
                 &mut pass,
-                (SIZE / WORKGROUP_SIZE, SIZE / WORKGROUP_SIZE, 1),
-                &self.compute_bind_group[(self.frame_num % 2) as usize],
+                (energy::workgroups(points), 1, 1),
+                // What the step above wrote.
+                &self.grid.energy_bind_group[((self.frame_num + 1) % 2) as usize],
+                &self.params_bind_group,
+                &self.medium_bind_group,
             );
         }
 
@@ -249,17 +983,349 @@ impl EngineBase for Wave {
                 })],
                 depth_stencil_attachment: None,
             });
+            let [x, y, width, height] = self.viewport();
+            pass.set_viewport(x, y, width, height, 0.0, 1.0);
             self.render_pipeline.draw_indexed(
                 &mut pass,
                 0..6, 0..1,
                 self.square_indices.slice(..),
                 self.square_verts.slice(..),
-                &self.out_tex_bind_group,
+                &self.grid.out_tex_bind_group,
             );
         }
 
         queue.submit(Some(cmds.finish().into()));
 
         self.frame_num = self.frame_num.wrapping_add(1);
+
+        if log_energy {
+            let recorded = self
+                .grid
+                .energy_partials
+                .read(device, queue)
+                .and_then(|partials| {
+                    let log = self.energy_log.as_mut().unwrap();
+                    log.record(self.frame, self.time, Energy::from_partials(&partials))
+                });
+            if let Err(err) = recorded {
+                log::error!("{:#}, stopping the energy log", err);
+                self.energy_log = None;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{capture::OffscreenTarget, engine_base::{self, Spawner}, wave::reference};
+
+    const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+    /// Odd, so each run after the first starts from the other buffer of the ping-pong.
+    const STEPS: usize = 21;
+    /// Relative to the largest `x` and `v` of the CPU state.
+    const TOLERANCE: f32 = 1e-4;
+
+    /// A Gaussian bump off centre, wide enough to reach the edges within `STEPS`.
+    fn bump(GridSize(width, height): GridSize) -> Vec<WavePoint> {
+        let (cx, cy, sigma) = (0.3 * width as f32, 0.6 * height as f32, 3.0);
+        (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x as f32 - cx, y as f32 - cy)))
+            .map(|(dx, dy)| WavePoint {
+                x: (-(dx * dx + dy * dy) / (2.0 * sigma * sigma)).exp(),
+                v: 0.0,
+            })
+            .collect()
+    }
+
+    /// The fallback adapter and what a frame renders into.
+    struct Gpu {
+        adapter: wgpu::Adapter,
+        device: wgpu::Device,
+        queue: wgpu::Queue,
+        config: wgpu::SurfaceConfiguration,
+        target: OffscreenTarget,
+        spawner: Spawner<'static>,
+    }
+
+    impl Gpu {
+        /// `None` on machines without a fallback adapter, the tests are skipped there.
+        fn new() -> Option<Self> {
+            let Some((adapter, device, queue)) = engine_base::fallback_device::<Wave>() else {
+                eprintln!("No fallback adapter, skipping");
+                return None;
+            };
+            let config = wgpu::SurfaceConfiguration {
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+                format: FORMAT,
+                width: 64,
+                height: 64,
+                present_mode: wgpu::PresentMode::Fifo,
+                alpha_mode: wgpu::CompositeAlphaMode::Opaque,
+                view_formats: vec![FORMAT],
+            };
+            let target = OffscreenTarget::new(&device, FORMAT, (config.width, config.height));
+            Some(Self {
+                adapter,
+                device,
+                queue,
+                config,
+                target,
+                spawner: Spawner::new(),
+            })
+        }
+
+        fn wave(&self) -> Wave {
+            Wave::init(&self.config, &self.adapter, &self.device, &self.queue)
+        }
+
+        fn step(&self, wave: &mut Wave) {
+            wave.render(&self.target.view, &self.device, &self.queue, &self.spawner);
+        }
+
+        fn state(&self, wave: &Wave) -> Vec<WavePoint> {
+            wave.read_state(&self.device, &self.queue).unwrap()
+        }
+    }
+
+    fn assert_close(gpu: &[WavePoint], cpu: &[WavePoint], what: &str) {
+        assert_eq!(gpu.len(), cpu.len());
+        let largest = |f: fn(&WavePoint) -> f32| cpu.iter().map(f).fold(0.0, f32::max);
+        let (x_scale, v_scale) = (largest(|p| p.x.abs()), largest(|p| p.v.abs()));
+        for (i, (gpu, cpu)) in gpu.iter().zip(cpu).enumerate() {
+            assert!(
+                (gpu.x - cpu.x).abs() <= TOLERANCE * x_scale && (gpu.v - cpu.v).abs() <= TOLERANCE * v_scale,
+                "{}: point {} is {:?} on the GPU and {:?} on the CPU",
+                what,
+                i,
+                gpu,
+                cpu
+            );
+        }
+    }
+
+    /// Slower to the right, damped towards the bottom and with an obstacle left of the middle.
+    fn obstacle_medium() -> image::RgbaImage {
+        image::RgbaImage::from_fn(8, 6, |x, y| {
+            let obstacle = (2..4).contains(&x) && (2..4).contains(&y);
+            image::Rgba([255 - 20 * x as u8, 4 * y as u8, if obstacle { 255 } else { 0 }, 255])
+        })
+    }
+
+    #[test]
+    fn schemes_match_cpu_reference() {
+        let Some(gpu) = Gpu::new() else {
+            return;
+        };
+        let mut wave = gpu.wave();
+
+        // Not a multiple of the workgroup size either way.
+        let size = GridSize(37, 29);
+        let plain = image::RgbaImage::from_pixel(1, 1, image::Rgba([255, 0, 0, 255]));
+        let medium_path = std::env::temp_dir().join(format!("the-sim-medium-{}.png", std::process::id()));
+        for medium in [plain, obstacle_medium()] {
+            medium.save(&medium_path).unwrap();
+            wave.medium_bind_group = Wave::load_medium(medium_path.to_str(), &gpu.device, &gpu.queue).unwrap();
+            for scheme in Scheme::ALL {
+                // Both ends of the Jacobi ping-pong.
+                let iterations: &[u32] = if scheme == Scheme::CrankNicolson { &[5, 6] } else { &[1] };
+                for (&iterations, boundary, stencil) in iterations
+                    .iter()
+                    .flat_map(|it| Boundary::ALL.map(|boundary| (it, boundary)))
+                    .flat_map(|(it, boundary)| Stencil::ALL.map(|stencil| (it, boundary, stencil)))
+                {
+                    let mut params = WaveParams::new((size.0, size.1));
+                    params.scheme = scheme as u32;
+                    params.iterations = iterations;
+                    params.boundary = boundary as u32;
+                    params.stencil = stencil as u32;
+                    params.absorb_width = 4;
+                    params.damping = 0.5;
+                    params.dt = 0.015;
+                    if params.check_scheme().is_err() {
+                        continue;
+                    }
+                    params.enforce_cfl();
+                    wave.params = params;
+                    wave.params_modified = true;
+
+                    let mut cpu = bump(size);
+                    wave.grid = Grid::new(size, &cpu, &gpu.device).unwrap();
+                    for step in 1..=STEPS {
+                        gpu.step(&mut wave);
+                        cpu = reference::step(&params, &medium, &cpu);
+                        let what = format!(
+                            "{:?} ({} iterations) {:?} {:?} in a {}x{} medium, step {}",
+                            scheme,
+                            iterations,
+                            boundary,
+                            stencil,
+                            medium.width(),
+                            medium.height(),
+                            step
+                        );
+                        assert_close(&gpu.state(&wave), &cpu, &what);
+                    }
+                }
+            }
+        }
+        std::fs::remove_file(medium_path).unwrap();
+    }
+
+    /// What `energy.wgsl` adds up, summed in `f64`.
+    fn cpu_energy(params: &WaveParams, medium: &image::RgbaImage, data: &[WavePoint]) -> Energy {
+        let (width, height) = (params.width as usize, params.height as usize);
+        let periodic = params.boundary == Boundary::Periodic as u32;
+        let du = params.du as f64;
+        let slope = |x: usize, y: usize, (ax, ay): (usize, usize)| {
+            let (mut qx, mut qy) = (x + ax, y + ay);
+            if periodic {
+                (qx, qy) = (qx % width, qy % height);
+            } else if qx >= width || qy >= height {
+                return 0.0;
+            }
+            (data[qx + qy * width].x as f64 - data[x + y * width].x as f64) / du
+        };
+        let (mut kinetic, mut potential, mut max_x) = (0.0, 0.0, 0.0f32);
+        for y in 0..height {
+            for x in 0..width {
+                let point = data[x + y * width];
+                let texel = medium.get_pixel(
+                    (x * medium.width() as usize / width) as u32,
+                    (y * medium.height() as usize / height) as u32,
+                );
+                let c = params.c as f64 * texel[0] as f64 / 255.0;
+                let (dx, dy) = (slope(x, y, (1, 0)), slope(x, y, (0, 1)));
+                kinetic += 0.5 * (point.v as f64).powi(2) * du * du;
+                potential += 0.5 * c * c * (dx * dx + dy * dy) * du * du;
+                max_x = max_x.max(point.x.abs());
+            }
+        }
+        Energy {
+            kinetic: kinetic as f32,
+            potential: potential as f32,
+            max_x,
+        }
+    }
+
+    #[test]
+    fn energy_matches_cpu_sum() {
+        let Some(gpu) = Gpu::new() else {
+            return;
+        };
+        let mut wave = gpu.wave();
+
+        // More points than one pass of every invocation covers, so the grid-stride loop goes around again.
+        let size = GridSize(613, 431);
+        assert!(size.0 * size.1 > energy::MAX_WORKGROUPS * energy::WORKGROUP_SIZE);
+        let medium = obstacle_medium();
+        let medium_path = std::env::temp_dir().join(format!("the-sim-energy-medium-{}.png", std::process::id()));
+        medium.save(&medium_path).unwrap();
+        wave.medium_bind_group = Wave::load_medium(medium_path.to_str(), &gpu.device, &gpu.queue).unwrap();
+        std::fs::remove_file(&medium_path).unwrap();
+
+        for boundary in [Boundary::Periodic, Boundary::Dirichlet] {
+            let mut params = WaveParams::new((size.0, size.1));
+            params.boundary = boundary as u32;
+            params.enforce_cfl();
+            wave.params = params;
+            wave.params_modified = true;
+            // Moving everywhere, the bump would sit inside the obstacle.
+            let waves: Vec<_> = (0..size.1)
+                .flat_map(|y| (0..size.0).map(move |x| (x as f32, y as f32)))
+                .map(|(x, y)| WavePoint {
+                    x: (0.05 * x).sin() * (0.07 * y).cos(),
+                    v: (0.03 * x + 0.02 * y).cos(),
+                })
+                .collect();
+            wave.grid = Grid::new(size, &waves, &gpu.device).unwrap();
+            wave.energy_log = Some(EnergyLog::new(1, None).unwrap());
+            for _ in 0..3 {
+                gpu.step(&mut wave);
+            }
+
+            let partials = wave.grid.energy_partials.read(&gpu.device, &gpu.queue).unwrap();
+            assert_eq!(partials.len(), energy::MAX_WORKGROUPS as usize);
+            let gpu_energy = Energy::from_partials(&partials);
+            let cpu_energy = cpu_energy(&params, &medium, &gpu.state(&wave));
+            assert!(cpu_energy.kinetic > 0.0 && cpu_energy.potential > 0.0);
+            for (what, gpu_value, cpu_value) in [
+                ("kinetic energy", gpu_energy.kinetic, cpu_energy.kinetic),
+                ("potential energy", gpu_energy.potential, cpu_energy.potential),
+                ("max |x|", gpu_energy.max_x, cpu_energy.max_x),
+            ] {
+                assert!(
+                    (gpu_value - cpu_value).abs() <= 1e-4 * cpu_value,
+                    "{:?}: {} is {} on the GPU and {} on the CPU",
+                    boundary,
+                    what,
+                    gpu_value,
+                    cpu_value
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn grid_size_from_str() {
+        assert_eq!("512x256".parse(), Ok(GridSize(512, 256)));
+        assert_eq!("64".parse(), Ok(GridSize(64, 64)));
+        assert_eq!(" 32 x 16 ".parse(), Ok(GridSize(32, 16)));
+        for bad in ["x", "", "64x", "x64", "-1", "8x8x8"] {
+            assert!(bad.parse::<GridSize>().is_err(), "{:?} parsed", bad);
+        }
+    }
+
+    #[test]
+    fn resample_keeps_linear_fields() {
+        let ramp = |GridSize(width, height): GridSize| -> Vec<WavePoint> {
+            (0..height)
+                .flat_map(|y| (0..width).map(move |x| (x as f32 / (width - 1) as f32, y as f32 / (height - 1) as f32)))
+                .map(|(x, y)| WavePoint { x: 2.0 * x + y, v: x - 3.0 * y })
+                .collect()
+        };
+        let (small, large) = (GridSize(4, 4), GridSize(8, 8));
+        let up = resample(&ramp(small), small, large);
+        assert_close(&up, &ramp(large), "4 to 8");
+        let corners = |data: &[WavePoint], GridSize(width, height): GridSize| {
+            let (width, height) = (width as usize, height as usize);
+            [0, width - 1, width * (height - 1), width * height - 1].map(|i| (data[i].x, data[i].v))
+        };
+        assert_eq!(corners(&up, large), corners(&ramp(small), small));
+        assert_close(&resample(&up, large, small), &ramp(small), "4 to 8 and back");
+
+        let wide = GridSize(6, 3);
+        assert_close(&resample(&ramp(small), small, wide), &ramp(wide), "4x4 to 6x3");
+    }
+
+    #[test]
+    fn snapshot_round_trip() {
+        let Some(gpu) = Gpu::new() else {
+            return;
+        };
+        let mut wave = gpu.wave();
+        let size = GridSize(40, 24);
+        wave.grid = Grid::new(size, &bump(size), &gpu.device).unwrap();
+        wave.params = WaveParams::new((size.0, size.1));
+        wave.params.boundary = Boundary::Periodic as u32;
+        wave.params.stencil = Stencil::NinePoint as u32;
+        wave.params.damping = 0.3;
+        wave.params_modified = true;
+        for _ in 0..5 {
+            gpu.step(&mut wave);
+        }
+
+        let path = std::env::temp_dir().join(format!("the-sim-{}.snapshot", std::process::id()));
+        let path = path.to_str().unwrap();
+        wave.save_snapshot(path, &gpu.device, &gpu.queue).unwrap();
+        let mut loaded = gpu.wave();
+        loaded.load_snapshot(path, &gpu.device).unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        assert_eq!(loaded.grid.size, size);
+        assert_eq!(bytemuck::bytes_of(&loaded.params), bytemuck::bytes_of(&wave.params));
+        assert_eq!(loaded.time, wave.time);
+        let (state, loaded_state) = (gpu.state(&wave), gpu.state(&loaded));
+        assert_eq!(bytemuck::cast_slice::<_, u8>(&loaded_state), bytemuck::cast_slice::<_, u8>(&state));
     }
 }
@@ -0,0 +1,103 @@
+//! Energy of the wave field, summed on the GPU by `energy.wgsl`, see `--energy-every`.
+//!
+//! Without damping, emitters or absorbing edges the total should stay about constant, so a total
+//! that runs away means the parameters are unstable and one that drops means something leaks.
+
+use std::{fs::File, io::Write};
+
+use anyhow::Context;
+
+/// Has to match `@workgroup_size` in `energy.wgsl`.
+pub const WORKGROUP_SIZE: u32 = 256;
+/// Past this many workgroups every invocation sums more than one point.
+pub const MAX_WORKGROUPS: u32 = 1024;
+
+/// Workgroups to dispatch for a grid of `points`, one partial sum each.
+pub fn workgroups(points: u32) -> u32 {
+    points.div_ceil(WORKGROUP_SIZE).min(MAX_WORKGROUPS)
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Energy {
+    pub kinetic: f32,
+    pub potential: f32,
+    /// Largest displacement, in either direction.
+    pub max_x: f32,
+}
+
+impl Energy {
+    /// Add up the per workgroup `[kinetic, potential, max |x|, _]` sums.
+    pub fn from_partials(partials: &[[f32; 4]]) -> Self {
+        partials.iter().fold(Self::default(), |sum, [kinetic, potential, max_x, _]| Self {
+            kinetic: sum.kinetic + kinetic,
+            potential: sum.potential + potential,
+            max_x: sum.max_x.max(*max_x),
+        })
+    }
+
+    pub fn total(&self) -> f32 {
+        self.kinetic + self.potential
+    }
+}
+
+/// Logs the energy every few frames, and keeps a CSV of it if asked to.
+pub struct EnergyLog {
+    /// In frames.
+    pub every: u32,
+    csv: Option<File>,
+    /// Whether the field blew up already, to only warn once.
+    diverged: bool,
+}
+
+impl EnergyLog {
+    pub fn new(every: u32, csv: Option<&str>) -> anyhow::Result<Self> {
+        if every == 0 {
+            anyhow::bail!("--energy-every has to be at least 1");
+        }
+        let csv = match csv {
+            Some(path) => {
+                let mut file = File::create(path).with_context(|| format!("failed to create {}", path))?;
+                writeln!(file, "frame,time,kinetic,potential,total,max_x")?;
+                Some(file)
+            }
+            None => None,
+        };
+        Ok(Self {
+            every,
+            csv,
+            diverged: false,
+        })
+    }
+
+    pub fn is_due(&self, frame: u64) -> bool {
+        frame.is_multiple_of(self.every as u64)
+    }
+
+    pub fn record(&mut self, frame: u64, time: f32, energy: Energy) -> anyhow::Result<()> {
+        log::info!(
+            "t = {:.3}: kinetic {:.5e}, potential {:.5e}, total {:.5e}, max |x| {:.4}",
+            time,
+            energy.kinetic,
+            energy.potential,
+            energy.total(),
+            energy.max_x
+        );
+        if !energy.total().is_finite() && !self.diverged {
+            log::warn!("The field blew up by t = {:.3}, dt is probably too large for du", time);
+        }
+        self.diverged |= !energy.total().is_finite();
+        if let Some(csv) = &mut self.csv {
+            writeln!(
+                csv,
+                "{},{},{},{},{},{}",
+                frame,
+                time,
+                energy.kinetic,
+                energy.potential,
+                energy.total(),
+                energy.max_x
+            )?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,301 @@
+//! What the wave starts from, and goes back to on reset, see `--initial`.
+//!
+//! `--initial` takes the name of a preset, or a file like
+//!
+//! ```json
+//! { "preset": "packet", "center": [0.3, 0.5], "width": 0.08, "wavelength": 0.03, "angle": 0.5 }
+//! ```
+//!
+//! setting any of the numbers the presets use. With `displacement` and `velocity` images instead
+//! of a preset, the fields come from their brightness: mid grey is at rest, white is `amplitude`
+//! and black `-amplitude`. Images are stretched over the grid, positions are fractions of the grid
+//! and lengths fractions of its shorter side, so everything scales with the resolution.
+
+use anyhow::Context;
+use nanorand::{Rng, WyRand};
+use serde::Deserialize;
+
+use super::{core::WavePoint, params::WaveParams};
+
+/// What the block was placed for, it scales with the grid.
+const BLOCK_GRID_SIZE: u32 = 256;
+/// Brightness within a level of the middle is at rest, 8-bit images have no level right at it.
+const MID_GREY_TOLERANCE: f32 = 1.0 / 255.0;
+
+type GrayImage = image::ImageBuffer<image::Luma<f32>, Vec<f32>>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum Preset {
+    /// A raised rectangle left of the middle, what the simulation always started from.
+    Block,
+    /// A round bump at `center`, `width` across.
+    Gaussian,
+    /// A plane wave of `wavelength` under a Gaussian envelope, travelling along `angle`.
+    Packet,
+    /// A circular ridge of `radius`, which splits into an inward and an outward ring.
+    Ring,
+    /// Independent random displacements, every wavelength the grid has at once.
+    Noise,
+}
+impl Preset {
+    pub const ALL: [Preset; 5] = [Self::Block, Self::Gaussian, Self::Packet, Self::Ring, Self::Noise];
+    pub fn next(self) -> Self {
+        let i = Self::ALL.iter().position(|it| *it == self).unwrap_or(0);
+        Self::ALL[(i + 1) % Self::ALL.len()]
+    }
+    fn default_amplitude(self) -> f32 {
+        match self {
+            Self::Block => 2.0,
+            _ => 1.0,
+        }
+    }
+}
+impl std::str::FromStr for Preset {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "block" => Ok(Self::Block),
+            "gaussian" => Ok(Self::Gaussian),
+            "packet" => Ok(Self::Packet),
+            "ring" => Ok(Self::Ring),
+            "noise" => Ok(Self::Noise),
+            _ => Err("expected one of block, gaussian, packet, ring, noise".to_string()),
+        }
+    }
+}
+impl TryFrom<String> for Preset {
+    type Error = String;
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// An `--initial` file, everything is optional.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct InitialFile {
+    preset: Option<Preset>,
+    displacement: Option<String>,
+    velocity: Option<String>,
+    amplitude: Option<f32>,
+    center: Option<[f32; 2]>,
+    width: Option<f32>,
+    radius: Option<f32>,
+    wavelength: Option<f32>,
+    /// In radians, counterclockwise from +x.
+    angle: Option<f32>,
+    seed: Option<u64>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Initial {
+    pub preset: Preset,
+    /// Take the place of the preset when either is there, the other field is at rest.
+    displacement: Option<GrayImage>,
+    velocity: Option<GrayImage>,
+    /// The preset's own if not set.
+    amplitude: Option<f32>,
+    center: [f32; 2],
+    width: f32,
+    radius: f32,
+    wavelength: f32,
+    angle: f32,
+    seed: u64,
+}
+
+impl Default for Initial {
+    fn default() -> Self {
+        Self {
+            preset: Preset::Block,
+            displacement: None,
+            velocity: None,
+            amplitude: None,
+            center: [0.5, 0.5],
+            width: 0.05,
+            radius: 0.25,
+            wavelength: 0.04,
+            angle: 0.0,
+            seed: 0,
+        }
+    }
+}
+
+fn load_gray(path: &str) -> anyhow::Result<GrayImage> {
+    let img = image::open(path).with_context(|| format!("failed to load {}", path))?;
+    Ok(img.to_luma32f())
+}
+
+impl Initial {
+    /// A preset by name, or an `--initial` file.
+    pub fn load(spec: &str) -> anyhow::Result<Self> {
+        if let Ok(preset) = spec.parse() {
+            return Ok(Self {
+                preset,
+                ..Default::default()
+            });
+        }
+        if !std::path::Path::new(spec).exists() {
+            anyhow::bail!("--initial expects one of block, gaussian, packet, ring, noise or a file, got {:?}", spec);
+        }
+        let file: InitialFile = crate::keyframes::load(spec)?;
+        let mut this = Self::default();
+        if file.preset.is_some() && (file.displacement.is_some() || file.velocity.is_some()) {
+            anyhow::bail!("{} has both a preset and images, pick one", spec);
+        }
+        let positive = |name: &str, value: Option<f32>, default: f32| match value {
+            Some(v) if v <= 0.0 => Err(anyhow::anyhow!("{} in {} must be positive, got {}", name, spec, v)),
+            _ => Ok(value.unwrap_or(default)),
+        };
+        this.width = positive("width", file.width, this.width)?;
+        this.radius = positive("radius", file.radius, this.radius)?;
+        this.wavelength = positive("wavelength", file.wavelength, this.wavelength)?;
+        this.preset = file.preset.unwrap_or(this.preset);
+        this.amplitude = file.amplitude;
+        this.center = file.center.unwrap_or(this.center);
+        this.angle = file.angle.unwrap_or(this.angle);
+        this.seed = file.seed.unwrap_or(this.seed);
+        this.set_images(file.displacement.as_deref(), file.velocity.as_deref())?;
+        Ok(this)
+    }
+
+    /// Start from images rather than the preset, if either is given.
+    pub fn set_images(&mut self, displacement: Option<&str>, velocity: Option<&str>) -> anyhow::Result<()> {
+        if displacement.is_some() || velocity.is_some() {
+            self.displacement = displacement.map(load_gray).transpose()?;
+            self.velocity = velocity.map(load_gray).transpose()?;
+        }
+        Ok(())
+    }
+
+    /// Switch to the next preset, dropping any images.
+    pub fn next_preset(&mut self) {
+        self.preset = if self.uses_images() { Preset::Block } else { self.preset.next() };
+        self.displacement = None;
+        self.velocity = None;
+    }
+
+    fn uses_images(&self) -> bool {
+        self.displacement.is_some() || self.velocity.is_some()
+    }
+
+    pub fn log(&self) {
+        if self.uses_images() {
+            log::info!("Starting from images");
+        } else {
+            log::info!("Starting from the {:?} preset", self.preset);
+        }
+    }
+
+    /// The field on a `width` by `height` grid. `params` gives the packet the velocity of a wave
+    /// travelling at `c` through the plain medium.
+    pub fn state(&self, (width, height): (u32, u32), params: &WaveParams) -> Vec<WavePoint> {
+        if self.uses_images() {
+            let field = |img: &Option<GrayImage>| {
+                img.as_ref().map(|img| {
+                    image::imageops::resize(img, width, height, image::imageops::FilterType::Triangle)
+                })
+            };
+            let (displacement, velocity) = (field(&self.displacement), field(&self.velocity));
+            let amplitude = self.amplitude.unwrap_or(1.0);
+            let value = |img: &Option<GrayImage>, x: u32, y: u32| {
+                let offset = img.as_ref().map_or(0.0, |img| img.get_pixel(x, y).0[0] - 0.5);
+                if offset.abs() <= MID_GREY_TOLERANCE { 0.0 } else { 2.0 * amplitude * offset }
+            };
+            return (0..height)
+                .flat_map(|y| (0..width).map(move |x| (x, y)))
+                .map(|(x, y)| WavePoint {
+                    x: value(&displacement, x, y),
+                    v: value(&velocity, x, y),
+                })
+                .collect();
+        }
+
+        let amplitude = self.amplitude.unwrap_or(self.preset.default_amplitude());
+        let mut state = vec![WavePoint { x: 0.0, v: 0.0 }; (width * height) as usize];
+        let block = |i: usize, from: u32, to: u32, n: u32| {
+            (from * n / BLOCK_GRID_SIZE..to * n / BLOCK_GRID_SIZE).contains(&(i as u32))
+        };
+        // Lengths in cells.
+        let unit = width.min(height) as f32;
+        let center = [self.center[0] * width as f32, self.center[1] * height as f32];
+        let (sigma, radius) = (self.width * unit, self.radius * unit);
+        let k = std::f32::consts::TAU / (self.wavelength * unit);
+        let (sin, cos) = self.angle.sin_cos();
+        let mut rng = WyRand::new_seed(self.seed);
+        for (i, point) in state.iter_mut().enumerate() {
+            let (column, row) = (i % width as usize, i / width as usize);
+            let (dx, dy) = (column as f32 + 0.5 - center[0], row as f32 + 0.5 - center[1]);
+            let envelope = |d2: f32| amplitude * (-d2 / (2.0 * sigma * sigma)).exp();
+            *point = match self.preset {
+                Preset::Block => WavePoint {
+                    x: if block(column, 50, 70, width) && block(row, 50, 100, height) { amplitude } else { 0.0 },
+                    v: 0.0,
+                },
+                Preset::Gaussian => WavePoint {
+                    x: envelope(dx * dx + dy * dy),
+                    v: 0.0,
+                },
+                Preset::Packet => {
+                    // Along and across the direction of travel. Moving along means x(s - c t), so
+                    // v = -c dx/ds, with s in cells and c in du per unit of time.
+                    let (s, q) = (dx * cos + dy * sin, dy * cos - dx * sin);
+                    let e = envelope(s * s + q * q);
+                    let slope = e * (-s / (sigma * sigma) * (k * s).cos() - k * (k * s).sin());
+                    WavePoint {
+                        x: e * (k * s).cos(),
+                        v: -params.c / params.du * slope,
+                    }
+                }
+                Preset::Ring => {
+                    let r = (dx * dx + dy * dy).sqrt() - radius;
+                    WavePoint {
+                        x: envelope(r * r),
+                        v: 0.0,
+                    }
+                }
+                Preset::Noise => WavePoint {
+                    x: amplitude * (2.0 * rng.generate::<f32>() - 1.0),
+                    v: 0.0,
+                },
+            };
+        }
+        state
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn block_stays_where_it_was() {
+        let params = WaveParams::new((256, 256));
+        let state = Initial::default().state((256, 256), &params);
+        for (i, point) in state.iter().enumerate() {
+            let (x, y) = (i % 256, i / 256);
+            let inside = (50..70).contains(&x) && (50..100).contains(&y);
+            assert_eq!(point.x, if inside { 2.0 } else { 0.0 }, "at {:?}", (x, y));
+            assert_eq!(point.v, 0.0);
+        }
+    }
+
+    #[test]
+    fn packet_travels_along_its_angle() {
+        // Moving right, the field a little left of any point is where it will be next.
+        // Large enough for central differences, 20 cells a wavelength.
+        let params = WaveParams::new((512, 512));
+        let initial = Initial {
+            preset: Preset::Packet,
+            ..Default::default()
+        };
+        let state = initial.state((512, 512), &params);
+        let row = 256 * 512;
+        let cells_per_time = params.c / params.du;
+        for x in 216..296 {
+            let slope = state[row + x + 1].x - state[row + x - 1].x;
+            let expected = -cells_per_time * slope / 2.0;
+            assert!((state[row + x].v - expected).abs() < 0.05 * cells_per_time, "at {}", x);
+        }
+    }
+}
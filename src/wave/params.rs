@@ -0,0 +1,434 @@
+//! Parameters of the wave equation, as `compute.wgsl` gets them.
+
+use bytemuck::{Pod, Zeroable};
+use serde::{Deserialize, Serialize};
+
+/// Leave a little room below [`WaveParams::max_courant`] when clamping, right at the limit the
+/// highest frequencies don't decay.
+const CLAMP_FACTOR: f32 = 0.99;
+
+/// What happens to waves at the edges of the grid, values match the `BOUNDARY_*` constants in
+/// `compute.wgsl`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", rename_all = "kebab-case")]
+#[repr(u32)]
+pub enum Boundary {
+    /// Fixed walls, `x = 0` at the edges, waves come back upside down.
+    Dirichlet = 0,
+    /// Free edges with no slope across them, waves come back the right way up.
+    Neumann = 1,
+    /// Leaving one edge enters from the opposite one.
+    Periodic = 2,
+    /// First order Mur edges, which let waves hitting them head on out. With
+    /// `WaveParams::absorb_width` a damping layer inside the edges takes care of the rest.
+    Absorbing = 3,
+}
+impl Boundary {
+    pub const ALL: [Boundary; 4] = [Self::Dirichlet, Self::Neumann, Self::Periodic, Self::Absorbing];
+    pub fn from_u32(v: u32) -> Self {
+        Self::ALL.into_iter().find(|it| *it as u32 == v).unwrap_or(Self::Dirichlet)
+    }
+    pub fn next(self) -> Self {
+        Self::from_u32((self as u32 + 1) % Self::ALL.len() as u32)
+    }
+}
+impl std::str::FromStr for Boundary {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "dirichlet" | "fixed" => Ok(Self::Dirichlet),
+            "neumann" | "free" => Ok(Self::Neumann),
+            "periodic" => Ok(Self::Periodic),
+            "absorbing" | "mur" => Ok(Self::Absorbing),
+            _ => Err("expected one of dirichlet, neumann, periodic, absorbing".to_string()),
+        }
+    }
+}
+impl TryFrom<String> for Boundary {
+    type Error = String;
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// How the field is stepped in time, see `compute.wgsl` for the entry points of each.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", rename_all = "kebab-case")]
+#[repr(u32)]
+pub enum Scheme {
+    /// Semi-implicit Euler, `v += a * dt` then `x += v * dt`. One pass.
+    Euler = 0,
+    /// Velocity Verlet, with `v` at the same time as `x`. Two passes.
+    Verlet = 1,
+    /// Classic fourth order Runge-Kutta, which lets `dt` go a bit further. Four passes.
+    #[serde(rename = "rk4")]
+    RungeKutta4 = 2,
+    /// Implicit Crank-Nicolson, stable at any `dt` but smearing out short waves as it grows.
+    /// Solved by `WaveParams::iterations` Jacobi passes.
+    CrankNicolson = 3,
+}
+impl Scheme {
+    pub const ALL: [Scheme; 4] = [Self::Euler, Self::Verlet, Self::RungeKutta4, Self::CrankNicolson];
+    pub fn from_u32(v: u32) -> Self {
+        Self::ALL.into_iter().find(|it| *it as u32 == v).unwrap_or(Self::Euler)
+    }
+    pub fn next(self) -> Self {
+        Self::from_u32((self as u32 + 1) % Self::ALL.len() as u32)
+    }
+}
+impl std::str::FromStr for Scheme {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "euler" => Ok(Self::Euler),
+            "verlet" | "leapfrog" => Ok(Self::Verlet),
+            "rk4" => Ok(Self::RungeKutta4),
+            "crank-nicolson" | "cn" => Ok(Self::CrankNicolson),
+            _ => Err("expected one of euler, verlet, rk4, crank-nicolson".to_string()),
+        }
+    }
+}
+impl TryFrom<String> for Scheme {
+    type Error = String;
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// Discrete Laplacian, values match the `STENCIL_*` constants in `compute.wgsl`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", rename_all = "kebab-case")]
+#[repr(u32)]
+pub enum Stencil {
+    /// Second order, from the four direct neighbours.
+    FivePoint = 0,
+    /// Second order with the diagonals too, which spreads the error evenly over directions.
+    NinePoint = 1,
+    /// Fourth order, from two neighbours each way along both axes.
+    FourthOrder = 2,
+}
+impl Stencil {
+    pub const ALL: [Stencil; 3] = [Self::FivePoint, Self::NinePoint, Self::FourthOrder];
+    pub fn from_u32(v: u32) -> Self {
+        Self::ALL.into_iter().find(|it| *it as u32 == v).unwrap_or(Self::FivePoint)
+    }
+    pub fn next(self) -> Self {
+        Self::from_u32((self as u32 + 1) % Self::ALL.len() as u32)
+    }
+    /// Largest eigenvalue of the stencil times `du^2`, the checkerboard's.
+    fn spectral_radius(self) -> f32 {
+        match self {
+            Self::FivePoint => 8.0,
+            Self::NinePoint => 16.0 / 3.0,
+            Self::FourthOrder => 32.0 / 3.0,
+        }
+    }
+}
+impl std::str::FromStr for Stencil {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "5-point" | "five-point" => Ok(Self::FivePoint),
+            "9-point" | "nine-point" => Ok(Self::NinePoint),
+            "4th-order" | "fourth-order" => Ok(Self::FourthOrder),
+            _ => Err("expected one of 5-point, 9-point, 4th-order".to_string()),
+        }
+    }
+}
+impl TryFrom<String> for Stencil {
+    type Error = String;
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// Uniform for `compute.wgsl`, a multiple of 16 bytes.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+pub struct WaveParams {
+    /// Grid points along x and y.
+    pub width: u32,
+    pub height: u32,
+    /// Wave speed, in the units of `du` per unit of time.
+    pub c: f32,
+    /// Time step of one dispatch.
+    pub dt: f32,
+    /// Grid spacing.
+    pub du: f32,
+    /// Fraction of the velocity lost per unit of time.
+    pub damping: f32,
+    /// A [`Boundary`].
+    pub boundary: u32,
+    /// Cells of damping layer inside absorbing edges, 0 for only the Mur condition.
+    pub absorb_width: u32,
+    /// A [`Scheme`].
+    pub scheme: u32,
+    /// A [`Stencil`].
+    pub stencil: u32,
+    /// Jacobi passes per step for [`Scheme::CrankNicolson`].
+    pub iterations: u32,
+    pub _spacer: u32,
+}
+
+impl WaveParams {
+    pub fn new((width, height): (u32, u32)) -> Self {
+        Self {
+            width,
+            height,
+            // What the constants in the shader used to give, they had c^2 = 0.1.
+            c: 0.316,
+            dt: 0.005,
+            du: 0.01,
+            damping: 0.0,
+            boundary: Boundary::Dirichlet as u32,
+            absorb_width: 0,
+            scheme: Scheme::Euler as u32,
+            stencil: Stencil::FivePoint as u32,
+            iterations: 20,
+            _spacer: 0,
+        }
+    }
+
+    pub fn courant(&self) -> f32 {
+        self.c * self.dt / self.du
+    }
+
+    /// Largest Courant number `c * dt / du` the scheme survives with the stencil, infinite for
+    /// the implicit one.
+    pub fn max_courant(&self) -> f32 {
+        // Where the highest frequency of the stencil leaves the scheme's stability region.
+        let highest = Stencil::from_u32(self.stencil).spectral_radius().sqrt();
+        match Scheme::from_u32(self.scheme) {
+            Scheme::Euler | Scheme::Verlet => 2.0 / highest,
+            Scheme::RungeKutta4 => 2.0 * std::f32::consts::SQRT_2 / highest,
+            Scheme::CrankNicolson => f32::INFINITY,
+        }
+    }
+
+    /// Shrink `dt` if the scheme would blow up otherwise. Returns the `dt` it had, if it did.
+    pub fn enforce_cfl(&mut self) -> Option<f32> {
+        if self.courant() <= self.max_courant() {
+            return None;
+        }
+        let dt = self.dt;
+        self.dt = CLAMP_FACTOR * self.max_courant() * self.du / self.c;
+        Some(dt)
+    }
+
+    /// Like [`WaveParams::enforce_cfl`], with a warning when it clamps.
+    pub fn enforce_cfl_loudly(&mut self) {
+        if let Some(dt) = self.enforce_cfl() {
+            log::warn!(
+                "c * dt / du = {:.3} is above the stability limit {:.3}, clamped dt from {} to {}",
+                self.c * dt / self.du,
+                self.max_courant(),
+                dt,
+                self.dt
+            );
+        }
+    }
+
+    /// Jacobi iterations only converge for stencils whose centre outweighs the rest.
+    pub fn check_scheme(&self) -> anyhow::Result<()> {
+        if Scheme::from_u32(self.scheme) == Scheme::CrankNicolson
+            && Stencil::from_u32(self.stencil) == Stencil::FourthOrder
+        {
+            anyhow::bail!("crank-nicolson only works with the 5-point and 9-point stencils");
+        }
+        Ok(())
+    }
+
+    pub fn log(&self) {
+        log::info!(
+            "c = {:.4}, dt = {:.5}, du = {:.4}, damping = {:.3} (c * dt / du = {:.3}), {:?} boundary",
+            self.c,
+            self.dt,
+            self.du,
+            self.damping,
+            self.courant(),
+            Boundary::from_u32(self.boundary)
+        );
+        log::info!(
+            "{:?} with the {:?} stencil{}",
+            Scheme::from_u32(self.scheme),
+            Stencil::from_u32(self.stencil),
+            match Scheme::from_u32(self.scheme) {
+                Scheme::CrankNicolson => format!(", {} Jacobi iterations", self.iterations),
+                _ => String::new(),
+            }
+        );
+        if Boundary::from_u32(self.boundary) == Boundary::Absorbing && self.absorb_width > 0 {
+            log::info!("Absorbing layer {} cells wide", self.absorb_width);
+        }
+    }
+}
+
+/// Values set by a `--params` file, everything is optional. Snapshots keep the parameters in the
+/// same shape.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ParamsFile {
+    pub c: Option<f32>,
+    pub dt: Option<f32>,
+    pub du: Option<f32>,
+    pub damping: Option<f32>,
+    pub boundary: Option<Boundary>,
+    pub absorb_width: Option<u32>,
+    pub scheme: Option<Scheme>,
+    pub stencil: Option<Stencil>,
+    pub iterations: Option<u32>,
+}
+
+impl From<&WaveParams> for ParamsFile {
+    fn from(params: &WaveParams) -> Self {
+        Self {
+            c: Some(params.c),
+            dt: Some(params.dt),
+            du: Some(params.du),
+            damping: Some(params.damping),
+            boundary: Some(Boundary::from_u32(params.boundary)),
+            absorb_width: Some(params.absorb_width),
+            scheme: Some(Scheme::from_u32(params.scheme)),
+            stencil: Some(Stencil::from_u32(params.stencil)),
+            iterations: Some(params.iterations),
+        }
+    }
+}
+
+impl ParamsFile {
+    /// Set the given values, without checking stability.
+    pub fn apply(&self, params: &mut WaveParams) -> anyhow::Result<()> {
+        let positive = |name: &str, value: Option<f32>| match value {
+            Some(v) if v <= 0.0 => Err(anyhow::anyhow!("{} must be positive, got {}", name, v)),
+            _ => Ok(value),
+        };
+        if let Some(c) = positive("c", self.c)? {
+            params.c = c;
+        }
+        if let Some(dt) = positive("dt", self.dt)? {
+            params.dt = dt;
+        }
+        if let Some(du) = positive("du", self.du)? {
+            params.du = du;
+        }
+        if let Some(damping) = self.damping {
+            if damping < 0.0 {
+                anyhow::bail!("damping can't be negative, got {}", damping);
+            }
+            params.damping = damping;
+        }
+        if let Some(boundary) = self.boundary {
+            params.boundary = boundary as u32;
+        }
+        if let Some(absorb_width) = self.absorb_width {
+            let limit = params.width.min(params.height) / 2;
+            if absorb_width > limit {
+                anyhow::bail!("absorb_width can be at most half the grid, {} cells, got {}", limit, absorb_width);
+            }
+            params.absorb_width = absorb_width;
+        }
+        if let Some(scheme) = self.scheme {
+            params.scheme = scheme as u32;
+        }
+        if let Some(stencil) = self.stencil {
+            params.stencil = stencil as u32;
+        }
+        if let Some(iterations) = self.iterations {
+            if iterations == 0 {
+                anyhow::bail!("iterations must be at least 1");
+            }
+            params.iterations = iterations;
+        }
+        params.check_scheme()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Stability limits worked out by hand: `2 / sqrt(rho)` for Euler and Verlet, `2 sqrt(2) /
+    /// sqrt(rho)` for RK4, with the spectral radius `rho` of 8, 16/3 and 32/3 for the stencils.
+    fn expected_limit(scheme: Scheme, stencil: Stencil) -> f32 {
+        let rho: f32 = match stencil {
+            Stencil::FivePoint => 8.0,
+            Stencil::NinePoint => 16.0 / 3.0,
+            Stencil::FourthOrder => 32.0 / 3.0,
+        };
+        match scheme {
+            Scheme::Euler | Scheme::Verlet => 2.0 / rho.sqrt(),
+            Scheme::RungeKutta4 => 2.0 * 2f32.sqrt() / rho.sqrt(),
+            Scheme::CrankNicolson => f32::INFINITY,
+        }
+    }
+
+    fn params(scheme: Scheme, stencil: Stencil, courant: f32) -> WaveParams {
+        let mut params = WaveParams::new((64, 64));
+        params.scheme = scheme as u32;
+        params.stencil = stencil as u32;
+        params.dt = courant * params.du / params.c;
+        params
+    }
+
+    #[test]
+    fn cfl_limits() {
+        for scheme in Scheme::ALL {
+            for stencil in Stencil::ALL {
+                let what = format!("{:?} {:?}", scheme, stencil);
+                let limit = expected_limit(scheme, stencil);
+                let max_courant = params(scheme, stencil, 1.0).max_courant();
+                if scheme == Scheme::CrankNicolson {
+                    assert_eq!(max_courant, f32::INFINITY, "{}", what);
+                    assert_eq!(params(scheme, stencil, 100.0).enforce_cfl(), None, "{}", what);
+                    continue;
+                }
+                assert!((max_courant - limit).abs() <= 1e-6 * limit, "{}", what);
+
+                let mut below = params(scheme, stencil, 0.99 * limit);
+                let dt = below.dt;
+                assert_eq!(below.enforce_cfl(), None, "{}", what);
+                assert_eq!(below.dt, dt, "{}", what);
+
+                let mut above = params(scheme, stencil, 1.01 * limit);
+                let dt = above.dt;
+                assert_eq!(above.enforce_cfl(), Some(dt), "{}", what);
+                assert!(above.courant() <= limit && above.courant() >= 0.98 * limit, "{}", what);
+                assert_eq!(above.enforce_cfl(), None, "{}", what);
+            }
+        }
+    }
+
+    #[test]
+    fn crank_nicolson_needs_a_diagonally_dominant_stencil() {
+        for scheme in Scheme::ALL {
+            for stencil in Stencil::ALL {
+                let rejected = scheme == Scheme::CrankNicolson && stencil == Stencil::FourthOrder;
+                assert_eq!(params(scheme, stencil, 0.1).check_scheme().is_err(), rejected, "{:?} {:?}", scheme, stencil);
+            }
+        }
+        let file = ParamsFile {
+            scheme: Some(Scheme::CrankNicolson),
+            stencil: Some(Stencil::FourthOrder),
+            ..Default::default()
+        };
+        assert!(file.apply(&mut WaveParams::new((64, 64))).is_err());
+    }
+
+    #[test]
+    fn params_file_rejects_zero_iterations() {
+        let mut params = WaveParams::new((64, 64));
+        let file = ParamsFile {
+            iterations: Some(0),
+            ..Default::default()
+        };
+        assert!(file.apply(&mut params).is_err());
+        assert_eq!(params.iterations, WaveParams::new((64, 64)).iterations);
+
+        let file = ParamsFile {
+            iterations: Some(3),
+            ..Default::default()
+        };
+        file.apply(&mut params).unwrap();
+        assert_eq!(params.iterations, 3);
+    }
+}
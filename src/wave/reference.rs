@@ -0,0 +1,333 @@
+//! CPU reference for the schemes of `shaders/compute.wgsl`, in f32.
+//!
+//! Everything here mirrors the WGSL function of the same name, down to the order of the
+//! arithmetic and the passes each scheme dispatches, so a few steps on both should agree to
+//! rounding. Sources aren't covered: no emitters and no brush.
+
+use super::{
+    core::WavePoint,
+    params::{Boundary, Scheme, Stencil, WaveParams},
+};
+
+type Point = (i32, i32);
+/// What the scratch buffers hold for every point, see `scratch_0` in the shader.
+type Scratch = Vec<[f32; 4]>;
+
+/// See `MEDIUM_DAMPING` in the shader.
+const MEDIUM_DAMPING: f32 = 50.0;
+
+/// Where a pass reads the state it steps from, like `FROM_IN` and `FROM_SCRATCH_*`.
+#[derive(Clone, Copy)]
+enum Src<'a> {
+    In,
+    Scratch(&'a [[f32; 4]]),
+}
+
+/// One dispatch's view of the grid: `d_in`, the parameters and the medium.
+struct Field<'a> {
+    params: &'a WaveParams,
+    medium: &'a image::RgbaImage,
+    d_in: &'a [WavePoint],
+}
+
+impl Field<'_> {
+    fn size(&self) -> Point {
+        (self.params.width as i32, self.params.height as i32)
+    }
+
+    fn index(&self, (x, y): Point) -> usize {
+        (x + y * self.params.width as i32) as usize
+    }
+
+    fn boundary(&self) -> Boundary {
+        Boundary::from_u32(self.params.boundary)
+    }
+
+    /// Every point of the grid through `f`, what one dispatch of a pass writes.
+    fn pass<T>(&self, f: impl Fn(Point) -> T) -> Vec<T> {
+        let (width, height) = self.size();
+        (0..height).flat_map(|y| (0..width).map(move |x| (x, y))).map(f).collect()
+    }
+
+    fn medium_at(&self, (x, y): Point) -> [f32; 4] {
+        let (width, height) = self.size();
+        let (dw, dh) = (self.medium.width() as i32, self.medium.height() as i32);
+        let texel = self.medium.get_pixel((x * dw / width) as u32, (y * dh / height) as u32);
+        texel.0.map(|it| it as f32 / 255.0)
+    }
+
+    fn state(&self, src: Src, p: Point) -> [f32; 2] {
+        match src {
+            Src::In => {
+                let point = self.d_in[self.index(p)];
+                [point.x, point.v]
+            }
+            Src::Scratch(scratch) => {
+                let [x, v, _, _] = scratch[self.index(p)];
+                [x, v]
+            }
+        }
+    }
+
+    fn displacement(&self, src: Src, (x, y): Point) -> f32 {
+        let (width, height) = self.size();
+        let q = match self.boundary() {
+            Boundary::Periodic => ((x + width) % width, (y + height) % height),
+            _ => (x.clamp(0, width - 1), y.clamp(0, height - 1)),
+        };
+        self.state(src, q)[0]
+    }
+
+    fn center_weight(&self) -> f32 {
+        match Stencil::from_u32(self.params.stencil) {
+            Stencil::FivePoint => -4.0,
+            Stencil::NinePoint => -20.0 / 6.0,
+            Stencil::FourthOrder => -5.0,
+        }
+    }
+
+    fn laplacian(&self, src: Src, (x, y): Point) -> f32 {
+        let d = |dx: i32, dy: i32| self.displacement(src, (x + dx, y + dy));
+        let here = d(0, 0);
+        let sides = d(1, 0) + d(-1, 0) + d(0, 1) + d(0, -1);
+        let sum = match Stencil::from_u32(self.params.stencil) {
+            Stencil::FivePoint => sides + self.center_weight() * here,
+            Stencil::NinePoint => {
+                let corners = d(1, 1) + d(-1, 1) + d(1, -1) + d(-1, -1);
+                (4.0 * sides + corners) / 6.0 + self.center_weight() * here
+            }
+            Stencil::FourthOrder => {
+                let far = d(2, 0) + d(-2, 0) + d(0, 2) + d(0, -2);
+                4.0 / 3.0 * sides - far / 12.0 + self.center_weight() * here
+            }
+        };
+        sum / (self.params.du * self.params.du)
+    }
+
+    fn speed_squared(&self, p: Point) -> f32 {
+        let c = self.params.c * self.medium_at(p)[0];
+        c * c
+    }
+
+    fn layer_damping(&self, (x, y): Point) -> f32 {
+        if self.boundary() != Boundary::Absorbing || self.params.absorb_width == 0 {
+            return 0.0;
+        }
+        let (width, height) = self.size();
+        let edge = x.min(y).min((width - 1 - x).min(height - 1 - y));
+        let layer = self.params.absorb_width as f32;
+        let depth = (layer - edge as f32).max(0.0) / layer;
+        let strongest = 10.0 * self.params.c / (layer * self.params.du);
+        strongest * depth * depth
+    }
+
+    fn damping_at(&self, p: Point) -> f32 {
+        self.params.damping + self.medium_at(p)[1] * MEDIUM_DAMPING + self.layer_damping(p)
+    }
+
+    fn on_edge(&self, (x, y): Point) -> bool {
+        let (width, height) = self.size();
+        x == 0 || y == 0 || x == width - 1 || y == height - 1
+    }
+
+    fn is_obstacle(&self, p: Point) -> bool {
+        self.medium_at(p)[2] > 0.5
+    }
+
+    fn is_stepped(&self, p: Point) -> bool {
+        let free_edges = matches!(self.boundary(), Boundary::Neumann | Boundary::Periodic);
+        !self.is_obstacle(p) && (!self.on_edge(p) || free_edges)
+    }
+
+    fn is_mur_edge(&self, p: Point) -> bool {
+        !self.is_obstacle(p) && self.on_edge(p) && self.boundary() == Boundary::Absorbing
+    }
+
+    fn held(&self, p: Point) -> [f32; 2] {
+        if self.is_mur_edge(p) {
+            self.state(Src::In, p)
+        } else {
+            [0.0; 2]
+        }
+    }
+
+    fn inner_of(&self, (x, y): Point) -> Point {
+        let (width, height) = self.size();
+        let inward = |i: i32, n: i32| (i == 0) as i32 - (i == n - 1) as i32;
+        (x + inward(x, width), y + inward(y, height))
+    }
+
+    fn mur(&self, p: Point, inner_x: f32) -> WavePoint {
+        let inner = self.inner_of(p);
+        let (dx, dy) = ((inner.0 - p.0) as f32, (inner.1 - p.1) as f32);
+        let distance = self.params.du * (dx * dx + dy * dy).sqrt();
+        let courant = self.speed_squared(p).sqrt() * self.params.dt;
+        let k = (courant - distance) / (courant + distance);
+        let before = self.d_in[self.index(p)];
+        let x = self.d_in[self.index(inner)].x + k * (inner_x - before.x);
+        WavePoint {
+            x,
+            v: (x - before.x) / self.params.dt,
+        }
+    }
+
+    /// The last pass of every scheme, given how the scheme steps a point.
+    fn finish(&self, p: Point, point: impl Fn(Point) -> WavePoint) -> WavePoint {
+        if self.is_stepped(p) {
+            point(p)
+        } else if self.is_mur_edge(p) {
+            self.mur(p, point(self.inner_of(p)).x)
+        } else {
+            WavePoint { x: 0.0, v: 0.0 }
+        }
+    }
+
+    fn euler_point(&self, p: Point) -> WavePoint {
+        let dt = self.params.dt;
+        let mut out = self.d_in[self.index(p)];
+        out.v = (out.v + self.speed_squared(p) * self.laplacian(Src::In, p) * dt) / (1.0 + self.damping_at(p) * dt);
+        out.x += out.v * dt;
+        out
+    }
+
+    fn half_kick(&self, src: Src, p: Point, v: f32) -> f32 {
+        let dt = self.params.dt / 2.0;
+        (v + self.speed_squared(p) * self.laplacian(src, p) * dt) / (1.0 + self.damping_at(p) * dt)
+    }
+
+    fn verlet_drift(&self, p: Point) -> [f32; 4] {
+        let [x, v] = if self.is_stepped(p) {
+            let here = self.d_in[self.index(p)];
+            let v = self.half_kick(Src::In, p, here.v);
+            [here.x + v * self.params.dt, v]
+        } else {
+            self.held(p)
+        };
+        [x, v, 0.0, 0.0]
+    }
+
+    fn verlet_point(&self, drifted: &[[f32; 4]], p: Point) -> WavePoint {
+        let [x, v, _, _] = drifted[self.index(p)];
+        WavePoint {
+            x,
+            v: self.half_kick(Src::Scratch(drifted), p, v),
+        }
+    }
+
+    fn slope(&self, src: Src, p: Point) -> [f32; 2] {
+        let [_, v] = self.state(src, p);
+        [v, self.speed_squared(p) * self.laplacian(src, p) - self.damping_at(p) * v]
+    }
+
+    fn rk4_stage(&self, src: Src, p: Point, fraction: f32, weight: f32, sum: [f32; 2]) -> [f32; 4] {
+        if !self.is_stepped(p) {
+            let [x, v] = self.held(p);
+            return [x, v, 0.0, 0.0];
+        }
+        let k = self.slope(src, p);
+        let here = self.state(Src::In, p);
+        let step = fraction * self.params.dt;
+        [
+            here[0] + step * k[0],
+            here[1] + step * k[1],
+            sum[0] + weight * k[0],
+            sum[1] + weight * k[1],
+        ]
+    }
+
+    fn rk4_point(&self, last: &[[f32; 4]], p: Point) -> WavePoint {
+        let [_, _, s0, s1] = last[self.index(p)];
+        let k = self.slope(Src::Scratch(last), p);
+        let here = self.state(Src::In, p);
+        let step = self.params.dt / 6.0;
+        WavePoint {
+            x: here[0] + step * (s0 + k[0]),
+            v: here[1] + step * (s1 + k[1]),
+        }
+    }
+
+    fn cn_alpha(&self, p: Point) -> f32 {
+        let b = self.damping_at(p) * self.params.dt / 2.0;
+        self.speed_squared(p) * self.params.dt * self.params.dt / (4.0 * (1.0 + b))
+    }
+
+    fn cn_start(&self, p: Point) -> [f32; 4] {
+        if !self.is_stepped(p) {
+            let x = self.held(p)[0];
+            return [x, x, 0.0, 0.0];
+        }
+        let here = self.d_in[self.index(p)];
+        let b = self.damping_at(p) * self.params.dt / 2.0;
+        let predicted = here.x + self.params.dt * here.v / (1.0 + b);
+        let rhs = predicted + self.cn_alpha(p) * self.laplacian(Src::In, p);
+        [predicted, rhs, 0.0, 0.0]
+    }
+
+    fn jacobi(&self, guesses: &[[f32; 4]], p: Point) -> [f32; 4] {
+        let [x, rhs] = self.state(Src::Scratch(guesses), p);
+        if !self.is_stepped(p) {
+            return [x, rhs, 0.0, 0.0];
+        }
+        let du = self.params.du;
+        let a = self.cn_alpha(p) / (du * du);
+        let neighbours = self.laplacian(Src::Scratch(guesses), p) * du * du - self.center_weight() * x;
+        [(rhs + a * neighbours) / (1.0 - a * self.center_weight()), rhs, 0.0, 0.0]
+    }
+
+    fn cn_point(&self, solution: &[[f32; 4]], p: Point) -> WavePoint {
+        let here = self.d_in[self.index(p)];
+        let x = solution[self.index(p)][0];
+        WavePoint {
+            x,
+            v: 2.0 * (x - here.x) / self.params.dt - here.v,
+        }
+    }
+}
+
+/// `input` one step on with [`Scheme::Euler`], what the `euler` pass writes to `d_out`.
+pub fn euler(params: &WaveParams, medium: &image::RgbaImage, input: &[WavePoint]) -> Vec<WavePoint> {
+    let field = Field { params, medium, d_in: input };
+    field.pass(|p| field.finish(p, |p| field.euler_point(p)))
+}
+
+/// `input` one step on with [`Scheme::Verlet`], through `verlet_drift` and `verlet_kick`.
+pub fn verlet(params: &WaveParams, medium: &image::RgbaImage, input: &[WavePoint]) -> Vec<WavePoint> {
+    let field = Field { params, medium, d_in: input };
+    let drifted: Scratch = field.pass(|p| field.verlet_drift(p));
+    field.pass(|p| field.finish(p, |p| field.verlet_point(&drifted, p)))
+}
+
+/// `input` one step on with [`Scheme::RungeKutta4`], through the three `rk4_stage_*` passes and
+/// `rk4_finish`.
+pub fn rk4(params: &WaveParams, medium: &image::RgbaImage, input: &[WavePoint]) -> Vec<WavePoint> {
+    let field = Field { params, medium, d_in: input };
+    let sum = |scratch: &[[f32; 4]], p| {
+        let [_, _, s0, s1] = scratch[field.index(p)];
+        [s0, s1]
+    };
+    let scratch_0: Scratch = field.pass(|p| field.rk4_stage(Src::In, p, 0.5, 1.0, [0.0; 2]));
+    let scratch_1: Scratch = field.pass(|p| field.rk4_stage(Src::Scratch(&scratch_0), p, 0.5, 2.0, sum(&scratch_0, p)));
+    let scratch_0: Scratch = field.pass(|p| field.rk4_stage(Src::Scratch(&scratch_1), p, 1.0, 2.0, sum(&scratch_1, p)));
+    field.pass(|p| field.finish(p, |p| field.rk4_point(&scratch_0, p)))
+}
+
+/// `input` one step on with [`Scheme::CrankNicolson`], through `cn_start`, `params.iterations`
+/// Jacobi passes and `cn_finish_*`.
+pub fn crank_nicolson(params: &WaveParams, medium: &image::RgbaImage, input: &[WavePoint]) -> Vec<WavePoint> {
+    let field = Field { params, medium, d_in: input };
+    let mut guesses: Scratch = field.pass(|p| field.cn_start(p));
+    for _ in 0..params.iterations {
+        guesses = field.pass(|p| field.jacobi(&guesses, p));
+    }
+    field.pass(|p| field.finish(p, |p| field.cn_point(&guesses, p)))
+}
+
+/// `input` one step on with `params.scheme`.
+pub fn step(params: &WaveParams, medium: &image::RgbaImage, input: &[WavePoint]) -> Vec<WavePoint> {
+    match Scheme::from_u32(params.scheme) {
+        Scheme::Euler => euler(params, medium, input),
+        Scheme::Verlet => verlet(params, medium, input),
+        Scheme::RungeKutta4 => rk4(params, medium, input),
+        Scheme::CrankNicolson => crank_nicolson(params, medium, input),
+    }
+}
@@ -3,45 +3,507 @@ struct WavePoint {
     v: f32,
 };
 
-const size: u32 = 256u;
+// See `wave::params::WaveParams`.
+struct WaveParams {
+    width: u32,
+    height: u32,
+    c: f32,
+    dt: f32,
+    du: f32,
+    damping: f32,
+    boundary: u32,
+    absorb_width: u32,
+    scheme: u32,
+    stencil: u32,
+    iterations: u32,
+};
+
+// See `wave::params::Boundary`.
+const BOUNDARY_DIRICHLET: u32 = 0u;
+const BOUNDARY_NEUMANN: u32 = 1u;
+const BOUNDARY_PERIODIC: u32 = 2u;
+const BOUNDARY_ABSORBING: u32 = 3u;
+
+// See `wave::params::Stencil`.
+const STENCIL_NINE_POINT: u32 = 1u;
+const STENCIL_FOURTH_ORDER: u32 = 2u;
+
+// See `wave::sources::Brush`.
+const BRUSH_DISPLACEMENT: u32 = 1u;
+const BRUSH_IMPULSE: u32 = 2u;
 
-const c: f32 = 0.1;
-const dt: f32 = 0.005;
-const du: f32 = 0.01;
+// See `wave::sources::Emitter`, in grid cells.
+struct Emitter {
+    start: vec2<f32>,
+    end: vec2<f32>,
+    frequency: f32,
+    amplitude: f32,
+    phase: f32,
+    radius: f32,
+};
+
+// See `wave::sources::SourceData`.
+struct Sources {
+    time: f32,
+    count: u32,
+    brush: u32,
+    brush_radius: f32,
+    brush_from: vec2<f32>,
+    brush_to: vec2<f32>,
+    brush_strength: f32,
+    // MAX_EMITTERS
+    @align(16) emitters: array<Emitter, 16>,
+};
+
+// Damping of a cell with a full medium damping channel, see `Wave::load_medium`.
+const MEDIUM_DAMPING: f32 = 50.0;
+
+// Where a pass reads the state it steps from, see `state`.
+const FROM_IN: u32 = 0u;
+const FROM_SCRATCH_0: u32 = 1u;
+const FROM_SCRATCH_1: u32 = 2u;
 
 @group(0) @binding(0) var<storage, read> d_in: array<WavePoint>;
 @group(0) @binding(1) var<storage, read_write> d_out: array<WavePoint>;
 @group(0) @binding(2) var out_tex: texture_storage_2d<rgba8unorm, write>;
+// Intermediate states of the schemes with more than one pass, what goes in them is up to each.
+// `.xy` is always a state the next pass can step from.
+@group(0) @binding(3) var<storage, read_write> scratch_0: array<vec4<f32>>;
+@group(0) @binding(4) var<storage, read_write> scratch_1: array<vec4<f32>>;
+
+@group(1) @binding(0) var<uniform> params: WaveParams;
+
+// Stretched over the whole grid, whatever its size. Red scales the wave speed, green adds damping
+// and blue above one half is an obstacle.
+@group(2) @binding(0) var medium: texture_2d<f32>;
+
+@group(3) @binding(0) var<uniform> sources: Sources;
+
+fn index(p: vec2<i32>) -> u32 {
+    return u32(p.x) + u32(p.y) * params.width;
+}
+
+fn grid_size() -> vec2<i32> {
+    return vec2<i32>(i32(params.width), i32(params.height));
+}
+
+fn medium_at(p: vec2<i32>) -> vec4<f32> {
+    let dims = vec2<i32>(textureDimensions(medium));
+    return textureLoad(medium, p * dims / grid_size(), 0);
+}
+
+// `x` and `v` at point `i` of the state in `src`.
+fn state(src: u32, i: u32) -> vec2<f32> {
+    if (src == FROM_SCRATCH_0) {
+        return scratch_0[i].xy;
+    } else if (src == FROM_SCRATCH_1) {
+        return scratch_1[i].xy;
+    }
+    let point = d_in[i];
+    return vec2<f32>(point.x, point.v);
+}
+
+// Displacement at `p`, which may be up to two steps off the grid.
+fn displacement(src: u32, p: vec2<i32>) -> f32 {
+    let size = grid_size();
+    var q = p;
+    if (params.boundary == BOUNDARY_PERIODIC) {
+        // Never more than two off and the grid is larger, so this stays clear of `%` on negative
+        // numbers, which some backends leave undefined.
+        q = (p + size) % size;
+    } else {
+        // Off the edge mirrors the edge itself, no slope across it. Only Neumann edges are updated
+        // with this, the others set their edges on their own.
+        q = clamp(p, vec2<i32>(0), size - 1);
+    }
+    return state(src, index(q)).x;
+}
+
+// Weight of the point itself in `laplacian`, times du^2.
+fn center_weight() -> f32 {
+    if (params.stencil == STENCIL_NINE_POINT) {
+        return -20.0 / 6.0;
+    } else if (params.stencil == STENCIL_FOURTH_ORDER) {
+        return -5.0;
+    }
+    return -4.0;
+}
+
+fn laplacian(src: u32, p: vec2<i32>) -> f32 {
+    let here = displacement(src, p);
+    let sides = displacement(src, p + vec2<i32>(1, 0)) + displacement(src, p - vec2<i32>(1, 0))
+        + displacement(src, p + vec2<i32>(0, 1)) + displacement(src, p - vec2<i32>(0, 1));
+    var sum = sides + center_weight() * here;
+    if (params.stencil == STENCIL_NINE_POINT) {
+        let corners = displacement(src, p + vec2<i32>(1, 1)) + displacement(src, p + vec2<i32>(-1, 1))
+            + displacement(src, p + vec2<i32>(1, -1)) + displacement(src, p + vec2<i32>(-1, -1));
+        sum = (4.0 * sides + corners) / 6.0 + center_weight() * here;
+    } else if (params.stencil == STENCIL_FOURTH_ORDER) {
+        let far = displacement(src, p + vec2<i32>(2, 0)) + displacement(src, p - vec2<i32>(2, 0))
+            + displacement(src, p + vec2<i32>(0, 2)) + displacement(src, p - vec2<i32>(0, 2));
+        sum = 4.0 / 3.0 * sides - far / 12.0 + center_weight() * here;
+    }
+    return sum / (params.du * params.du);
+}
+
+fn speed_squared(p: vec2<i32>) -> f32 {
+    let c = params.c * medium_at(p).r;
+    return c * c;
+}
+
+// Extra damping inside the absorbing layer, growing quadratically towards the edges.
+fn layer_damping(p: vec2<i32>) -> f32 {
+    if (params.boundary != BOUNDARY_ABSORBING || params.absorb_width == 0u) {
+        return 0.0;
+    }
+    let size = vec2<i32>(i32(params.width), i32(params.height));
+    let edge = min(min(p.x, p.y), min(size.x - 1 - p.x, size.y - 1 - p.y));
+    let width = f32(params.absorb_width);
+    let depth = max(width - f32(edge), 0.0) / width;
+    // Strong enough to leave about 1e-3 of a wave crossing the layer twice.
+    let strongest = 10.0 * params.c / (width * params.du);
+    return strongest * depth * depth;
+}
+
+fn damping_at(p: vec2<i32>) -> f32 {
+    return params.damping + medium_at(p).g * MEDIUM_DAMPING + layer_damping(p);
+}
+
+// Distance from `p` to the line from `a` to `b`.
+fn segment_distance(p: vec2<f32>, a: vec2<f32>, b: vec2<f32>) -> f32 {
+    let ab = b - a;
+    let t = select(0.0, clamp(dot(p - a, ab) / dot(ab, ab), 0.0, 1.0), dot(ab, ab) > 0.0);
+    return distance(p, a + t * ab);
+}
+
+// How much of a source with `radius` reaches `distance` away, nothing past three radii.
+fn falloff(distance: f32, radius: f32) -> f32 {
+    let r = distance / radius;
+    return select(0.0, exp(-r * r), r < 3.0);
+}
+
+// Drive `point` at `p` from the emitters and the brush, `before` is where it was last step.
+fn apply_sources(p: vec2<i32>, point: WavePoint, before: WavePoint) -> WavePoint {
+    let center = vec2<f32>(p) + 0.5;
+    var out = point;
+    for (var i = 0u; i < sources.count; i++) {
+        let e = sources.emitters[i];
+        let weight = falloff(segment_distance(center, e.start, e.end), e.radius);
+        if (weight > 0.0) {
+            // Hard sources, the field follows the emitter where it is.
+            let target_x = e.amplitude * sin(6.2831853 * e.frequency * sources.time + e.phase);
+            out.x = mix(out.x, target_x, weight);
+            out.v = (out.x - before.x) / params.dt;
+        }
+    }
+    if (sources.brush != 0u) {
+        let amount = sources.brush_strength
+            * falloff(segment_distance(center, sources.brush_from, sources.brush_to), sources.brush_radius);
+        if (sources.brush == BRUSH_DISPLACEMENT) {
+            out.x += amount;
+        } else if (sources.brush == BRUSH_IMPULSE) {
+            out.v += amount;
+        }
+    }
+    return out;
+}
+
+fn on_edge(p: vec2<i32>) -> bool {
+    return any(p == vec2<i32>(0)) || any(p == grid_size() - 1);
+}
+
+fn is_obstacle(p: vec2<i32>) -> bool {
+    return medium_at(p).b > 0.5;
+}
+
+// Whether the schemes step the point, rather than the boundary or an obstacle setting it.
+fn is_stepped(p: vec2<i32>) -> bool {
+    let free_edges = params.boundary == BOUNDARY_NEUMANN || params.boundary == BOUNDARY_PERIODIC;
+    return !is_obstacle(p) && (!on_edge(p) || free_edges);
+}
+
+fn is_mur_edge(p: vec2<i32>) -> bool {
+    return !is_obstacle(p) && on_edge(p) && params.boundary == BOUNDARY_ABSORBING;
+}
+
+// What points that aren't stepped hold between passes: their last state on Mur edges and zero
+// elsewhere.
+fn held(p: vec2<i32>) -> vec2<f32> {
+    return select(vec2<f32>(0.0), state(FROM_IN, index(p)), is_mur_edge(p));
+}
+
+// The next point in from an edge point, corners look diagonally.
+fn inner_of(p: vec2<i32>) -> vec2<i32> {
+    let inward = select(vec2<i32>(0), vec2<i32>(1), p == vec2<i32>(0))
+        - select(vec2<i32>(0), vec2<i32>(1), p == grid_size() - 1);
+    return p + inward;
+}
+
+// First order Mur: waves leave along the normal at speed c. Takes where the scheme moved the
+// inner point to.
+fn mur(p: vec2<i32>, inner_x: f32) -> WavePoint {
+    let inner = inner_of(p);
+    let distance = params.du * length(vec2<f32>(inner - p));
+    let courant = sqrt(speed_squared(p)) * params.dt;
+    let k = (courant - distance) / (courant + distance);
+    let before = d_in[index(p)];
+    let x = d_in[index(inner)].x + k * (inner_x - before.x);
+    return WavePoint(x, (x - before.x) / params.dt);
+}
+
+// Store the stepped point, after the sources had their say.
+fn finish(p: vec2<i32>, stepped: WavePoint) {
+    var out = stepped;
+    if (!is_obstacle(p)) {
+        out = apply_sources(p, out, d_in[index(p)]);
+    }
+    d_out[index(p)] = out;
+
+    // Obstacles show up grey and slow regions green.
+    let m = medium_at(p);
+    let background = select(vec4<f32>(0.0, 0.15 * (1.0 - m.r), 0.0, 0.0), vec4<f32>(0.25, 0.25, 0.25, 0.0), m.b > 0.5);
+    textureStore(out_tex, p, vec4<f32>(-out.x, 0.0, out.x, 1.0) + background);
+}
+
+// Check bounds to avoid accessing out-of-range pixels
+fn outside(p: vec2<i32>) -> bool {
+    return any(p >= grid_size());
+}
+
+// Semi-implicit Euler, the point at `p` one step on.
+fn euler_point(p: vec2<i32>) -> WavePoint {
+    let dt = params.dt;
+    var out = d_in[index(p)];
+    // Implicit in the damping, so a thick layer can't overshoot and flip the velocity.
+    out.v = (out.v + speed_squared(p) * laplacian(FROM_IN, p) * dt) / (1.0 + damping_at(p) * dt);
+    out.x += out.v * dt;
+    return out;
+}
+
+@compute
+@workgroup_size(16, 16, 1) // WORKGROUP_SIZE
+fn euler(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let p = vec2<i32>(global_id.xy);
+    if (outside(p)) {
+        return;
+    }
+    var out = WavePoint(0.0, 0.0);
+    if (is_stepped(p)) {
+        out = euler_point(p);
+    } else if (is_mur_edge(p)) {
+        out = mur(p, euler_point(inner_of(p)).x);
+    }
+    finish(p, out);
+}
+
+// Velocity Verlet: half a kick and the drift into `scratch_0`, then the other half kick with the
+// forces at the new positions.
+
+// A half step of velocity, with the forces of the positions in `src`.
+fn half_kick(src: u32, p: vec2<i32>, v: f32) -> f32 {
+    let dt = params.dt / 2.0;
+    return (v + speed_squared(p) * laplacian(src, p) * dt) / (1.0 + damping_at(p) * dt);
+}
 
 @compute
-@workgroup_size(16, 16, 1)
-fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
-    // Get the current pixel position in the 2D array
-    let pixelX = global_id.x;
-    let pixelY = global_id.y;
-    let coord = vec2<i32>(global_id.xy);
-
-    // Check bounds to avoid accessing out-of-range pixels
-    if (pixelX < size - 1u && pixelY < size - 1u && pixelX > 0u && pixelY > 0u) {
-        let i = pixelX + pixelY * size;
-
-        let d2u_dt2 = c * (
-            (
-                (d_in[i + 1u].x - d_in[i].x) / du -
-                (d_in[i].x - d_in[i - 1u].x) / du
-            ) / du +
-            (
-                (d_in[i + size].x - d_in[i].x) / du -
-                (d_in[i].x - d_in[i - size].x) / du
-            ) / du
-        );
-
-        var out = d_in[i];
-        out.v += d2u_dt2 * dt;
-        out.x += out.v * dt;
-
-        d_out[i] = out;
-        
-        textureStore(out_tex, coord, vec4<f32>(-out.x, 0.0, out.x, 1.0));
-    }
-}
\ No newline at end of file
+@workgroup_size(16, 16, 1) // WORKGROUP_SIZE
+fn verlet_drift(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let p = vec2<i32>(global_id.xy);
+    if (outside(p)) {
+        return;
+    }
+    var drifted = held(p);
+    if (is_stepped(p)) {
+        let here = d_in[index(p)];
+        let v = half_kick(FROM_IN, p, here.v);
+        drifted = vec2<f32>(here.x + v * params.dt, v);
+    }
+    scratch_0[index(p)] = vec4<f32>(drifted, 0.0, 0.0);
+}
+
+fn verlet_point(p: vec2<i32>) -> WavePoint {
+    let drifted = scratch_0[index(p)];
+    return WavePoint(drifted.x, half_kick(FROM_SCRATCH_0, p, drifted.y));
+}
+
+@compute
+@workgroup_size(16, 16, 1) // WORKGROUP_SIZE
+fn verlet_kick(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let p = vec2<i32>(global_id.xy);
+    if (outside(p)) {
+        return;
+    }
+    var out = WavePoint(0.0, 0.0);
+    if (is_stepped(p)) {
+        out = verlet_point(p);
+    } else if (is_mur_edge(p)) {
+        out = mur(p, verlet_point(inner_of(p)).x);
+    }
+    finish(p, out);
+}
+
+// Classic Runge-Kutta. Every stage leaves the state the next one starts from in `.xy` and the
+// weighted sum of the slopes so far in `.zw`, ping-ponging between the scratch buffers.
+
+// Time derivative of `x` and `v` in `src`.
+fn slope(src: u32, p: vec2<i32>) -> vec2<f32> {
+    let here = state(src, index(p));
+    return vec2<f32>(here.y, speed_squared(p) * laplacian(src, p) - damping_at(p) * here.y);
+}
+
+fn rk4_stage(src: u32, p: vec2<i32>, fraction: f32, weight: f32, sum: vec2<f32>) -> vec4<f32> {
+    if (!is_stepped(p)) {
+        return vec4<f32>(held(p), 0.0, 0.0);
+    }
+    let k = slope(src, p);
+    return vec4<f32>(state(FROM_IN, index(p)) + fraction * params.dt * k, sum + weight * k);
+}
+
+@compute
+@workgroup_size(16, 16, 1) // WORKGROUP_SIZE
+fn rk4_stage_1(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let p = vec2<i32>(global_id.xy);
+    if (outside(p)) {
+        return;
+    }
+    scratch_0[index(p)] = rk4_stage(FROM_IN, p, 0.5, 1.0, vec2<f32>(0.0));
+}
+
+@compute
+@workgroup_size(16, 16, 1) // WORKGROUP_SIZE
+fn rk4_stage_2(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let p = vec2<i32>(global_id.xy);
+    if (outside(p)) {
+        return;
+    }
+    scratch_1[index(p)] = rk4_stage(FROM_SCRATCH_0, p, 0.5, 2.0, scratch_0[index(p)].zw);
+}
+
+@compute
+@workgroup_size(16, 16, 1) // WORKGROUP_SIZE
+fn rk4_stage_3(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let p = vec2<i32>(global_id.xy);
+    if (outside(p)) {
+        return;
+    }
+    scratch_0[index(p)] = rk4_stage(FROM_SCRATCH_1, p, 1.0, 2.0, scratch_1[index(p)].zw);
+}
+
+fn rk4_point(p: vec2<i32>) -> WavePoint {
+    let sum = scratch_0[index(p)].zw + slope(FROM_SCRATCH_0, p);
+    let stepped = state(FROM_IN, index(p)) + params.dt / 6.0 * sum;
+    return WavePoint(stepped.x, stepped.y);
+}
+
+@compute
+@workgroup_size(16, 16, 1) // WORKGROUP_SIZE
+fn rk4_finish(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let p = vec2<i32>(global_id.xy);
+    if (outside(p)) {
+        return;
+    }
+    var out = WavePoint(0.0, 0.0);
+    if (is_stepped(p)) {
+        out = rk4_point(p);
+    } else if (is_mur_edge(p)) {
+        out = mur(p, rk4_point(inner_of(p)).x);
+    }
+    finish(p, out);
+}
+
+// Crank-Nicolson, averaging the forces and velocities of both ends of the step. Eliminating the
+// new velocity leaves `x' - a L x' = x + dt v / (1 + b) + a L x` for the new displacement, with
+// `a = c^2 dt^2 / (4 (1 + b))` and `b = damping dt / 2`. Jacobi iterations solve it, keeping the
+// guess in `.x` and the right hand side in `.y`.
+
+fn cn_alpha(p: vec2<i32>) -> f32 {
+    let b = damping_at(p) * params.dt / 2.0;
+    return speed_squared(p) * params.dt * params.dt / (4.0 * (1.0 + b));
+}
+
+@compute
+@workgroup_size(16, 16, 1) // WORKGROUP_SIZE
+fn cn_start(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let p = vec2<i32>(global_id.xy);
+    if (outside(p)) {
+        return;
+    }
+    let here = d_in[index(p)];
+    if (!is_stepped(p)) {
+        let x = held(p).x;
+        scratch_0[index(p)] = vec4<f32>(x, x, 0.0, 0.0);
+        return;
+    }
+    let b = damping_at(p) * params.dt / 2.0;
+    let predicted = here.x + params.dt * here.v / (1.0 + b);
+    let rhs = predicted + cn_alpha(p) * laplacian(FROM_IN, p);
+    // An explicit step is a decent first guess.
+    scratch_0[index(p)] = vec4<f32>(predicted, rhs, 0.0, 0.0);
+}
+
+fn jacobi(src: u32, p: vec2<i32>) -> vec4<f32> {
+    let guess = state(src, index(p));
+    if (!is_stepped(p)) {
+        return vec4<f32>(guess, 0.0, 0.0);
+    }
+    let a = cn_alpha(p) / (params.du * params.du);
+    // The Laplacian without the point's own part.
+    let neighbours = laplacian(src, p) * params.du * params.du - center_weight() * guess.x;
+    let x = (guess.y + a * neighbours) / (1.0 - a * center_weight());
+    return vec4<f32>(x, guess.y, 0.0, 0.0);
+}
+
+@compute
+@workgroup_size(16, 16, 1) // WORKGROUP_SIZE
+fn cn_jacobi_0(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let p = vec2<i32>(global_id.xy);
+    if (outside(p)) {
+        return;
+    }
+    scratch_1[index(p)] = jacobi(FROM_SCRATCH_0, p);
+}
+
+@compute
+@workgroup_size(16, 16, 1) // WORKGROUP_SIZE
+fn cn_jacobi_1(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let p = vec2<i32>(global_id.xy);
+    if (outside(p)) {
+        return;
+    }
+    scratch_0[index(p)] = jacobi(FROM_SCRATCH_1, p);
+}
+
+fn cn_point(src: u32, p: vec2<i32>) -> WavePoint {
+    let here = d_in[index(p)];
+    let x = state(src, index(p)).x;
+    return WavePoint(x, 2.0 * (x - here.x) / params.dt - here.v);
+}
+
+fn cn_finish(src: u32, p: vec2<i32>) {
+    var out = WavePoint(0.0, 0.0);
+    if (is_stepped(p)) {
+        out = cn_point(src, p);
+    } else if (is_mur_edge(p)) {
+        out = mur(p, cn_point(src, inner_of(p)).x);
+    }
+    finish(p, out);
+}
+
+// The solution is in `scratch_0` after an even number of iterations, in `scratch_1` otherwise.
+@compute
+@workgroup_size(16, 16, 1) // WORKGROUP_SIZE
+fn cn_finish_0(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let p = vec2<i32>(global_id.xy);
+    if (!outside(p)) {
+        cn_finish(FROM_SCRATCH_0, p);
+    }
+}
+
+@compute
+@workgroup_size(16, 16, 1) // WORKGROUP_SIZE
+fn cn_finish_1(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let p = vec2<i32>(global_id.xy);
+    if (!outside(p)) {
+        cn_finish(FROM_SCRATCH_1, p);
+    }
+}
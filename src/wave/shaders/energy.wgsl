@@ -0,0 +1,99 @@
+// Sums the energy of the field per workgroup, `wave::energy` adds up the workgroups.
+
+struct WavePoint {
+    x: f32,
+    v: f32,
+};
+
+// See `wave::params::WaveParams`.
+struct WaveParams {
+    width: u32,
+    height: u32,
+    c: f32,
+    dt: f32,
+    du: f32,
+    damping: f32,
+    boundary: u32,
+    absorb_width: u32,
+    scheme: u32,
+    stencil: u32,
+    iterations: u32,
+};
+
+// See `wave::params::Boundary`.
+const BOUNDARY_PERIODIC: u32 = 2u;
+
+// ENERGY_WORKGROUP_SIZE
+const WORKGROUP_SIZE: u32 = 256u;
+
+@group(0) @binding(0) var<storage, read> d_in: array<WavePoint>;
+// Kinetic and potential energy and the largest |x| of each workgroup.
+@group(0) @binding(1) var<storage, read_write> partials: array<vec4<f32>>;
+
+@group(1) @binding(0) var<uniform> params: WaveParams;
+
+// See `compute.wgsl`.
+@group(2) @binding(0) var medium: texture_2d<f32>;
+
+var<workgroup> reduced: array<vec4<f32>, WORKGROUP_SIZE>;
+
+fn index(p: vec2<i32>) -> u32 {
+    return u32(p.x) + u32(p.y) * params.width;
+}
+
+// Potential energy density comes from the slope towards the next point along `axis`, if there is
+// one.
+fn slope(p: vec2<i32>, axis: vec2<i32>) -> f32 {
+    let size = vec2<i32>(i32(params.width), i32(params.height));
+    var q = p + axis;
+    if (params.boundary == BOUNDARY_PERIODIC) {
+        q = q % size;
+    } else if (any(q >= size)) {
+        return 0.0;
+    }
+    return (d_in[index(q)].x - d_in[index(p)].x) / params.du;
+}
+
+@compute
+@workgroup_size(256, 1, 1) // ENERGY_WORKGROUP_SIZE
+fn main(
+    @builtin(global_invocation_id) global_id: vec3<u32>,
+    @builtin(local_invocation_index) local: u32,
+    @builtin(workgroup_id) group: vec3<u32>,
+    @builtin(num_workgroups) groups: vec3<u32>,
+) {
+    let count = params.width * params.height;
+    let dims = vec2<i32>(textureDimensions(medium));
+    let size = vec2<i32>(i32(params.width), i32(params.height));
+    let area = params.du * params.du;
+
+    // A fixed number of workgroups, so large grids get more than one point per invocation.
+    var sum = vec4<f32>(0.0);
+    for (var i = global_id.x; i < count; i += groups.x * WORKGROUP_SIZE) {
+        let p = vec2<i32>(i32(i % params.width), i32(i / params.width));
+        let point = d_in[i];
+        let c = params.c * textureLoad(medium, p * dims / size, 0).r;
+        let dx = slope(p, vec2<i32>(1, 0));
+        let dy = slope(p, vec2<i32>(0, 1));
+        sum.x += 0.5 * point.v * point.v * area;
+        sum.y += 0.5 * c * c * (dx * dx + dy * dy) * area;
+        sum.z = max(sum.z, abs(point.x));
+    }
+    reduced[local] = sum;
+    workgroupBarrier();
+
+    for (var stride = WORKGROUP_SIZE / 2u; stride > 0u; stride /= 2u) {
+        if (local < stride) {
+            let other = reduced[local + stride];
+            reduced[local] = vec4<f32>(
+                reduced[local].xy + other.xy,
+                max(reduced[local].z, other.z),
+                0.0,
+            );
+        }
+        workgroupBarrier();
+    }
+    if (local == 0u) {
+        partials[group.x] = reduced[0];
+    }
+}
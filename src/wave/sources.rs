@@ -0,0 +1,149 @@
+//! Oscillating emitters and the mouse brush, as `compute.wgsl` gets them.
+//!
+//! Emitters come from an `--emitters` file, a list like
+//!
+//! ```json
+//! [
+//!     { "position": [0.3, 0.5], "frequency": 2.0, "amplitude": 0.5 },
+//!     { "from": [0.1, 0.2], "to": [0.1, 0.8], "frequency": 2.1, "phase": 1.57 }
+//! ]
+//! ```
+//!
+//! Points and lines are given as fractions of the grid, so they stay put when it is resized. TOML
+//! has no lists at the top level, so emitters are JSON or RON.
+
+use bytemuck::{Pod, Zeroable};
+use serde::Deserialize;
+
+use crate::keyframes;
+
+/// Has to match the length of `Sources::emitters` in `compute.wgsl`.
+pub const MAX_EMITTERS: usize = 16;
+
+/// An emitter in grid cells, a point if `start == end`.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, Pod, Zeroable)]
+pub struct Emitter {
+    pub start: [f32; 2],
+    pub end: [f32; 2],
+    /// In oscillations per unit of simulated time.
+    pub frequency: f32,
+    pub amplitude: f32,
+    /// In radians.
+    pub phase: f32,
+    /// Cells over which the emitter fades out, sideways.
+    pub radius: f32,
+}
+
+/// What dragging the mouse does, values match the `BRUSH_*` constants in `compute.wgsl`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum Brush {
+    None = 0,
+    /// Add to the displacement, with the left button.
+    Displacement = 1,
+    /// Add to the velocity, with the right button.
+    Impulse = 2,
+}
+
+/// Uniform for `compute.wgsl`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+pub struct SourceData {
+    /// Simulated time of the state being computed.
+    pub time: f32,
+    /// Emitters in use at the start of `emitters`.
+    pub count: u32,
+    /// A [`Brush`].
+    pub brush: u32,
+    pub brush_radius: f32,
+    /// The brush paints along the line from where the cursor was last frame, in grid cells.
+    pub brush_from: [f32; 2],
+    pub brush_to: [f32; 2],
+    /// Added per frame at the middle of the brush.
+    pub brush_strength: f32,
+    pub _spacer: [u32; 3],
+    pub emitters: [Emitter; MAX_EMITTERS],
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct EmitterSpec {
+    /// A point emitter, as a fraction of the grid.
+    position: Option<[f32; 2]>,
+    /// The ends of a line emitter, as fractions of the grid.
+    from: Option<[f32; 2]>,
+    to: Option<[f32; 2]>,
+    frequency: f32,
+    #[serde(default = "default_amplitude")]
+    amplitude: f32,
+    #[serde(default)]
+    phase: f32,
+    /// In grid cells.
+    #[serde(default = "default_radius")]
+    radius: f32,
+}
+
+fn default_amplitude() -> f32 {
+    1.0
+}
+fn default_radius() -> f32 {
+    1.5
+}
+
+/// Emitters with positions as fractions of the grid, see [`Emitters::in_cells`].
+#[derive(Debug, Clone, Default)]
+pub struct Emitters(Vec<Emitter>);
+
+impl Emitters {
+    pub fn load(path: &str) -> anyhow::Result<Self> {
+        let specs: Vec<EmitterSpec> = keyframes::load(path)?;
+        if specs.len() > MAX_EMITTERS {
+            anyhow::bail!("{} has {} emitters, at most {} are supported", path, specs.len(), MAX_EMITTERS);
+        }
+        let emitters = specs
+            .into_iter()
+            .enumerate()
+            .map(|(i, spec)| {
+                let (start, end) = match (spec.position, spec.from, spec.to) {
+                    (Some(position), None, None) => (position, position),
+                    (None, Some(from), Some(to)) => (from, to),
+                    _ => anyhow::bail!("emitter {} in {} needs either `position` or `from` and `to`", i, path),
+                };
+                if spec.radius <= 0.0 {
+                    anyhow::bail!("emitter {} in {} needs a positive radius, got {}", i, path, spec.radius);
+                }
+                Ok(Emitter {
+                    start,
+                    end,
+                    frequency: spec.frequency,
+                    amplitude: spec.amplitude,
+                    phase: spec.phase,
+                    radius: spec.radius,
+                })
+            })
+            .collect::<anyhow::Result<_>>()?;
+        Ok(Self(emitters))
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Write the emitters into `data`, in the cells of a `width` by `height` grid.
+    pub fn in_cells(&self, (width, height): (u32, u32), data: &mut SourceData) {
+        let cells = |[x, y]: [f32; 2]| [x * width as f32, y * height as f32];
+        for (out, emitter) in data.emitters.iter_mut().zip(&self.0) {
+            *out = Emitter {
+                start: cells(emitter.start),
+                end: cells(emitter.end),
+                ..*emitter
+            };
+        }
+        data.count = self.0.len() as u32;
+    }
+}